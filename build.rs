@@ -8,12 +8,22 @@ use std::path::Path;
 
 fn main() -> Result<(), String> {
     let proto_file = "proto/kvs.proto";
-    // Check that proto file exists
-    if !Path::new(proto_file).exists() {
-        return Err("Proto file does not exist.".to_string());
+    let otlp_proto_file = "proto/otlp_trace.proto";
+    // Check that proto files exist
+    for file in [proto_file, otlp_proto_file].iter() {
+        if !Path::new(file).exists() {
+            return Err(format!("Proto file {} does not exist.", file));
+        }
     }
     // Generate code from proto file
-    match tonic_build::compile_protos(proto_file) {
+    if let Err(e) = tonic_build::compile_protos(proto_file) {
+        return Err(format!("Failed: {:?}", e));
+    }
+    // kvsd only exports spans, a client is sufficient
+    match tonic_build::configure()
+        .build_server(false)
+        .compile(&[otlp_proto_file], &["proto"])
+    {
         Ok(_o) => Ok(()),
         Err(e) => Err(format!("Failed: {:?}", e)),
     }
@@ -7,22 +7,27 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
 // Rust Standard Library
-use std::io::{self, Read};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read};
 
 //tonic
-use tonic::transport::{Certificate, ClientTlsConfig};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Uri};
+use tonic::{Request, Status};
 
 // gRPC imports
 use kvs_api::kvs_client::KvsClient;
-use kvs_api::KeyValuePair;
+use kvs_api::{KeyValuePair, Limits, LimitsRequest, SnapshotRequest};
+// The associated type of the snapshot stream is named after the lower case RPC
+#[allow(non_camel_case_types)]
 pub mod kvs_api {
     tonic::include_proto!("kvs_api");
 }
 
 //kvs crates
 use utils::{
+    backup::{ArchiveWriter, BackupSecret, Manifest},
     crypto,
-    filesystem_wrapper::get_exec_dir,
+    filesystem_wrapper::{get_exec_dir, sync_file},
     input_validation,
     log::{log, set_log_silent, LOG_STDERR, LOG_STDOUT},
 };
@@ -34,6 +39,9 @@ use clap::{App, AppSettings, Arg, SubCommand};
 const INPUT_CLI: u8 = 0;
 const INPUT_PIPE: u8 = 1;
 
+// Metadata key of the request ID
+const REQUEST_ID_KEY: &str = "x-request-id";
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Specify commandline arguments
@@ -60,6 +68,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Set to enable TLS support for gRPC.\nIf set certificate and private key are expected as ca.crt in the execution directory of kvsc binary.")
                 .long("tls"),
         )
+        .arg(
+            Arg::with_name("tls-identity")
+                .help("Set to authenticate with a client certificate, requires \"tls\".\nIf set certificate and private key are expected as client.crt and client.key in the execution directory of kvsc binary.")
+                .long("tls-identity")
+                .requires("tls"),
+        )
+        .arg(
+            Arg::with_name("unix")
+                .help("Path of the Unix domain socket the kvs daemon is listening on, replaces \"ip\" and \"port\".")
+                .long("unix")
+                .takes_value(true)
+                .conflicts_with_all(&["ip", "port"]),
+        )
+        .arg(
+            Arg::with_name("request-id")
+                .help("Request ID to correlate the request with the logs and traces of the kvs daemon.\nIf not given the daemon generates one, it is shown if the request fails.")
+                .long("request-id")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("silent")
             .help("Supress all stdout and stderr messages.")
@@ -71,14 +98,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .arg(
                 Arg::with_name("key")
                 .long("key")
-                .help("Key of the key value pair, max. length 32.")
+                .help("Key of the key value pair, max. length as configured in kvsd (default: 32).")
                 .takes_value(true)
                 .required(true)
             )
             .arg(
                 Arg::with_name("value")
                 .long("value")
-                .help("Value of the key value pair, max. length 1024.")
+                .help("Value of the key value pair, max. length as configured in kvsd (default: 1024).")
                 .takes_value(true)
             )
             .arg(
@@ -93,7 +120,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .arg(
                 Arg::with_name("key")
                 .long("key")
-                .help("Key of the key value pair, max. length 32.")
+                .help("Key of the key value pair, max. length as configured in kvsd (default: 32).")
                 .takes_value(true)
                 .required(true)
             )
@@ -104,10 +131,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .arg(
                 Arg::with_name("key")
                 .long("key")
-                .help("Key of the key value pair, max. length 32.")
+                .help("Key of the key value pair, max. length as configured in kvsd (default: 32).")
+                .takes_value(true)
+                .required(true)
+            )
+        )
+        .subcommand(
+            SubCommand::with_name("limits")
+            .about("Show the limits of the kvs daemon and the current usage.")
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+            .about("Write a consistent snapshot of all key value pairs to an encrypted archive.")
+            .arg(
+                Arg::with_name("output")
+                .long("output")
+                .help("File the archive is written to, an existing file is replaced once the archive is complete.")
                 .takes_value(true)
                 .required(true)
             )
+            .arg(
+                Arg::with_name("key-file")
+                .long("key-file")
+                .help("File containing the Base64 encoded 32 byte key the archive is encrypted with.")
+                .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("passphrase-file")
+                .long("passphrase-file")
+                .help("File containing the passphrase the key of the archive is derived from, alternative for \"key-file\".")
+                .takes_value(true)
+                .conflicts_with("key-file")
+            )
         )
         .get_matches();
 
@@ -152,14 +207,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // create a channel for the connection to the server
     let socket = format!("http://{}:{}", ip, port).parse().unwrap();
-    let channel;
+    let mut endpoint = tonic::transport::Channel::builder(socket);
     if matches.is_present("tls") {
         let path = get_exec_dir();
         log(
             format!("TLS Option for gRPC given, looking for ca.crt in {}", path),
             LOG_STDOUT,
         );
-        let trust_store = match crypto::TrustStore::new(path) {
+        let trust_store = match crypto::TrustStore::new(path.clone()) {
             Ok(trusted) => trusted,
             Err(e) => {
                 log(format!("Error during store: {:?}", e), LOG_STDERR);
@@ -167,14 +222,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         };
         let cert = Certificate::from_pem(trust_store.get_trusted_certificate());
-
-        channel = tonic::transport::Channel::builder(socket)
-            .tls_config(ClientTlsConfig::new().ca_certificate(cert))?
-            .connect()
-            .await?;
-    } else {
-        channel = tonic::transport::Channel::builder(socket).connect().await?;
+        let mut tls_config = ClientTlsConfig::new().ca_certificate(cert);
+        // Present a client certificate if the daemon requires one
+        if matches.is_present("tls-identity") {
+            log(
+                format!(
+                    "TLS identity given, looking for client.crt and client.key in {}",
+                    path
+                ),
+                LOG_STDOUT,
+            );
+            let credentials = match crypto::Credentials::from_files(
+                format!("{}/client.crt", path),
+                format!("{}/client.key", path),
+            ) {
+                Ok(credentials) => credentials,
+                Err(e) => {
+                    log(format!("Error loading TLS identity: {:?}", e), LOG_STDERR);
+                    std::process::exit(0x0001);
+                }
+            };
+            tls_config = tls_config.identity(Identity::from_pem(
+                credentials.get_certificate(),
+                credentials.get_private_key(),
+            ));
+        }
+        endpoint = endpoint.tls_config(tls_config)?;
     }
+    let channel = match matches.value_of("unix") {
+        Some(path) => connect_unix(endpoint, path.to_string()).await?,
+        None => endpoint.connect().await?,
+    };
 
     // create a gRPC client from the channel
     let mut client = KvsClient::new(channel);
@@ -182,11 +260,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // handle subcommands
     match matches.subcommand() {
         ("store", Some(sub_m)) => {
+            // The input is validated against the limits of the daemon, the defaults are used if
+            // the daemon does not report them
+            let limits = request_limits(&mut client, &matches).await;
+            let max_key_length = match &limits {
+                Ok(limits) => limits.max_key_length as usize,
+                Err(_e) => input_validation::KEY_LEN_MAX,
+            };
             // Perform input validation on options
-            if !input_validation::validate_key(sub_m.value_of("key").unwrap().to_string()) {
-                log("Provided key invalid.".to_string(), LOG_STDERR);
-                std::process::exit(0x0001);
-            }
+            validate_key(sub_m.value_of("key").unwrap(), Some(max_key_length));
             // Check whether either value or pipe are given
             let mut _value_input: u8 = INPUT_CLI;
             if sub_m.is_present("value") && !sub_m.is_present("pipe") {
@@ -229,8 +311,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 };
             }
+            // The value is trimmed by the daemon before checking its length
+            if let Ok(limits) = &limits {
+                if limits.max_value_length > 0
+                    && value.trim().len() as u64 > limits.max_value_length
+                {
+                    log(
+                        format!(
+                            "Provided value exceeds the limit of {} bytes of the {} backend.",
+                            limits.max_value_length, limits.backend
+                        ),
+                        LOG_STDERR,
+                    );
+                    std::process::exit(0x0001);
+                }
+            }
             // creating a new Request
-            let request = tonic::Request::new(KeyValuePair { key, value });
+            let request = new_request(&matches, KeyValuePair { key, value });
             // Send request and handle response
             match client.store(request).await {
                 Ok(response) => {
@@ -241,24 +338,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     std::process::exit(0x0000);
                 }
                 Err(e) => {
-                    log(format!("Error during store: {:?}", e.message()), LOG_STDERR);
+                    log_failure("store", &e);
                     std::process::exit(0x0001);
                 }
             };
         }
         ("get", Some(sub_m)) => {
             // Perform input validation on options
-            if !input_validation::validate_key(sub_m.value_of("key").unwrap().to_string()) {
-                log("Provided key invalid.".to_string(), LOG_STDERR);
-                std::process::exit(0x0001);
-            }
+            validate_key(sub_m.value_of("key").unwrap(), None);
             // Get values of options
             let key = sub_m.value_of("key").unwrap().to_string();
             // creating a new Request
-            let request = tonic::Request::new(KeyValuePair {
-                key,
-                value: "".to_string(),
-            });
+            let request = new_request(
+                &matches,
+                KeyValuePair {
+                    key,
+                    value: "".to_string(),
+                },
+            );
             // Send request and handle response
             match client.get(request).await {
                 Ok(response) => {
@@ -267,24 +364,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     std::process::exit(0x0000);
                 }
                 Err(e) => {
-                    log(format!("Error during get: {:?}", e.message()), LOG_STDERR);
+                    log_failure("get", &e);
                     std::process::exit(0x0001);
                 }
             };
         }
         ("delete", Some(sub_m)) => {
             // Perform input validation on options
-            if !input_validation::validate_key(sub_m.value_of("key").unwrap().to_string()) {
-                log("Provided key invalid.".to_string(), LOG_STDERR);
-                std::process::exit(0x0001);
-            }
+            validate_key(sub_m.value_of("key").unwrap(), None);
             // Get values of options
             let key = sub_m.value_of("key").unwrap().to_string();
             // creating a new Request
-            let request = tonic::Request::new(KeyValuePair {
-                key,
-                value: "".to_string(),
-            });
+            let request = new_request(
+                &matches,
+                KeyValuePair {
+                    key,
+                    value: "".to_string(),
+                },
+            );
 
             // Send request and handle response
             match client.delete(request).await {
@@ -296,13 +393,63 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     std::process::exit(0x0000);
                 }
                 Err(e) => {
-                    log(
-                        format!("Error during delete: {:?}", e.message()),
-                        LOG_STDERR,
-                    );
+                    log_failure("delete", &e);
+                    std::process::exit(0x0001);
+                }
+            };
+        }
+        ("limits", Some(_sub_m)) => match request_limits(&mut client, &matches).await {
+            Ok(limits) => {
+                // Dont log but directly write to stdout to return the limits
+                println!("Backend: {}", limits.backend);
+                println!("Maximum key length: {}", limits.max_key_length);
+                println!(
+                    "Maximum value length: {}",
+                    describe_limit(limits.max_value_length)
+                );
+                println!(
+                    "Maximum entries: {} (used: {})",
+                    describe_limit(limits.max_entries),
+                    limits.entries
+                );
+                println!(
+                    "Maximum store bytes: {} (used: {})",
+                    describe_limit(limits.max_store_bytes),
+                    limits.store_bytes
+                );
+                std::process::exit(0x0000);
+            }
+            Err(e) => {
+                log_failure("limits", &e);
+                std::process::exit(0x0001);
+            }
+        },
+        ("backup", Some(sub_m)) => {
+            let secret = match (
+                sub_m.value_of("key-file"),
+                sub_m.value_of("passphrase-file"),
+            ) {
+                (Some(path), None) => BackupSecret::from_key_file(path.to_string()),
+                (None, Some(path)) => BackupSecret::from_passphrase_file(path.to_string()),
+                _ => Err("Provide either \"key-file\" or \"passphrase-file\".".to_string()),
+            };
+            let secret = match secret {
+                Ok(secret) => secret,
+                Err(e) => {
+                    log(e, LOG_STDERR);
                     std::process::exit(0x0001);
                 }
             };
+            let output = sub_m.value_of("output").unwrap();
+            let manifest = backup(&mut client, &matches, output, &secret).await;
+            log(
+                format!(
+                    "Backup of {} key value pairs of the {} backend written to \"{}\".",
+                    manifest.entries, manifest.backend, output
+                ),
+                LOG_STDOUT,
+            );
+            std::process::exit(0x0000);
         }
         _ => {
             log("Unknown subcommand.".to_string(), LOG_STDERR);
@@ -310,3 +457,176 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 }
+
+// Stream a snapshot into an encrypted archive, it is written to "<output>.tmp" and renamed
+// once it is complete and synced. Exits if the backup fails.
+async fn backup(
+    client: &mut KvsClient<Channel>,
+    matches: &clap::ArgMatches<'_>,
+    output: &str,
+    secret: &BackupSecret,
+) -> Manifest {
+    let temp_path = format!("{}.tmp", output);
+    let mut stream = match client
+        .snapshot(new_request(matches, SnapshotRequest {}))
+        .await
+    {
+        Ok(response) => response.into_inner(),
+        Err(e) => {
+            log_failure("backup", &e);
+            std::process::exit(0x0001);
+        }
+    };
+    let file = match File::create(&temp_path) {
+        Ok(file) => file,
+        Err(e) => fail_backup(
+            &temp_path,
+            format!("Could not create \"{}\": {}", temp_path, e),
+        ),
+    };
+    let mut writer = match ArchiveWriter::new(BufWriter::new(file), secret) {
+        Ok(writer) => writer,
+        Err(e) => fail_backup(&temp_path, e),
+    };
+    // Backend, creation time and number of entries of the snapshot
+    let mut snapshot: Option<(String, u64, u64)> = None;
+    let mut received: u64 = 0;
+    loop {
+        let message = match stream.message().await {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                log_failure("backup", &e);
+                std::process::exit(0x0001);
+            }
+        };
+        snapshot = Some((message.backend, message.created, message.total_entries));
+        for pair in message.entries {
+            received += 1;
+            if let Err(e) = writer.add(pair.key, pair.value) {
+                fail_backup(&temp_path, e);
+            }
+        }
+    }
+    let (backend, created) = match snapshot {
+        Some((backend, created, total_entries)) if total_entries == received => (backend, created),
+        _ => fail_backup(&temp_path, "Snapshot is incomplete.".to_string()),
+    };
+    let manifest = match writer.finish(&backend, created) {
+        Ok(manifest) => manifest,
+        Err(e) => fail_backup(&temp_path, e),
+    };
+    if let Err(e) = sync_file(temp_path.clone()).and_then(|()| fs::rename(&temp_path, output)) {
+        fail_backup(&temp_path, format!("Could not write \"{}\": {}", output, e));
+    }
+    manifest
+}
+
+// Remove an incomplete archive and exit
+fn fail_backup(temp_path: &str, message: String) -> ! {
+    let _ = fs::remove_file(temp_path);
+    log(format!("Error during backup: {}", message), LOG_STDERR);
+    std::process::exit(0x0001);
+}
+
+// Validate a key against the key length limit of the daemon, exits if it is invalid
+// Request the limits enforced by the daemon
+async fn request_limits(
+    client: &mut KvsClient<Channel>,
+    matches: &clap::ArgMatches<'_>,
+) -> Result<Limits, Status> {
+    match client.limits(new_request(matches, LimitsRequest {})).await {
+        Ok(response) => Ok(response.into_inner()),
+        Err(e) => Err(e),
+    }
+}
+
+// Validate a key, its length is only checked by the daemon if no maximum length is given
+fn validate_key(key: &str, max_length: Option<usize>) {
+    let valid = input_validation::validate_key_with_limit(
+        key.to_string(),
+        max_length.unwrap_or(usize::MAX),
+    );
+    if !valid {
+        let message = match max_length {
+            Some(max_length) => format!(
+                "Provided key invalid, only letters, digits and \"_\" and a length of up to {} are allowed.",
+                max_length
+            ),
+            None => "Provided key invalid, only letters, digits and \"_\" are allowed.".to_string(),
+        };
+        log(message, LOG_STDERR);
+        std::process::exit(0x0001);
+    }
+}
+
+// A limit of 0 is not enforced by the daemon
+fn describe_limit(limit: u64) -> String {
+    match limit {
+        0 => "unlimited".to_string(),
+        limit => limit.to_string(),
+    }
+}
+
+// Create a request carrying the request ID given by argument
+fn new_request<T>(matches: &clap::ArgMatches, message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    if let Some(request_id) = matches.value_of("request-id") {
+        match request_id.parse() {
+            Ok(request_id) => {
+                request.metadata_mut().insert(REQUEST_ID_KEY, request_id);
+            }
+            Err(_e) => {
+                log("Provided request ID invalid.".to_string(), LOG_STDERR);
+                std::process::exit(0x0001);
+            }
+        }
+    }
+    request
+}
+
+// Log a failed request with the request ID returned by the daemon
+fn log_failure(operation: &str, status: &Status) {
+    let request_id = status
+        .metadata()
+        .get(REQUEST_ID_KEY)
+        .and_then(|request_id| request_id.to_str().ok());
+    match request_id {
+        Some(request_id) => log(
+            format!(
+                "Error during {}: {:?} (request ID: {})",
+                operation,
+                status.message(),
+                request_id
+            ),
+            LOG_STDERR,
+        ),
+        None => log(
+            format!("Error during {}: {:?}", operation, status.message()),
+            LOG_STDERR,
+        ),
+    }
+}
+
+// Connect to a daemon listening on a Unix domain socket, the endpoint URI is ignored
+#[cfg(unix)]
+async fn connect_unix(
+    endpoint: Endpoint,
+    path: String,
+) -> Result<tonic::transport::Channel, Box<dyn std::error::Error>> {
+    let channel = endpoint
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            tokio::net::UnixStream::connect(path.clone())
+        }))
+        .await?;
+    Ok(channel)
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(
+    _endpoint: Endpoint,
+    _path: String,
+) -> Result<tonic::transport::Channel, Box<dyn std::error::Error>> {
+    Err("Unix domain sockets are only supported on Unix.".into())
+}
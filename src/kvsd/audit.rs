@@ -0,0 +1,252 @@
+/*
+*  kvsd audit module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+
+// json
+use serde::{Deserialize, Serialize};
+
+// kvs modules
+use utils::crypto::sha3_256_hex;
+use utils::log::timestamp;
+use utils::{log_error, log_warn};
+
+// Previous hash of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+// Keys longer than this are truncated in the audit log
+const KEY_LEN_RECORDED: usize = 64;
+
+// Audited access, the hash of an entry covers all of these fields
+#[derive(Deserialize, Serialize)]
+struct AuditRecord {
+    sequence: u64,
+    timestamp: String,
+    // IP address of the client, "local" for Unix domain sockets
+    peer: String,
+    // SHA3-256 fingerprint of the TLS client certificate
+    identity: Option<String>,
+    operation: String,
+    key: String,
+    // gRPC status code name of the response
+    result: String,
+    previous_hash: String,
+}
+
+// Line of the audit log
+#[derive(Deserialize, Serialize)]
+struct AuditEntry {
+    #[serde(flatten)]
+    record: AuditRecord,
+    hash: String,
+}
+
+impl AuditRecord {
+    fn hash(&self) -> String {
+        sha3_256_hex(serde_json::to_string(self).unwrap().as_bytes())
+    }
+}
+
+// Position of the end of the chain
+struct ChainHead {
+    file: File,
+    sequence: u64,
+    hash: String,
+}
+
+// Append-only audit log, each entry is chained to the previous one by its hash
+pub struct AuditLog {
+    path: String,
+    head: Mutex<ChainHead>,
+}
+
+impl AuditLog {
+    // Open the audit log and continue its chain, the log is created if it does not exist
+    pub fn open(path: String) -> Result<AuditLog, String> {
+        let (last, torn) = match File::open(&path) {
+            Ok(file) => last_entry(file)?,
+            Err(_e) => (None, None),
+        };
+        let (sequence, hash) = match last {
+            Some(entry) => (entry.record.sequence + 1, entry.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        let file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Could not open audit log \"{}\": {}", path, e)),
+        };
+        // A line without newline was torn by a crash while it was written, drop it
+        if let Some(length) = torn {
+            log_warn!(
+                "Audit log \"{}\" ends with an incomplete entry, truncating it to {} bytes.",
+                path,
+                length
+            );
+            if let Err(e) = file.set_len(length).and_then(|()| file.sync_all()) {
+                return Err(format!("Could not truncate audit log \"{}\": {}", path, e));
+            }
+        }
+        Ok(AuditLog {
+            path,
+            head: Mutex::new(ChainHead {
+                file,
+                sequence,
+                hash,
+            }),
+        })
+    }
+
+    // Append an entry, values are never recorded
+    pub fn record(
+        &self,
+        peer: String,
+        identity: Option<String>,
+        operation: &str,
+        key: &str,
+        result: &str,
+    ) {
+        let mut head = self.head.lock().unwrap();
+        let record = AuditRecord {
+            sequence: head.sequence,
+            timestamp: timestamp(),
+            peer,
+            identity,
+            operation: operation.to_string(),
+            key: key.chars().take(KEY_LEN_RECORDED).collect(),
+            result: result.to_string(),
+            previous_hash: head.hash.clone(),
+        };
+        let hash = record.hash();
+        let entry = AuditEntry { record, hash };
+        let line = format!("{}\n", serde_json::to_string(&entry).unwrap());
+        // The entry is persisted before the request is answered
+        let written = head
+            .file
+            .write_all(line.as_bytes())
+            .and_then(|()| head.file.flush())
+            .and_then(|()| head.file.sync_data());
+        if let Err(e) = written {
+            log_error!("Could not write audit log \"{}\": {}", self.path, e);
+            return;
+        }
+        head.sequence += 1;
+        head.hash = entry.hash;
+    }
+}
+
+// Last entry of an audit log, None if it is empty.
+// If the last line is incomplete, the length of the complete lines is returned as well.
+fn last_entry(file: File) -> Result<(Option<AuditEntry>, Option<u64>), String> {
+    let mut reader = BufReader::new(file);
+    let mut last = None;
+    let mut number = 0;
+    let mut length: u64 = 0;
+    loop {
+        let mut line = Vec::new();
+        let read = match reader.read_until(b'\n', &mut line) {
+            Ok(read) => read,
+            Err(e) => return Err(format!("Could not read audit log: {}", e)),
+        };
+        if read == 0 {
+            break;
+        }
+        if !line.ends_with(b"\n") {
+            return Ok((parse_entry(last)?, Some(length)));
+        }
+        number += 1;
+        length += read as u64;
+        last = Some((number, line));
+    }
+    Ok((parse_entry(last)?, None))
+}
+
+fn parse_entry(line: Option<(usize, Vec<u8>)>) -> Result<Option<AuditEntry>, String> {
+    match line {
+        Some((number, line)) => match serde_json::from_slice(&line) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(e) => Err(format!("Audit log entry in line {} invalid: {}", number, e)),
+        },
+        None => Ok(None),
+    }
+}
+
+// Verify the hash chain of an audit log, returns the number of entries and the hash of the last entry
+pub fn verify(path: String) -> Result<(u64, String), String> {
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => return Err(format!("Could not open audit log \"{}\": {}", path, e)),
+    };
+    let mut sequence: u64 = 0;
+    let mut previous_hash = GENESIS_HASH.to_string();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Err(format!("Could not read audit log: {}", e)),
+        };
+        let entry: AuditEntry = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(e) => return Err(format!("Line {} invalid: {}", number + 1, e)),
+        };
+        // Added or reordered fields are not covered by the hash
+        if serde_json::to_string(&entry).unwrap() != line {
+            return Err(format!(
+                "Line {}: entry modified, not in canonical form.",
+                number + 1
+            ));
+        }
+        if entry.record.sequence != sequence {
+            return Err(format!(
+                "Line {}: sequence {} expected, found {}.",
+                number + 1,
+                sequence,
+                entry.record.sequence
+            ));
+        }
+        if entry.record.previous_hash != previous_hash {
+            return Err(format!(
+                "Line {}: chain broken, previous hash does not match.",
+                number + 1
+            ));
+        }
+        if entry.record.hash() != entry.hash {
+            return Err(format!(
+                "Line {}: entry modified, hash does not match.",
+                number + 1
+            ));
+        }
+        sequence += 1;
+        previous_hash = entry.hash;
+    }
+    Ok((sequence, previous_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn open_torn_entry_ok() {
+        let path = std::env::temp_dir().join(format!("kvsd-audit-{}.log", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        let audit_log = AuditLog::open(path.clone()).unwrap();
+        audit_log.record("local".to_string(), None, "store", "key", "OK");
+        drop(audit_log);
+        // Crash while the second entry was written
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"sequence\":1,\"timest").unwrap();
+        drop(file);
+        let audit_log = AuditLog::open(path.clone()).unwrap();
+        audit_log.record("local".to_string(), None, "get", "key", "OK");
+        drop(audit_log);
+        let verified = verify(path.clone());
+        let _ = fs::remove_file(&path);
+        assert_eq!(verified.map(|(entries, _hash)| entries), Ok(2));
+    }
+}
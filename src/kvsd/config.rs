@@ -0,0 +1,715 @@
+/*
+*  kvsd config module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::env;
+use std::net::SocketAddr;
+
+// CLI interface
+use clap::ArgMatches;
+
+// toml
+use serde::Deserialize;
+
+// tonic
+use tonic::transport::Endpoint;
+
+// kvs modules
+use crate::listener::{ListenAddress, Listener, TlsSettings};
+use crate::store::json_store::MAP_SIZE_MAX;
+use utils::backup::BackupSecret;
+use utils::filesystem_wrapper::{get_exec_dir, read_file_to_string};
+use utils::input_validation::{self, KEY_LEN_MAX, VALUE_LEN_MAX, VALUE_LEN_MIN};
+use utils::log::{Filter, LogSettings, FORMAT_NAMES, OUTPUT_FILE, OUTPUT_NAMES};
+
+// Prefix of environment variables overriding settings
+const ENV_PREFIX: &str = "KVSD_";
+
+// Settings that can be given as commandline argument or environment variable.
+// The environment variable is the upper case setting prefixed with "KVSD_" and "-" replaced by "_".
+pub const SETTINGS: [&str; 43] = [
+    "ip",
+    "port",
+    "tls",
+    "tls-path",
+    "tls-client-ca",
+    "http",
+    "http-ip",
+    "http-port",
+    "metrics",
+    "metrics-ip",
+    "metrics-port",
+    "backend",
+    "path",
+    "shutdown-timeout",
+    "queue-capacity",
+    "queue-timeout",
+    "wipe-on-shutdown",
+    "compression-threshold",
+    "dedup",
+    "verify",
+    "max-entries",
+    "max-key-length",
+    "max-value-length",
+    "max-store-bytes",
+    "rate-limit-client-rps",
+    "rate-limit-client-bps",
+    "rate-limit-global-rps",
+    "rate-limit-global-bps",
+    "silent",
+    "log-level",
+    "log-format",
+    "log-output",
+    "log-file",
+    "redact-keys",
+    "stats-interval",
+    "audit-log",
+    "otlp-endpoint",
+    "backup-path",
+    "backup-interval",
+    "backup-retention",
+    "backup-key-file",
+    "backup-passphrase-file",
+    "config",
+];
+
+// Supported backends
+pub const BACKENDS: [&str; 6] = ["json", "file", "log", "sled", "sqlite", "memory"];
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub ip: String,
+    pub port: u16,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> ListenerConfig {
+        ListenerConfig {
+            ip: "127.0.0.1".to_string(),
+            port: 27001,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    // Directory containing grpc.crt and grpc.key
+    pub path: String,
+    // CA certificate clients have to authenticate with, if set
+    pub client_ca: Option<String>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig {
+            enabled: false,
+            path: get_exec_dir(),
+            client_ca: None,
+        }
+    }
+}
+
+// Listener in addition to the one configured by [listener] and [tls]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdditionalListenerConfig {
+    pub ip: String,
+    pub port: u16,
+    // Path of a Unix domain socket, replaces ip and port if set
+    pub unix: Option<String>,
+    // Permissions of the Unix domain socket
+    pub unix_mode: u32,
+    pub tls: bool,
+    pub tls_path: String,
+    pub tls_client_ca: Option<String>,
+}
+
+impl Default for AdditionalListenerConfig {
+    fn default() -> AdditionalListenerConfig {
+        AdditionalListenerConfig {
+            ip: "127.0.0.1".to_string(),
+            port: 0,
+            unix: None,
+            unix_mode: 0o600,
+            tls: false,
+            tls_path: get_exec_dir(),
+            tls_client_ca: None,
+        }
+    }
+}
+
+// HTTP/JSON gateway, served with the settings of [tls]
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub ip: String,
+    pub port: u16,
+}
+
+impl Default for HttpConfig {
+    fn default() -> HttpConfig {
+        HttpConfig {
+            enabled: false,
+            ip: "127.0.0.1".to_string(),
+            port: 27080,
+        }
+    }
+}
+
+// Prometheus metrics endpoint, served on GET /metrics
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub ip: String,
+    pub port: u16,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> MetricsConfig {
+        MetricsConfig {
+            enabled: false,
+            ip: "127.0.0.1".to_string(),
+            port: 27090,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreConfig {
+    pub backend: String,
+    pub path: String,
+    // Seconds for persisting all pending changes on shutdown
+    pub shutdown_timeout: u64,
+    // Actions waiting for the store handler, further requests wait for free space
+    pub queue_capacity: usize,
+    // Milliseconds a request waits for free space in the queue, 0 fails immediately
+    pub queue_timeout: u64,
+    // Overwrite all entries of the memory backend on shutdown
+    pub wipe_on_shutdown: bool,
+    // Minimum value length in bytes for compressing values before the encryption, 0 disables compression
+    pub compression_threshold: usize,
+    // Share the value file of identical values in the file backend
+    pub dedup: bool,
+    // Authenticate all value files of the file backend on start-up
+    pub verify: bool,
+    // Load the store without repairing or changing it, set for the source of a migration
+    #[serde(skip)]
+    pub read_only: bool,
+}
+
+impl Default for StoreConfig {
+    fn default() -> StoreConfig {
+        StoreConfig {
+            backend: "json".to_string(),
+            path: get_exec_dir(),
+            shutdown_timeout: 10,
+            queue_capacity: 1024,
+            queue_timeout: 1000,
+            wipe_on_shutdown: false,
+            compression_threshold: 0,
+            dedup: false,
+            verify: false,
+            read_only: false,
+        }
+    }
+}
+
+// Limits, a rate limit or store bytes limit of 0 disables the respective limit
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    // Maximum number of entries of the backends holding values in RAM
+    pub max_entries: usize,
+    // Maximum key length of all backends
+    pub max_key_length: usize,
+    // Maximum value length of the backends holding values in RAM
+    pub max_value_length: usize,
+    // Memory budget for the keys and encrypted values of the backends holding values in RAM
+    pub max_store_bytes: usize,
+    pub client_requests_per_second: u64,
+    pub client_bytes_per_second: u64,
+    pub global_requests_per_second: u64,
+    pub global_bytes_per_second: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> LimitsConfig {
+        LimitsConfig {
+            max_entries: MAP_SIZE_MAX,
+            max_key_length: KEY_LEN_MAX,
+            max_value_length: VALUE_LEN_MAX,
+            max_store_bytes: 0,
+            client_requests_per_second: 0,
+            client_bytes_per_second: 0,
+            global_requests_per_second: 0,
+            global_bytes_per_second: 0,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub silent: bool,
+    // Default level followed by levels per module, e.g. "info,kvsd::grpc=debug"
+    pub level: String,
+    // "text" or "json" lines
+    pub format: String,
+    // "stderr", "file", "syslog" or "journald"
+    pub output: String,
+    pub file: String,
+    // Size in bytes after which the log file is rotated
+    pub file_max_size: u64,
+    // Rotated log files kept besides the current one
+    pub file_max_files: u32,
+    // Replace keys in log events by "<redacted>"
+    pub redact_keys: bool,
+    // Seconds between logging the daemon stats, 0 disables it
+    pub stats_interval: u64,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            silent: false,
+            level: "info".to_string(),
+            format: "text".to_string(),
+            output: "stderr".to_string(),
+            file: format!("{}/kvsd.log", get_exec_dir()),
+            file_max_size: 10 * 1024 * 1024,
+            file_max_files: 5,
+            redact_keys: false,
+            stats_interval: 0,
+        }
+    }
+}
+
+impl LoggingConfig {
+    // Settings of the logger
+    pub fn log_settings(&self) -> Result<LogSettings, String> {
+        let format = match FORMAT_NAMES.iter().position(|name| *name == self.format) {
+            Some(format) => format as u8,
+            None => {
+                return Err(format!(
+                    "Log format \"{}\" invalid, possible values: {}.",
+                    self.format,
+                    FORMAT_NAMES.join(", ")
+                ))
+            }
+        };
+        let output = match OUTPUT_NAMES.iter().position(|name| *name == self.output) {
+            Some(output) => output as u8,
+            None => {
+                return Err(format!(
+                    "Log output \"{}\" invalid, possible values: {}.",
+                    self.output,
+                    OUTPUT_NAMES.join(", ")
+                ))
+            }
+        };
+        if output == OUTPUT_FILE && self.file_max_size == 0 {
+            return Err("Maximum log file size has to be at least 1.".to_string());
+        }
+        Ok(LogSettings {
+            filter: Filter::parse(&self.level)?,
+            format,
+            output,
+            file: self.file.clone(),
+            file_max_size: self.file_max_size,
+            file_max_files: self.file_max_files,
+            redact_keys: self.redact_keys,
+        })
+    }
+}
+
+// Audit log of all requests, disabled if no path is set
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    pub path: Option<String>,
+}
+
+// Export of request spans to an OpenTelemetry collector, disabled if no endpoint is set
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TracingConfig {
+    // OTLP/gRPC endpoint of the collector, e.g. "http://127.0.0.1:4317"
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> TracingConfig {
+        TracingConfig {
+            otlp_endpoint: None,
+            service_name: "kvsd".to_string(),
+        }
+    }
+}
+
+// Scheduled snapshots, disabled if no path is set
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupConfig {
+    // Directory the snapshots are written to
+    pub path: Option<String>,
+    // Seconds between two snapshots
+    pub interval: u64,
+    // Number of snapshots kept, older snapshots are deleted
+    pub retention: usize,
+    // File containing the Base64 encoded 32 byte key the snapshots are encrypted with
+    pub key_file: Option<String>,
+    // File containing the passphrase the key is derived from, alternative for key_file
+    pub passphrase_file: Option<String>,
+}
+
+impl Default for BackupConfig {
+    fn default() -> BackupConfig {
+        BackupConfig {
+            path: None,
+            interval: 86400,
+            retention: 7,
+            key_file: None,
+            passphrase_file: None,
+        }
+    }
+}
+
+impl BackupConfig {
+    // Read the secret snapshots are encrypted with
+    pub fn secret(&self) -> Result<BackupSecret, String> {
+        match (&self.key_file, &self.passphrase_file) {
+            (Some(key_file), None) => BackupSecret::from_key_file(key_file.clone()),
+            (None, Some(passphrase_file)) => {
+                BackupSecret::from_passphrase_file(passphrase_file.clone())
+            }
+            (Some(_key_file), Some(_passphrase_file)) => Err(
+                "Set either a backup key file or a backup passphrase file, not both.".to_string(),
+            ),
+            (None, None) => Err("No backup key file or backup passphrase file set.".to_string()),
+        }
+    }
+}
+
+// Complete kvsd configuration
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listener: ListenerConfig,
+    pub tls: TlsConfig,
+    pub listeners: Vec<AdditionalListenerConfig>,
+    pub http: HttpConfig,
+    pub metrics: MetricsConfig,
+    pub store: StoreConfig,
+    pub limits: LimitsConfig,
+    pub logging: LoggingConfig,
+    pub audit: AuditConfig,
+    pub tracing: TracingConfig,
+    pub backup: BackupConfig,
+}
+
+impl Config {
+    // Load the configuration from a TOML file, missing settings keep their defaults
+    pub fn from_file(path: String) -> Result<Config, String> {
+        let content = match read_file_to_string(path.clone()) {
+            Ok(content) => content,
+            Err(e) => return Err(format!("Could not read config file \"{}\": {}", path, e)),
+        };
+        match toml::from_str(content.as_str()) {
+            Ok(config) => Ok(config),
+            Err(e) => Err(format!("Could not parse config file \"{}\": {}", path, e)),
+        }
+    }
+
+    // Load the configuration file given by argument or environment variable,
+    // then apply environment variables and arguments overriding it.
+    pub fn load(matches: &ArgMatches) -> Result<Config, String> {
+        let config_file = match matches.value_of("config") {
+            Some(file) => Some(file.to_string()),
+            None => env::var(env_name("config")).ok(),
+        };
+        let mut config = match config_file {
+            Some(file) => Config::from_file(file)?,
+            None => Config::default(),
+        };
+        config.apply_environment()?;
+        config.apply_arguments(matches)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Override settings given as environment variables
+    pub fn apply_environment(&mut self) -> Result<(), String> {
+        for name in SETTINGS.iter() {
+            if let Ok(value) = env::var(env_name(name)) {
+                self.set(name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Override settings given as commandline arguments, flags are set to "true"
+    pub fn apply_arguments(&mut self, matches: &ArgMatches) -> Result<(), String> {
+        for name in SETTINGS.iter() {
+            if matches.is_present(name) {
+                let value = matches.value_of(name).unwrap_or("true").to_string();
+                self.set(name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Set a single setting from its string representation
+    pub fn set(&mut self, name: &str, value: String) -> Result<(), String> {
+        match name {
+            "ip" => self.listener.ip = value,
+            "port" => self.listener.port = parse_number(name, value)?,
+            "tls" => self.tls.enabled = parse_bool(name, value)?,
+            "tls-path" => self.tls.path = value,
+            "tls-client-ca" => self.tls.client_ca = Some(value),
+            "http" => self.http.enabled = parse_bool(name, value)?,
+            "http-ip" => self.http.ip = value,
+            "http-port" => self.http.port = parse_number(name, value)?,
+            "metrics" => self.metrics.enabled = parse_bool(name, value)?,
+            "metrics-ip" => self.metrics.ip = value,
+            "metrics-port" => self.metrics.port = parse_number(name, value)?,
+            "backend" => self.store.backend = value,
+            "path" => self.store.path = value,
+            "shutdown-timeout" => self.store.shutdown_timeout = parse_number(name, value)?,
+            "queue-capacity" => self.store.queue_capacity = parse_number(name, value)?,
+            "queue-timeout" => self.store.queue_timeout = parse_number(name, value)?,
+            "wipe-on-shutdown" => self.store.wipe_on_shutdown = parse_bool(name, value)?,
+            "compression-threshold" => {
+                self.store.compression_threshold = parse_number(name, value)?
+            }
+            "dedup" => self.store.dedup = parse_bool(name, value)?,
+            "verify" => self.store.verify = parse_bool(name, value)?,
+            "max-entries" => self.limits.max_entries = parse_number(name, value)?,
+            "max-key-length" => self.limits.max_key_length = parse_number(name, value)?,
+            "max-value-length" => self.limits.max_value_length = parse_number(name, value)?,
+            "max-store-bytes" => self.limits.max_store_bytes = parse_number(name, value)?,
+            "rate-limit-client-rps" => {
+                self.limits.client_requests_per_second = parse_number(name, value)?
+            }
+            "rate-limit-client-bps" => {
+                self.limits.client_bytes_per_second = parse_number(name, value)?
+            }
+            "rate-limit-global-rps" => {
+                self.limits.global_requests_per_second = parse_number(name, value)?
+            }
+            "rate-limit-global-bps" => {
+                self.limits.global_bytes_per_second = parse_number(name, value)?
+            }
+            "silent" => self.logging.silent = parse_bool(name, value)?,
+            "log-level" => self.logging.level = value,
+            "log-format" => self.logging.format = value,
+            "log-output" => self.logging.output = value,
+            "log-file" => self.logging.file = value,
+            "redact-keys" => self.logging.redact_keys = parse_bool(name, value)?,
+            "stats-interval" => self.logging.stats_interval = parse_number(name, value)?,
+            "audit-log" => self.audit.path = Some(value),
+            "otlp-endpoint" => self.tracing.otlp_endpoint = Some(value),
+            "backup-path" => self.backup.path = Some(value),
+            "backup-interval" => self.backup.interval = parse_number(name, value)?,
+            "backup-retention" => self.backup.retention = parse_number(name, value)?,
+            "backup-key-file" => self.backup.key_file = Some(value),
+            "backup-passphrase-file" => self.backup.passphrase_file = Some(value),
+            // The config file itself is handled by load()
+            "config" => (),
+            _ => return Err(format!("Unknown setting \"{}\".", name)),
+        }
+        Ok(())
+    }
+
+    // All listeners serving the gRPC interface, the one of [listener] and [tls] first
+    pub fn listeners(&self) -> Vec<Listener> {
+        let mut listeners = vec![Listener {
+            address: ListenAddress::Tcp(socket_address(&self.listener.ip, self.listener.port)),
+            tls: self.tls_settings(),
+        }];
+        for listener in self.listeners.iter() {
+            listeners.push(Listener {
+                address: match &listener.unix {
+                    Some(path) => ListenAddress::Unix(path.clone(), listener.unix_mode),
+                    None => ListenAddress::Tcp(socket_address(&listener.ip, listener.port)),
+                },
+                tls: match listener.tls {
+                    true => Some(TlsSettings {
+                        path: listener.tls_path.clone(),
+                        client_ca: listener.tls_client_ca.clone(),
+                    }),
+                    false => None,
+                },
+            });
+        }
+        listeners
+    }
+
+    // Listener of the HTTP gateway, None if the gateway is disabled
+    pub fn http_listener(&self) -> Option<Listener> {
+        match self.http.enabled {
+            true => Some(Listener {
+                address: ListenAddress::Tcp(socket_address(&self.http.ip, self.http.port)),
+                tls: self.tls_settings(),
+            }),
+            false => None,
+        }
+    }
+
+    // Socket of the metrics endpoint, None if the endpoint is disabled
+    pub fn metrics_socket(&self) -> Option<SocketAddr> {
+        match self.metrics.enabled {
+            true => Some(socket_address(&self.metrics.ip, self.metrics.port)),
+            false => None,
+        }
+    }
+
+    // TLS settings of [tls], None if TLS is disabled
+    fn tls_settings(&self) -> Option<TlsSettings> {
+        match self.tls.enabled {
+            true => Some(TlsSettings {
+                path: self.tls.path.clone(),
+                client_ca: self.tls.client_ca.clone(),
+            }),
+            false => None,
+        }
+    }
+
+    // Validate the settings like commandline arguments are validated
+    pub fn validate(&self) -> Result<(), String> {
+        validate_socket_address(&self.listener.ip, self.listener.port)?;
+        validate_socket_address(&self.http.ip, self.http.port)?;
+        validate_socket_address(&self.metrics.ip, self.metrics.port)?;
+        let mut paths = vec![&self.store.path, &self.tls.path, &self.logging.file];
+        paths.extend(self.tls.client_ca.iter());
+        paths.extend(self.audit.path.iter());
+        paths.extend(self.backup.path.iter());
+        paths.extend(self.backup.key_file.iter());
+        paths.extend(self.backup.passphrase_file.iter());
+        for listener in self.listeners.iter() {
+            match &listener.unix {
+                Some(_path) if cfg!(not(unix)) => {
+                    return Err("Unix listeners are only supported on Unix.".to_string())
+                }
+                Some(path) => paths.push(path),
+                None => validate_socket_address(&listener.ip, listener.port)?,
+            }
+            paths.push(&listener.tls_path);
+            paths.extend(listener.tls_client_ca.iter());
+        }
+        for path in paths.iter() {
+            if !input_validation::validate_path(path.to_string()) {
+                return Err(format!(
+                    "Path parameter \"{}\" invalid, only valid filesystem paths using alphanumeric characters, \"\\\", \"/\", \".\", \":\", \"-\", \"_\" are allowed.",
+                    path
+                ));
+            }
+        }
+        if !BACKENDS.contains(&self.store.backend.as_str()) {
+            return Err(format!(
+                "Backend parameter \"{}\" invalid, possible values: {}.",
+                self.store.backend,
+                BACKENDS.join(", ")
+            ));
+        }
+        if self.store.queue_capacity == 0 {
+            return Err("Queue capacity has to be at least 1.".to_string());
+        }
+        self.logging.log_settings()?;
+        if let Some(endpoint) = &self.tracing.otlp_endpoint {
+            // TLS is not supported for the export to a local collector
+            if !endpoint.starts_with("http://") || Endpoint::from_shared(endpoint.clone()).is_err()
+            {
+                return Err(format!(
+                    "OTLP endpoint \"{}\" invalid, expected e.g. \"http://127.0.0.1:4317\".",
+                    endpoint
+                ));
+            }
+        }
+        if self.limits.max_entries == 0 {
+            return Err("Maximum number of entries has to be at least 1.".to_string());
+        }
+        if self.limits.max_key_length == 0 {
+            return Err("Maximum key length has to be at least 1.".to_string());
+        }
+        if self.limits.max_value_length < VALUE_LEN_MIN {
+            return Err(format!(
+                "Maximum value length has to be at least {}.",
+                VALUE_LEN_MIN
+            ));
+        }
+        if self.backup.path.is_some() {
+            if self.backup.interval == 0 {
+                return Err("Backup interval has to be at least 1.".to_string());
+            }
+            if self.backup.retention == 0 {
+                return Err("Backup retention has to be at least 1.".to_string());
+            }
+            if self.backup.key_file.is_some() == self.backup.passphrase_file.is_some() {
+                return Err(
+                    "Scheduled snapshots require either a backup key file or a backup passphrase file."
+                        .to_string(),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_socket_address(ip: &str, port: u16) -> Result<(), String> {
+    if !input_validation::validate_ipv4(ip.to_string()) {
+        return Err(format!(
+            "IP parameter \"{}\" invalid, only IPv4 allowed.",
+            ip
+        ));
+    }
+    if !input_validation::validate_port(port.to_string()) {
+        return Err(format!(
+            "Port parameter \"{}\" invalid, only valid TCP port numbers allowed.",
+            port
+        ));
+    }
+    Ok(())
+}
+
+// Socket address of a validated IP and port
+fn socket_address(ip: &str, port: u16) -> SocketAddr {
+    format!("{}:{}", ip, port).parse().unwrap()
+}
+
+// Name of the environment variable of a setting
+fn env_name(name: &str) -> String {
+    format!("{}{}", ENV_PREFIX, name.to_uppercase().replace('-', "_"))
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: String) -> Result<T, String> {
+    if input_validation::validate_unsigned_integer(value.clone()) {
+        if let Ok(number) = value.parse() {
+            return Ok(number);
+        }
+    }
+    Err(format!(
+        "Parameter \"{}\" value \"{}\" invalid, only unsigned integers allowed.",
+        name, value
+    ))
+}
+
+fn parse_bool(name: &str, value: String) -> Result<bool, String> {
+    match value.as_str() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(format!(
+            "Parameter \"{}\" value \"{}\" invalid, only \"true\" or \"false\" allowed.",
+            name, value
+        )),
+    }
+}
@@ -5,130 +5,359 @@
 */
 
 // Rust Standard Library
-use std::net::SocketAddr;
-
-// Two Lock Queue
-use two_lock_queue::Sender;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 // Tokio Imports for gRPC
 use tokio::runtime::Runtime;
-use tonic::{
-    transport::{Identity, Server, ServerTlsConfig},
-    Request, Response, Status,
-};
+#[cfg(unix)]
+use tokio::stream::StreamExt;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time;
+use tonic::{metadata::MetadataMap, transport::Server, Code, Request, Response, Status};
 
 // gRPC imports
 use kvs_api::kvs_server::{Kvs, KvsServer};
-use kvs_api::KeyValuePair;
+use kvs_api::{KeyValuePair, Limits, LimitsRequest, Snapshot, SnapshotRequest};
+// The associated type of the snapshot stream is named after the lower case RPC
+#[allow(non_camel_case_types)]
 pub mod kvs_api {
     tonic::include_proto!("kvs_api");
 }
 
 // kvs modules
-use crate::store::file_store;
-use crate::store::json_store;
-use crate::store::store_actions::{QueueAction, ACTION_DELETE, ACTION_STORE};
-use utils::{
-    crypto,
-    filesystem_wrapper::get_exec_dir,
-    input_validation,
-    log::{log, LOG_STDOUT},
+use crate::audit::AuditLog;
+use crate::config::{Config, LimitsConfig};
+use crate::http;
+#[cfg(unix)]
+use crate::listener::{bind_unix, UnixStream};
+use crate::listener::{server_tls_config, ListenAddress, Listener};
+use crate::metrics;
+use crate::rate_limit::{RateLimitExceeded, RateLimiter, RateLimits, SCOPE_CLIENT};
+use crate::snapshot::SnapshotCopy;
+use crate::stats;
+use crate::store::backend::StorageBackend;
+use crate::store::store_actions::{
+    QueueAction, ACTION_DELETE, ACTION_SNAPSHOT, ACTION_STORE, SNAPSHOT_BATCHES_BUFFERED,
 };
+use crate::systemd;
+use crate::trace::{self, RequestContext, REQUEST_ID_KEY};
+use utils::crypto::{json_encrypted_len, sha3_256_hex};
+use utils::{input_validation, log_debug, log_error, log_info, log_warn};
 
-// Supported backends
-const BACKEND_JSON: u8 = 0;
-const BACKEND_FILE: u8 = 1;
+// Milliseconds without a heartbeat after which the store handler is considered unresponsive
+const STORE_HANDLER_TIMEOUT_MS: u64 = 10000;
+// Snapshot messages buffered for a slow client
+const SNAPSHOT_STREAM_CAPACITY: usize = 4;
 
 // Implementation of the gRPC Service
 //#[derive(Debug)]
+#[derive(Clone)]
 pub struct KvsImpl {
-    send_queue: Sender<QueueAction>,
-    backend: u8,
-    storage_path: String,
+    send_queue: mpsc::Sender<QueueAction>,
+    // Time a request waits for free space in the queue
+    queue_timeout: Duration,
+    backend: Arc<dyn StorageBackend>,
+    rate_limiter: Arc<RateLimiter>,
+    limits: LimitsConfig,
+    audit: Option<Arc<AuditLog>>,
+    // Snapshots contain all decrypted values, they are only served on TLS and Unix socket listeners
+    serves_snapshots: bool,
 }
 
-#[tonic::async_trait]
-impl Kvs for KvsImpl {
-    // store Implementation
-    async fn store(
+// Client a request was received from
+#[derive(Clone, Default)]
+pub struct Peer {
+    pub address: Option<IpAddr>,
+    // SHA3-256 fingerprint of the TLS client certificate
+    pub identity: Option<String>,
+}
+
+impl Peer {
+    fn from_request<T>(request: &Request<T>) -> Peer {
+        Peer {
+            address: request.remote_addr().map(|addr| addr.ip()),
+            identity: request.peer_certs().and_then(|certificates| {
+                certificates
+                    .first()
+                    .map(|certificate| sha3_256_hex(certificate.get_ref()))
+            }),
+        }
+    }
+
+    // IP address of the peer, "local" for Unix domain sockets
+    pub fn address_string(&self) -> String {
+        match self.address {
+            Some(address) => address.to_string(),
+            None => "local".to_string(),
+        }
+    }
+}
+
+// The handlers return the status of the gRPC interface
+#[allow(clippy::result_large_err)]
+impl KvsImpl {
+    // Check the rate limits for a peer and a request of the given size,
+    // returns the status to respond with if a limit is exceeded
+    fn rate_limit_exceeded(&self, peer: Option<IpAddr>, bytes: usize) -> Option<Status> {
+        match self.rate_limiter.check(peer, bytes) {
+            Ok(()) => None,
+            Err(exceeded) => Some(rate_limit_status(exceeded)),
+        }
+    }
+
+    // Handle a request of the store RPC, shared by gRPC and the HTTP gateway
+    pub async fn handle_store(
         &self,
-        request: Request<KeyValuePair>,
-    ) -> Result<Response<KeyValuePair>, Status> {
-        let message = request.into_inner();
+        peer: &Peer,
+        context: &RequestContext,
+        message: KeyValuePair,
+    ) -> Result<KeyValuePair, Status> {
+        let key = message.key.trim().to_string();
+        let operation = self.store_pair(peer.address, context, message);
+        self.handle(metrics::RPC_STORE, peer, context, key, operation)
+            .await
+    }
+
+    // Handle a request of the get RPC, shared by gRPC and the HTTP gateway
+    pub async fn handle_get(
+        &self,
+        peer: &Peer,
+        context: &RequestContext,
+        message: KeyValuePair,
+    ) -> Result<KeyValuePair, Status> {
+        let key = message.key.trim().to_string();
+        let operation = self.get_pair(peer.address, context, message);
+        self.handle(metrics::RPC_GET, peer, context, key, operation)
+            .await
+    }
+
+    // Handle a request of the delete RPC, shared by gRPC and the HTTP gateway
+    pub async fn handle_delete(
+        &self,
+        peer: &Peer,
+        context: &RequestContext,
+        message: KeyValuePair,
+    ) -> Result<KeyValuePair, Status> {
+        let key = message.key.trim().to_string();
+        let operation = self.delete_pair(peer.address, context, message);
+        self.handle(metrics::RPC_DELETE, peer, context, key, operation)
+            .await
+    }
+
+    // Handle a request of the snapshot RPC, returns the point-in-time copy of the store
+    pub async fn handle_snapshot(
+        &self,
+        peer: &Peer,
+        context: &RequestContext,
+    ) -> Result<SnapshotCopy, Status> {
+        let operation = self.take_snapshot(peer.address, context);
+        self.handle(
+            metrics::RPC_SNAPSHOT,
+            peer,
+            context,
+            "".to_string(),
+            operation,
+        )
+        .await
+    }
+
+    // Handle a request of the limits RPC, shared by gRPC and the HTTP gateway
+    pub async fn handle_limits(
+        &self,
+        peer: &Peer,
+        context: &RequestContext,
+    ) -> Result<Limits, Status> {
+        let operation = async {
+            stats::increment(&stats::REQUESTS_LIMITS);
+            if let Some(status) = self.rate_limit_exceeded(peer.address, 0) {
+                return Err(status);
+            }
+            Ok(self.current_limits())
+        };
+        self.handle(
+            metrics::RPC_LIMITS,
+            peer,
+            context,
+            "".to_string(),
+            operation,
+        )
+        .await
+    }
+
+    // Run an operation, measure it, trace it and record it in the audit log
+    async fn handle<T>(
+        &self,
+        rpc: u8,
+        peer: &Peer,
+        context: &RequestContext,
+        key: String,
+        operation: impl Future<Output = Result<T, Status>>,
+    ) -> Result<T, Status> {
+        let start = Instant::now();
+        let started = SystemTime::now();
+        let result = operation.await;
+        metrics::observe_request(rpc, start, &result);
+        let code = match &result {
+            Ok(_) => Code::Ok,
+            Err(status) => status.code(),
+        };
+        // No entry is accessed by the limits RPC, kvsc requests the limits before storing a value
+        if let (Some(audit), false) = (&self.audit, rpc == metrics::RPC_LIMITS) {
+            audit.record(
+                peer.address_string(),
+                peer.identity.clone(),
+                metrics::RPC_NAMES[rpc as usize],
+                &key,
+                code_name(code),
+            );
+        }
+        log_debug!(
+            "Request handled.";
+            request_id = context.request_id,
+            rpc = metrics::RPC_NAMES[rpc as usize],
+            peer = peer.address_string(),
+            key = key,
+            result = code_name(code)
+        );
+        if trace::enabled() {
+            trace::record_request_span(
+                context,
+                format!("kvs_api.Kvs/{}", metrics::RPC_NAMES[rpc as usize]),
+                started,
+                vec![
+                    trace::string_attribute("rpc.system", "grpc".to_string()),
+                    trace::string_attribute("rpc.service", "kvs_api.Kvs".to_string()),
+                    trace::string_attribute(
+                        "rpc.method",
+                        metrics::RPC_NAMES[rpc as usize].to_string(),
+                    ),
+                    trace::int_attribute("rpc.grpc.status_code", code as i64),
+                    trace::string_attribute("net.peer.ip", peer.address_string()),
+                    trace::string_attribute("kvs.request_id", context.request_id.clone()),
+                ],
+                result
+                    .as_ref()
+                    .err()
+                    .map(|status| status.message().to_string()),
+            );
+        }
+        result
+    }
+
+    // Validate a key value pair and queue storing it
+    async fn store_pair(
+        &self,
+        peer: Option<IpAddr>,
+        context: &RequestContext,
+        message: KeyValuePair,
+    ) -> Result<KeyValuePair, Status> {
+        stats::increment(&stats::REQUESTS_STORE);
+        let request_size = message.key.len() + message.value.len();
+        if let Some(status) = self.rate_limit_exceeded(peer, request_size) {
+            return Err(status);
+        }
         // sanitize key and value
         let key: String = message.key.trim().to_string();
         let value: String = message.value.trim().to_string();
 
         // Check key
-        if !input_validation::validate_key(key.clone()) {
+        if !input_validation::validate_key_with_limit(key.clone(), self.limits.max_key_length) {
             return Err(Status::invalid_argument("Key invalid."));
         }
-        // Dont check length if file backend is used.
-        let mut check_length = true;
-        if self.backend == BACKEND_FILE {
-            check_length = false;
-        }
         // Check value
-        if !input_validation::validate_value(value.clone(), check_length) {
+        if !input_validation::validate_value(value.clone(), false) {
             return Err(Status::invalid_argument("Value invalid."));
         }
-        // Check size of store if JSON Backend is used
-        if self.backend == BACKEND_JSON && json_store::is_store_full() {
-            return Err(Status::resource_exhausted(
-                "Can not store more key value pairs, limit of 10.000 reached.",
-            ));
+        // Only check the size limits if the backend holds all values in RAM
+        if self.backend.holds_values_in_memory() {
+            if value.len() > self.limits.max_value_length {
+                return Err(Status::invalid_argument(format!(
+                    "Value exceeds the limit of {} bytes.",
+                    self.limits.max_value_length
+                )));
+            }
+            if self.backend.size() >= self.limits.max_entries {
+                return Err(Status::resource_exhausted(format!(
+                    "Can not store more key value pairs, limit of {} reached.",
+                    self.limits.max_entries
+                )));
+            }
+            // Existing entries are never evicted, the request is refused instead.
+            // The value is held encrypted, so its size is estimated after the encryption.
+            if self.limits.max_store_bytes > 0
+                && self.backend.memory_usage() + key.len() + json_encrypted_len(value.len())
+                    > self.limits.max_store_bytes
+            {
+                return Err(Status::resource_exhausted(format!(
+                    "Can not store key value pair, memory budget of {} bytes exhausted.",
+                    self.limits.max_store_bytes
+                )));
+            }
         }
         // Create QueueAction and send it to queue
         let action: QueueAction = QueueAction {
             kv: KeyValuePair { key, value },
             action: ACTION_STORE,
+            context: context.clone(),
+            queued: Instant::now(),
+            snapshot: None,
         };
-        self.send_queue.send(action).unwrap();
+        self.queue(action).await?;
 
-        Ok(Response::new(message))
+        Ok(message)
     }
-    // get Implementation
-    async fn get(&self, request: Request<KeyValuePair>) -> Result<Response<KeyValuePair>, Status> {
-        let message = request.into_inner();
+
+    // Validate a key and load its value
+    async fn get_pair(
+        &self,
+        peer: Option<IpAddr>,
+        _context: &RequestContext,
+        message: KeyValuePair,
+    ) -> Result<KeyValuePair, Status> {
+        stats::increment(&stats::REQUESTS_GET);
+        let request_size = message.key.len();
+        if let Some(status) = self.rate_limit_exceeded(peer, request_size) {
+            return Err(status);
+        }
         // sanitize key
         let key: String = message.key.trim().to_string();
         // Check key
-        if !input_validation::validate_key(key.clone()) {
+        if !input_validation::validate_key_with_limit(key.clone(), self.limits.max_key_length) {
             return Err(Status::invalid_argument("Key invalid."));
         }
-        let mut value: String = String::new();
-        // If JSON Backend is used load from HashMap, otherwise load from file
-        if self.backend == BACKEND_JSON {
-            value = match json_store::get_value(key.clone()) {
-                Ok(value) => value,
-                Err(e) => return Err(Status::not_found(e)),
-            };
-        } else if self.backend == BACKEND_FILE {
-            value = match file_store::get_value(key.clone(), self.storage_path.clone()) {
-                Ok(value) => value,
-                Err(e) => return Err(Status::not_found(e)),
-            };
-        }
+        // Reading is possible without the queue
+        let value: String = match self.backend.get(&key) {
+            Ok(value) => value,
+            Err(e) => return Err(Status::not_found(e)),
+        };
         // Create response message
-        let response_message: KeyValuePair = KeyValuePair { key, value };
-        Ok(Response::new(response_message))
+        Ok(KeyValuePair { key, value })
     }
-    // delete Implementation
-    async fn delete(
+
+    // Validate a key and queue deleting it
+    async fn delete_pair(
         &self,
-        request: Request<KeyValuePair>,
-    ) -> Result<Response<KeyValuePair>, Status> {
-        let message = request.into_inner();
+        peer: Option<IpAddr>,
+        context: &RequestContext,
+        message: KeyValuePair,
+    ) -> Result<KeyValuePair, Status> {
+        stats::increment(&stats::REQUESTS_DELETE);
+        let request_size = message.key.len();
+        if let Some(status) = self.rate_limit_exceeded(peer, request_size) {
+            return Err(status);
+        }
         // sanitize key
         let key: String = message.key.trim().to_string();
         // Check key
-        if !input_validation::validate_key(key.clone()) {
+        if !input_validation::validate_key_with_limit(key.clone(), self.limits.max_key_length) {
             return Err(Status::invalid_argument("Key invalid."));
         }
 
-        if self.backend == BACKEND_JSON && !json_store::key_exists(key.clone())
-            || self.backend == BACKEND_FILE && !file_store::key_exists(key.clone())
-        {
+        if !self.backend.exists(&key) {
             return Err(Status::not_found("Key not found!"));
         }
         // Create QueueAction and send it to queue
@@ -138,72 +367,581 @@ impl Kvs for KvsImpl {
                 value: "".to_string(),
             },
             action: ACTION_DELETE,
+            context: context.clone(),
+            queued: Instant::now(),
+            snapshot: None,
         };
-        self.send_queue.send(action).unwrap();
+        self.queue(action).await?;
 
         // Create response message
-        let response_message: KeyValuePair = KeyValuePair {
+        Ok(KeyValuePair {
             key,
             value: "".to_string(),
+        })
+    }
+
+    // Queue taking a snapshot and wait until the copy of the store is written. The store handler
+    // takes it in the order of the queue, so the snapshot contains exactly the changes queued before it.
+    async fn take_snapshot(
+        &self,
+        peer: Option<IpAddr>,
+        context: &RequestContext,
+    ) -> Result<SnapshotCopy, Status> {
+        stats::increment(&stats::REQUESTS_SNAPSHOT);
+        if !self.serves_snapshots {
+            return Err(Status::permission_denied(
+                "Snapshots are only served on TLS and Unix socket listeners.",
+            ));
+        }
+        if let Some(status) = self.rate_limit_exceeded(peer, 0) {
+            return Err(status);
+        }
+        let (reply_tx, mut reply_rx) = mpsc::channel(SNAPSHOT_BATCHES_BUFFERED);
+        let action: QueueAction = QueueAction {
+            kv: KeyValuePair {
+                key: "".to_string(),
+                value: "".to_string(),
+            },
+            action: ACTION_SNAPSHOT,
+            context: context.clone(),
+            queued: Instant::now(),
+            snapshot: Some(reply_tx),
+        };
+        self.queue(action).await?;
+        // The copy is written on a thread of its own, the disk writes would block the server
+        let backend = self.backend.name();
+        let (copy_tx, copy_rx) = oneshot::channel();
+        thread::spawn(move || {
+            let mut rt = crate::new_basic_runtime();
+            let copy = match rt.block_on(reply_rx.recv()) {
+                Some(Ok(first)) => {
+                    SnapshotCopy::write(backend, first, &mut || rt.block_on(reply_rx.recv()))
+                        .map_err(|e| Status::internal(format!("Could not take snapshot: {}", e)))
+                }
+                Some(Err(e)) => Err(Status::internal(format!("Could not take snapshot: {}", e))),
+                // The reply is dropped if the store handler stopped
+                None => Err(Status::unavailable("Store handler is not running.")),
+            };
+            let _ = copy_tx.send(copy);
+        });
+        match copy_rx.await {
+            Ok(copy) => copy,
+            Err(_e) => Err(Status::internal("Could not take snapshot.")),
+        }
+    }
+
+    // Length of the largest key and value accepted, bounds the request bodies of the HTTP gateway
+    pub fn max_entry_length(&self) -> usize {
+        self.limits.max_key_length + self.limits.max_value_length
+    }
+
+    // Limits enforced for the backend in use and the current usage
+    fn current_limits(&self) -> Limits {
+        // The size limits only apply if the backend holds all values in RAM
+        let enforced = |limit: usize| {
+            if self.backend.holds_values_in_memory() {
+                limit as u64
+            } else {
+                0
+            }
+        };
+        Limits {
+            backend: self.backend.name().to_string(),
+            max_key_length: self.limits.max_key_length as u64,
+            max_value_length: enforced(self.limits.max_value_length),
+            max_entries: enforced(self.limits.max_entries),
+            max_store_bytes: enforced(self.limits.max_store_bytes),
+            entries: self.backend.size() as u64,
+            store_bytes: self.backend.memory_usage() as u64,
+        }
+    }
+
+    // Queue an action for the store handler, waits for free space up to the queue timeout
+    async fn queue(&self, action: QueueAction) -> Result<(), Status> {
+        let mut send_queue = self.send_queue.clone();
+        // Counted before sending, the store handler may receive the action right away
+        metrics::queued();
+        let sent = time::timeout(self.queue_timeout, send_queue.send(action)).await;
+        if !matches!(sent, Ok(Ok(()))) {
+            metrics::dequeued();
+        }
+        match sent {
+            Ok(Ok(())) => Ok(()),
+            // The receiver is dropped if the store handler stopped
+            Ok(Err(_e)) => {
+                log_error!("Store handler is not running, changes can not be persisted.");
+                Err(Status::unavailable("Store handler is not running."))
+            }
+            Err(_elapsed) => {
+                stats::increment(&stats::QUEUE_FULL);
+                let age = systemd::store_handler_heartbeat_age();
+                if age >= STORE_HANDLER_TIMEOUT_MS {
+                    log_error!("Store handler did not respond for {} ms.", age);
+                    return Err(Status::unavailable("Store handler is not responding."));
+                }
+                Err(Status::unavailable("Queue is full, retry later."))
+            }
+        }
+    }
+}
+
+// Create a RESOURCE_EXHAUSTED status carrying a retry hint
+fn rate_limit_status(exceeded: RateLimitExceeded) -> Status {
+    let scope = if exceeded.scope == SCOPE_CLIENT {
+        stats::increment(&stats::RATE_LIMITED_CLIENT);
+        "client"
+    } else {
+        stats::increment(&stats::RATE_LIMITED_GLOBAL);
+        "global"
+    };
+    // Round up to not hint a retry before the limit is lifted
+    let retry_after_ms = exceeded.retry_after.as_millis() + 1;
+    let mut metadata = MetadataMap::new();
+    metadata.insert(
+        "retry-after-ms",
+        retry_after_ms.to_string().parse().unwrap(),
+    );
+    Status::with_metadata(
+        Code::ResourceExhausted,
+        format!(
+            "Rate limit ({}) exceeded, retry after {} ms.",
+            scope, retry_after_ms
+        ),
+        metadata,
+    )
+}
+
+// Name of a gRPC status code as used by the gRPC specification
+pub fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::Ok => "OK",
+        Code::Cancelled => "CANCELLED",
+        Code::InvalidArgument => "INVALID_ARGUMENT",
+        Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+        Code::NotFound => "NOT_FOUND",
+        Code::AlreadyExists => "ALREADY_EXISTS",
+        Code::PermissionDenied => "PERMISSION_DENIED",
+        Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+        Code::FailedPrecondition => "FAILED_PRECONDITION",
+        Code::Aborted => "ABORTED",
+        Code::OutOfRange => "OUT_OF_RANGE",
+        Code::Unimplemented => "UNIMPLEMENTED",
+        Code::Internal => "INTERNAL",
+        Code::Unavailable => "UNAVAILABLE",
+        Code::DataLoss => "DATA_LOSS",
+        Code::Unauthenticated => "UNAUTHENTICATED",
+        _ => "UNKNOWN",
+    }
+}
+
+// Context of a request with the request ID of its metadata
+fn request_context(metadata: &MetadataMap) -> RequestContext {
+    RequestContext::new(
+        metadata
+            .get(REQUEST_ID_KEY)
+            .and_then(|request_id| request_id.to_str().ok()),
+    )
+}
+
+// Respond with the request ID in the metadata, also if the request failed
+#[allow(clippy::result_large_err)]
+fn respond<T>(context: &RequestContext, result: Result<T, Status>) -> Result<Response<T>, Status> {
+    // Request IDs are validated to be printable ASCII
+    let request_id = context.request_id.parse().unwrap();
+    match result {
+        Ok(message) => {
+            let mut response = Response::new(message);
+            response.metadata_mut().insert(REQUEST_ID_KEY, request_id);
+            Ok(response)
+        }
+        Err(mut status) => {
+            status.metadata_mut().insert(REQUEST_ID_KEY, request_id);
+            Err(status)
+        }
+    }
+}
+
+// Stream a snapshot copy as messages of one chunk of entries each, a snapshot without entries
+// is a single empty message. Runs on a thread of its own and waits while the client does not
+// take the messages, the copy is removed once the client disconnects.
+fn stream_snapshot(
+    mut send: mpsc::Sender<Result<Snapshot, Status>>,
+    copy: SnapshotCopy,
+    backend: String,
+) {
+    let mut rt = crate::new_basic_runtime();
+    let manifest = copy.manifest.clone();
+    let message = |entries: Vec<(String, String)>| Snapshot {
+        backend: backend.clone(),
+        created: manifest.created,
+        total_entries: manifest.entries,
+        entries: entries
+            .into_iter()
+            .map(|(key, value)| KeyValuePair { key, value })
+            .collect(),
+    };
+    let mut send_entries = |entries| match rt.block_on(send.send(Ok(message(entries)))) {
+        Ok(()) => Ok(()),
+        Err(_e) => Err("Client disconnected.".to_string()),
+    };
+    let result = copy.read(&mut send_entries);
+    let last = match result {
+        Ok(()) if manifest.entries == 0 => Ok(message(Vec::new())),
+        Ok(()) => return,
+        Err(e) => Err(Status::internal(format!("Could not read snapshot: {}", e))),
+    };
+    let _ = rt.block_on(send.send(last));
+}
+
+#[tonic::async_trait]
+impl Kvs for KvsImpl {
+    // store Implementation
+    async fn store(
+        &self,
+        request: Request<KeyValuePair>,
+    ) -> Result<Response<KeyValuePair>, Status> {
+        let peer = Peer::from_request(&request);
+        let context = request_context(request.metadata());
+        let result = self
+            .handle_store(&peer, &context, request.into_inner())
+            .await;
+        respond(&context, result)
+    }
+    // get Implementation
+    async fn get(&self, request: Request<KeyValuePair>) -> Result<Response<KeyValuePair>, Status> {
+        let peer = Peer::from_request(&request);
+        let context = request_context(request.metadata());
+        let result = self.handle_get(&peer, &context, request.into_inner()).await;
+        respond(&context, result)
+    }
+    // delete Implementation
+    async fn delete(
+        &self,
+        request: Request<KeyValuePair>,
+    ) -> Result<Response<KeyValuePair>, Status> {
+        let peer = Peer::from_request(&request);
+        let context = request_context(request.metadata());
+        let result = self
+            .handle_delete(&peer, &context, request.into_inner())
+            .await;
+        respond(&context, result)
+    }
+    #[allow(non_camel_case_types)]
+    type snapshotStream = mpsc::Receiver<Result<Snapshot, Status>>;
+    // snapshot Implementation, the snapshot is taken before the first message is sent
+    async fn snapshot(
+        &self,
+        request: Request<SnapshotRequest>,
+    ) -> Result<Response<Self::snapshotStream>, Status> {
+        let peer = Peer::from_request(&request);
+        let context = request_context(request.metadata());
+        let copy = match self.handle_snapshot(&peer, &context).await {
+            Ok(copy) => copy,
+            Err(status) => return respond(&context, Err(status)),
         };
-        Ok(Response::new(response_message))
+        let (send, receive) = mpsc::channel(SNAPSHOT_STREAM_CAPACITY);
+        let backend = self.backend.name().to_string();
+        thread::spawn(move || stream_snapshot(send, copy, backend));
+        respond(&context, Ok(receive))
+    }
+    // limits Implementation
+    async fn limits(&self, request: Request<LimitsRequest>) -> Result<Response<Limits>, Status> {
+        let peer = Peer::from_request(&request);
+        let context = request_context(request.metadata());
+        let result = self.handle_limits(&peer, &context).await;
+        respond(&context, result)
     }
 }
 
-// Start the gRPC Server
+// Start the gRPC Server on all configured listeners, the HTTP gateway and the metrics endpoint if enabled,
+// they stop accepting requests once shutdown is signaled.
+// If a listener passed by socket activation is given, it replaces the first configured listener.
 pub fn start_grpc_server(
-    ip: String,
-    port: String,
-    enable_tls: bool,
-    send_queue: Sender<QueueAction>,
-    backend: u8,
-    storage_path: String,
+    config: Config,
+    activated_listeners: Vec<std::net::TcpListener>,
+    send_queue: mpsc::Sender<QueueAction>,
+    backend: Arc<dyn StorageBackend>,
+    audit: Option<Arc<AuditLog>>,
+    shutdown: oneshot::Receiver<()>,
+    listening: std::sync::mpsc::Sender<()>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let socket: SocketAddr = format!("{}:{}", ip, port).parse().unwrap();
+    let rate_limiter = Arc::new(RateLimiter::new(RateLimits {
+        client_requests_per_second: config.limits.client_requests_per_second,
+        client_bytes_per_second: config.limits.client_bytes_per_second,
+        global_requests_per_second: config.limits.global_requests_per_second,
+        global_bytes_per_second: config.limits.global_bytes_per_second,
+    }));
+    // All listeners share the same service and therefore the same store
+    let kvs = KvsImpl {
+        send_queue,
+        queue_timeout: Duration::from_millis(config.store.queue_timeout),
+        backend: backend.clone(),
+        rate_limiter,
+        limits: config.limits.clone(),
+        audit,
+        serves_snapshots: false,
+    };
 
+    let mut rt = Runtime::new().expect("failed to obtain a new RunTime object");
+    rt.block_on(async move {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let (error_tx, mut error_rx) = mpsc::unbounded_channel::<String>();
+        // Each server reports once its listener is bound
+        let (bound_tx, mut bound_rx) = mpsc::unbounded_channel::<()>();
+        let mut unbound = 0;
+        let mut activated_listeners = activated_listeners;
+        let mut servers = Vec::new();
+        for listener in config.listeners() {
+            let activated_listener = take_activated_listener(&mut activated_listeners, &listener);
+            let server = serve(
+                listener,
+                activated_listener,
+                kvs.clone(),
+                shutdown_rx.clone(),
+                bound_tx.clone(),
+            );
+            unbound += 1;
+            let error_tx = error_tx.clone();
+            servers.push(tokio::spawn(async move {
+                if let Err(e) = server.await {
+                    let _ = error_tx.send(format!("{}", e));
+                }
+            }));
+        }
+        if let Some(listener) = config.http_listener() {
+            let server = http::serve(listener, kvs.clone(), shutdown_rx.clone(), bound_tx.clone());
+            unbound += 1;
+            let error_tx = error_tx.clone();
+            servers.push(tokio::spawn(async move {
+                if let Err(e) = server.await {
+                    let _ = error_tx.send(format!("{}", e));
+                }
+            }));
+        }
+        if let Some(endpoint) = config.tracing.otlp_endpoint.clone() {
+            let exporter = trace::export_spans(
+                endpoint,
+                config.tracing.service_name.clone(),
+                shutdown_rx.clone(),
+            );
+            let error_tx = error_tx.clone();
+            servers.push(tokio::spawn(async move {
+                if let Err(e) = exporter.await {
+                    let _ = error_tx.send(format!("{}", e));
+                }
+            }));
+        }
+        if let Some(socket) = config.metrics_socket() {
+            let server = metrics::serve(socket, backend, shutdown_rx.clone(), bound_tx.clone());
+            unbound += 1;
+            let error_tx = error_tx.clone();
+            servers.push(tokio::spawn(async move {
+                if let Err(e) = server.await {
+                    let _ = error_tx.send(format!("{}", e));
+                }
+            }));
+        }
+        for activated_listener in activated_listeners {
+            log_warn!(
+                "Socket {} passed by systemd does not match a configured listener, it is not used.",
+                describe_activated_listener(&activated_listener)
+            );
+        }
+        drop(bound_tx);
+        // Run until shutdown is signaled or a listener fails
+        let shutdown = shutdown_signal(shutdown);
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                Some(e) = error_rx.recv() => return Err(e),
+                Some(()) = bound_rx.recv() => {
+                    unbound -= 1;
+                    if unbound == 0 {
+                        let _ = listening.send(());
+                    }
+                }
+            }
+        }
+        let _ = shutdown_tx.broadcast(true);
+        for server in servers {
+            let _ = server.await;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+// Socket passed by systemd socket activation bound to the address of a TCP listener
+fn take_activated_listener(
+    activated_listeners: &mut Vec<std::net::TcpListener>,
+    listener: &Listener,
+) -> Option<std::net::TcpListener> {
+    let socket = match listener.address {
+        ListenAddress::Tcp(socket) => socket,
+        ListenAddress::Unix(_, _) => return None,
+    };
+    let index = activated_listeners
+        .iter()
+        .position(|activated| activated.local_addr().ok() == Some(socket))?;
+    Some(activated_listeners.remove(index))
+}
+
+fn describe_activated_listener(activated_listener: &std::net::TcpListener) -> String {
+    match activated_listener.local_addr() {
+        Ok(address) => address.to_string(),
+        Err(_e) => "without TCP address".to_string(),
+    }
+}
+
+// Serve the gRPC interface on a single listener until shutdown is signaled,
+// bound is notified once the listener is bound
+async fn serve(
+    listener: Listener,
+    activated_listener: Option<std::net::TcpListener>,
+    kvs: KvsImpl,
+    shutdown: watch::Receiver<bool>,
+    bound: mpsc::UnboundedSender<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut kvs = kvs;
+    kvs.serves_snapshots =
+        listener.tls.is_some() || matches!(listener.address, ListenAddress::Unix(..));
+    let mut server = Server::builder();
     // If TLS is enabled start gRPC server with credentials
-    if enable_tls {
-        let path = get_exec_dir();
-        log(
-            format!(
-                "TLS Option for gRPC given, looking for certificate and private key in {}",
-                path
-            ),
-            LOG_STDOUT,
-        );
-        let credentials = match crypto::Credentials::new(path) {
-            Ok(creds) => creds,
-            Err(e) => return Err(e),
-        };
-        let identity =
-            Identity::from_pem(credentials.get_certificate(), credentials.get_private_key());
+    if let Some(tls) = &listener.tls {
+        server = server.tls_config(server_tls_config(tls)?)?;
+    }
+    let router = server.add_service(KvsServer::new(kvs));
+    let signal = broadcast_shutdown_signal(shutdown);
+    let description = listener.describe();
 
-        let kvs = KvsImpl {
-            send_queue,
-            backend,
-            storage_path,
-        };
+    match (activated_listener, listener.address) {
+        (Some(activated_listener), _) => {
+            log_info!("gRPC listening on {}", activated_listener.local_addr()?);
+            // Tokio requires non-blocking sockets
+            activated_listener.set_nonblocking(true)?;
+            let mut activated_listener = tokio::net::TcpListener::from_std(activated_listener)?;
+            let _ = bound.send(());
+            router
+                .serve_with_incoming_shutdown(activated_listener.incoming(), signal)
+                .await?;
+        }
+        (None, ListenAddress::Tcp(socket)) => {
+            let mut tcp_listener = tokio::net::TcpListener::bind(socket).await?;
+            log_info!("gRPC listening on {}", description);
+            let _ = bound.send(());
+            router
+                .serve_with_incoming_shutdown(tcp_listener.incoming(), signal)
+                .await?;
+        }
+        #[cfg(unix)]
+        (None, ListenAddress::Unix(path, mode)) => {
+            let mut unix_listener = bind_unix(&path, mode)?;
+            log_info!("gRPC listening on {}", description);
+            let _ = bound.send(());
+            router
+                .serve_with_incoming_shutdown(
+                    unix_listener
+                        .incoming()
+                        .map(|stream| stream.map(UnixStream)),
+                    signal,
+                )
+                .await?;
+        }
+        // Rejected when the configuration is validated
+        #[cfg(not(unix))]
+        (None, ListenAddress::Unix(_path, _mode)) => {
+            return Err("Unix listeners are only supported on Unix.".into());
+        }
+    }
+    Ok(())
+}
 
-        let mut rt = Runtime::new().expect("failed to obtain a new RunTime object");
-        let server_future = Server::builder()
-            .tls_config(ServerTlsConfig::new().identity(identity))?
-            .add_service(KvsServer::new(kvs))
-            .serve(socket);
-        rt.block_on(server_future)
-            .expect("failed to successfully run the future on RunTime");
-    } else {
-        let kvs = KvsImpl {
+// Resolves once a shutdown is signaled or the sender is dropped
+async fn shutdown_signal(shutdown: oneshot::Receiver<()>) {
+    let _ = shutdown.await;
+}
+
+// Resolves once a shutdown is broadcasted or the sender is dropped
+pub async fn broadcast_shutdown_signal(mut shutdown: watch::Receiver<bool>) {
+    while let Some(value) = shutdown.recv().await {
+        if value {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory_store::MemoryStore;
+    use std::sync::atomic::Ordering;
+
+    fn kvs(send_queue: mpsc::Sender<QueueAction>) -> KvsImpl {
+        KvsImpl {
             send_queue,
-            backend,
-            storage_path,
+            queue_timeout: Duration::from_millis(50),
+            backend: Arc::new(MemoryStore::new(false)),
+            rate_limiter: Arc::new(RateLimiter::new(RateLimits {
+                client_requests_per_second: 0,
+                client_bytes_per_second: 0,
+                global_requests_per_second: 0,
+                global_bytes_per_second: 0,
+            })),
+            limits: LimitsConfig::default(),
+            audit: None,
+            serves_snapshots: false,
+        }
+    }
+
+    fn action() -> QueueAction {
+        QueueAction {
+            kv: KeyValuePair {
+                key: "key".to_string(),
+                value: "value".to_string(),
+            },
+            action: ACTION_STORE,
+            context: RequestContext::new(None),
+            queued: Instant::now(),
+            snapshot: None,
+        }
+    }
+
+    #[test]
+    fn queue_full_unavailable() {
+        let (send_queue, _receive_queue) = mpsc::channel(1);
+        let kvs = kvs(send_queue);
+        systemd::store_handler_heartbeat();
+        let depth = metrics::QUEUE_DEPTH.load(Ordering::Relaxed);
+        let mut rt = Runtime::new().unwrap();
+        let first = rt.block_on(kvs.queue(action()));
+        let second = rt.block_on(kvs.queue(action())).unwrap_err();
+        assert_eq!(first.is_ok(), true);
+        assert_eq!(second.code(), Code::Unavailable);
+        assert_eq!(second.message(), "Queue is full, retry later.");
+        // Only the queued action is counted
+        assert_eq!(metrics::QUEUE_DEPTH.load(Ordering::Relaxed), depth + 1);
+        metrics::dequeued();
+    }
+
+    #[test]
+    fn activated_listener_matched_ok() {
+        let first = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let second = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = second.local_addr().unwrap();
+        let mut activated_listeners = vec![first, second];
+        let listener = Listener {
+            address: ListenAddress::Tcp(address),
+            tls: None,
         };
-        log(format!("gRPC listening on {}", socket), LOG_STDOUT);
-        let mut rt = Runtime::new().expect("failed to obtain a new RunTime object");
-        let server_future = Server::builder()
-            .add_service(KvsServer::new(kvs))
-            .serve(socket);
-        rt.block_on(server_future)
-            .expect("failed to successfully run the future on RunTime");
+        let matched = take_activated_listener(&mut activated_listeners, &listener);
+        assert_eq!(
+            matched.map(|matched| matched.local_addr().unwrap()),
+            Some(address)
+        );
+        assert_eq!(activated_listeners.len(), 1);
+        // Each passed socket is used by one listener only
+        let matched = take_activated_listener(&mut activated_listeners, &listener);
+        assert_eq!(matched.is_none(), true);
     }
-    Ok(())
 }
@@ -0,0 +1,334 @@
+/*
+*  kvsd HTTP gateway module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// Tokio
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tokio_rustls::{rustls::Session, TlsAcceptor};
+
+// hyper
+use hyper::body::HttpBody;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+
+// JSON
+use serde::Deserialize;
+use serde_json::json;
+
+// tonic
+use tonic::{Code, Status};
+
+// kvs modules
+use crate::grpc::kvs_api::KeyValuePair;
+use crate::grpc::{broadcast_shutdown_signal, code_name, KvsImpl, Peer};
+use crate::listener::{rustls_server_config, ListenAddress, Listener};
+use crate::trace::{RequestContext, REQUEST_ID_KEY};
+use utils::crypto::sha3_256_hex;
+use utils::{log_info, log_warn};
+
+// Path prefix of the key value endpoints, followed by the key
+const PATH_PREFIX: &str = "/v1/kv/";
+// Path of the limits endpoint
+const LIMITS_PATH: &str = "/v1/limits";
+// Allowance for the JSON syntax and escapes of a PUT request body besides key and value
+const JSON_OVERHEAD: usize = 1024;
+
+// Body of a PUT request
+#[derive(Deserialize)]
+struct StoreRequest {
+    value: String,
+}
+
+// Serve the HTTP gateway on a TCP listener until shutdown is signaled,
+// bound is notified once the listener is bound
+pub async fn serve(
+    listener: Listener,
+    kvs: KvsImpl,
+    shutdown: watch::Receiver<bool>,
+    bound: mpsc::UnboundedSender<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = match listener.address {
+        ListenAddress::Tcp(socket) => socket,
+        ListenAddress::Unix(_, _) => {
+            return Err("The HTTP gateway only supports TCP listeners.".into());
+        }
+    };
+    // The gateway shares the TLS settings of the gRPC listener
+    let acceptor = match &listener.tls {
+        Some(tls) => Some(TlsAcceptor::from(Arc::new(rustls_server_config(tls)?))),
+        None => None,
+    };
+    let mut tcp_listener = TcpListener::bind(socket).await?;
+    log_info!("HTTP gateway listening on {}", listener.describe());
+    let _ = bound.send(());
+
+    let signal = broadcast_shutdown_signal(shutdown);
+    tokio::pin!(signal);
+    loop {
+        let accepted = tokio::select! {
+            _ = &mut signal => return Ok(()),
+            accepted = tcp_listener.accept() => accepted,
+        };
+        let (stream, address) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log_warn!("HTTP gateway could not accept connection: {}", e);
+                continue;
+            }
+        };
+        let kvs = kvs.clone();
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let mut peer = Peer {
+                address: Some(address.ip()),
+                identity: None,
+            };
+            match acceptor {
+                Some(acceptor) => {
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => {
+                            // Identify clients authenticated by a certificate
+                            peer.identity = stream.get_ref().1.get_peer_certificates().and_then(
+                                |certificates| {
+                                    certificates
+                                        .first()
+                                        .map(|certificate| sha3_256_hex(&certificate.0))
+                                },
+                            );
+                            serve_connection(stream, kvs, peer, address).await
+                        }
+                        Err(e) => {
+                            log_warn!("HTTP gateway TLS handshake with {} failed: {}", address, e)
+                        }
+                    }
+                }
+                None => serve_connection(stream, kvs, peer, address).await,
+            }
+        });
+    }
+}
+
+// Serve the HTTP requests of a single connection
+async fn serve_connection<S>(stream: S, kvs: KvsImpl, peer: Peer, address: SocketAddr)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = service_fn(move |request| handle_request(kvs.clone(), peer.clone(), request));
+    if let Err(e) = Http::new().serve_connection(stream, service).await {
+        log_warn!("HTTP gateway connection to {} failed: {}", address, e);
+    }
+}
+
+// Answer a request with the request ID of its header or a generated one
+async fn handle_request(
+    kvs: KvsImpl,
+    peer: Peer,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let context = RequestContext::new(
+        request
+            .headers()
+            .get(REQUEST_ID_KEY)
+            .and_then(|request_id| request_id.to_str().ok()),
+    );
+    let mut response = route_request(kvs, peer, &context, request).await;
+    // Request IDs are validated to be printable ASCII
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_KEY, context.request_id.parse().unwrap());
+    Ok(response)
+}
+
+// Map a request onto the operations of the Kvs service
+async fn route_request(
+    kvs: KvsImpl,
+    peer: Peer,
+    context: &RequestContext,
+    request: Request<Body>,
+) -> Response<Body> {
+    if request.uri().path() == LIMITS_PATH && request.method() == Method::GET {
+        return match kvs.handle_limits(&peer, context).await {
+            Ok(limits) => json_response(
+                StatusCode::OK,
+                json!({
+                    "backend": limits.backend,
+                    "max_key_length": limits.max_key_length,
+                    "max_value_length": limits.max_value_length,
+                    "max_entries": limits.max_entries,
+                    "max_store_bytes": limits.max_store_bytes,
+                    "entries": limits.entries,
+                    "store_bytes": limits.store_bytes,
+                }),
+            ),
+            Err(status) => error_response(status),
+        };
+    }
+    let key = match request.uri().path().strip_prefix(PATH_PREFIX) {
+        Some(key) if !key.is_empty() => key.to_string(),
+        _ => {
+            return error_response(Status::not_found(format!(
+                "Unknown path, use {}{{key}}.",
+                PATH_PREFIX
+            )))
+        }
+    };
+    let result = match *request.method() {
+        Method::GET => {
+            kvs.handle_get(
+                &peer,
+                context,
+                KeyValuePair {
+                    key,
+                    value: "".to_string(),
+                },
+            )
+            .await
+        }
+        Method::PUT => {
+            let max_body_length = kvs.max_entry_length() + JSON_OVERHEAD;
+            match read_value(request, max_body_length).await {
+                Ok(value) => {
+                    kvs.handle_store(&peer, context, KeyValuePair { key, value })
+                        .await
+                }
+                Err(response) => return response,
+            }
+        }
+        Method::DELETE => {
+            kvs.handle_delete(
+                &peer,
+                context,
+                KeyValuePair {
+                    key,
+                    value: "".to_string(),
+                },
+            )
+            .await
+        }
+        _ => {
+            let mut response = error_response(Status::unimplemented(
+                "Method not allowed, use GET, PUT or DELETE.",
+            ));
+            *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            response
+                .headers_mut()
+                .insert(header::ALLOW, "GET, PUT, DELETE".parse().unwrap());
+            return response;
+        }
+    };
+    match result {
+        Ok(kv) => json_response(StatusCode::OK, json!({ "key": kv.key, "value": kv.value })),
+        Err(status) => error_response(status),
+    }
+}
+
+// Read the value of a PUT request body {"value": "..."} of at most max_body_length bytes
+async fn read_value(
+    request: Request<Body>,
+    max_body_length: usize,
+) -> Result<String, Response<Body>> {
+    // Reject announced oversized bodies before reading them
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse::<u64>().ok());
+    if let Some(content_length) = content_length {
+        if content_length > max_body_length as u64 {
+            return Err(payload_too_large(max_body_length));
+        }
+    }
+    // Chunked bodies are checked while reading
+    let mut body = request.into_body();
+    let mut bytes: Vec<u8> = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                return Err(error_response(Status::invalid_argument(format!(
+                    "Could not read request body: {}",
+                    e
+                ))))
+            }
+        };
+        if bytes.len() + chunk.len() > max_body_length {
+            return Err(payload_too_large(max_body_length));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    match serde_json::from_slice::<StoreRequest>(&bytes) {
+        Ok(store_request) => Ok(store_request.value),
+        Err(_e) => Err(error_response(Status::invalid_argument(
+            "Request body invalid, expected {\"value\": \"...\"}.",
+        ))),
+    }
+}
+
+fn payload_too_large(max_body_length: usize) -> Response<Body> {
+    let mut response = error_response(Status::out_of_range(format!(
+        "Request body too large, the maximum is {} bytes.",
+        max_body_length
+    )));
+    *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+    response
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// JSON error body mirroring the gRPC status, rate limit hints are passed on as headers
+fn error_response(status: Status) -> Response<Body> {
+    let mut response = json_response(
+        http_status(status.code()),
+        json!({
+            "code": status.code() as i32,
+            "status": code_name(status.code()),
+            "message": status.message(),
+        }),
+    );
+    if let Some(retry_after_ms) = status.metadata().get("retry-after-ms") {
+        if let Ok(milliseconds) = retry_after_ms.to_str().unwrap_or("").parse::<u64>() {
+            let headers = response.headers_mut();
+            headers.insert("retry-after-ms", milliseconds.into());
+            // Retry-After only supports seconds, round up
+            let seconds = (milliseconds as f64 / 1000.0).ceil() as u64;
+            headers.insert(header::RETRY_AFTER, seconds.into());
+        }
+    }
+    response
+}
+
+// HTTP status of a gRPC status code
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::from_u16(499).unwrap(),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
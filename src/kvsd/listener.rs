@@ -0,0 +1,171 @@
+/*
+*  kvsd listener module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+#[cfg(unix)]
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::pin::Pin;
+#[cfg(unix)]
+use std::task::{Context, Poll};
+
+// Tokio
+#[cfg(unix)]
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::{
+    internal::pemfile, AllowAnyAuthenticatedClient, NoClientAuth, RootCertStore, ServerConfig,
+};
+
+// tonic
+#[cfg(unix)]
+use tonic::transport::server::Connected;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+// kvs modules
+use utils::{crypto, filesystem_wrapper::read_file_to_string, log_info};
+
+// Address a listener is bound to
+#[derive(Clone)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    // Path and permissions of a Unix domain socket
+    Unix(String, u32),
+}
+
+// TLS settings of a listener
+#[derive(Clone)]
+pub struct TlsSettings {
+    // Directory containing grpc.crt and grpc.key
+    pub path: String,
+    // CA certificate clients have to authenticate with, if set
+    pub client_ca: Option<String>,
+}
+
+// Listener serving the gRPC interface
+#[derive(Clone)]
+pub struct Listener {
+    pub address: ListenAddress,
+    pub tls: Option<TlsSettings>,
+}
+
+impl Listener {
+    pub fn describe(&self) -> String {
+        let address = match &self.address {
+            ListenAddress::Tcp(socket) => format!("{}", socket),
+            ListenAddress::Unix(path, _mode) => format!("unix:{}", path),
+        };
+        match &self.tls {
+            Some(tls) if tls.client_ca.is_some() => {
+                format!("{} (TLS, client authentication)", address)
+            }
+            Some(_tls) => format!("{} (TLS)", address),
+            None => address,
+        }
+    }
+}
+
+// Load the server credentials and the optional client CA of a listener
+pub fn server_tls_config(tls: &TlsSettings) -> Result<ServerTlsConfig, Box<dyn std::error::Error>> {
+    log_info!(
+        "TLS Option for gRPC given, looking for certificate and private key in {}",
+        tls.path
+    );
+    let credentials = crypto::Credentials::new(tls.path.clone())?;
+    let identity = Identity::from_pem(credentials.get_certificate(), credentials.get_private_key());
+    let mut config = ServerTlsConfig::new().identity(identity);
+    if let Some(client_ca) = &tls.client_ca {
+        log_info!("Requiring client certificates issued by {}", client_ca);
+        let ca_certificate = read_file_to_string(client_ca.clone())?;
+        config = config.client_ca_root(Certificate::from_pem(ca_certificate));
+    }
+    Ok(config)
+}
+
+// rustls configuration of a listener not served by tonic, e.g. the HTTP gateway
+pub fn rustls_server_config(tls: &TlsSettings) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+    let credentials = crypto::Credentials::new(tls.path.clone())?;
+    let certificates = match pemfile::certs(&mut credentials.get_certificate()) {
+        Ok(certificates) => certificates,
+        Err(()) => return Err("Could not parse grpc.crt.".into()),
+    };
+    // Accept PKCS#8 as well as RSA private keys
+    let mut keys =
+        pemfile::pkcs8_private_keys(&mut credentials.get_private_key()).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut credentials.get_private_key()).unwrap_or_default();
+    }
+    let key = match keys.into_iter().next() {
+        Some(key) => key,
+        None => return Err("Could not parse grpc.key.".into()),
+    };
+    let verifier = match &tls.client_ca {
+        Some(client_ca) => {
+            let ca_certificate = read_file_to_string(client_ca.clone())?;
+            let mut roots = RootCertStore::empty();
+            match roots.add_pem_file(&mut ca_certificate.as_bytes()) {
+                Ok((valid, _invalid)) if valid > 0 => (),
+                _ => return Err(format!("Could not parse {}.", client_ca).into()),
+            }
+            AllowAnyAuthenticatedClient::new(roots)
+        }
+        None => NoClientAuth::new(),
+    };
+    let mut config = ServerConfig::new(verifier);
+    config.set_single_cert(certificates, key)?;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(config)
+}
+
+// Bind a Unix domain socket, a stale socket file of a previous run is replaced
+#[cfg(unix)]
+pub fn bind_unix(path: &str, mode: u32) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if std::path::Path::new(path).exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+// Unix domain socket connection accepted by the gRPC server
+#[cfg(unix)]
+pub struct UnixStream(pub tokio::net::UnixStream);
+
+#[cfg(unix)]
+impl Connected for UnixStream {}
+
+#[cfg(unix)]
+impl AsyncRead for UnixStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+#[cfg(unix)]
+impl AsyncWrite for UnixStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
@@ -7,37 +7,86 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 
 // Rust Standard Library
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-// Store
-extern crate two_lock_queue;
+// Tokio
+use tokio::runtime::{Builder, Runtime};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc as queue, oneshot};
+use tokio::time;
 
 //kvs modules
+mod audit;
+mod config;
 mod grpc;
+mod http;
+mod listener;
+mod metrics;
+mod rate_limit;
+mod snapshot;
+mod stats;
 mod store;
-use store::file_store;
-use store::json_store;
-use store::store_actions::QueueAction;
-use utils::filesystem_wrapper;
-use utils::input_validation;
-use utils::log::{log, set_log_silent, LOG_STDERR, LOG_STDOUT};
+mod systemd;
+mod trace;
+use config::{BackupConfig, Config};
+use store::backend::{create_backend, handle_action, lock_store};
+use store::migrate;
+use store::store_actions::{QueueAction, ACTION_NAMES, ACTION_SHUTDOWN};
+use trace::RequestContext;
+use utils::crypto;
+use utils::log::{self as logger, set_log_silent};
+use utils::{log_debug, log_error, log_info, log_warn};
+
+// gRPC imports
+use grpc::kvs_api::KeyValuePair;
 
 // CLI interface
 extern crate clap;
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 
 // CLI Signal handling
 extern crate ctrlc;
 
-// Supported backends
-const BACKEND_JSON: u8 = 0;
-const BACKEND_FILE: u8 = 1;
-
 fn main() {
     // Specify commandline arguments
     let matches = App::new("kvsd")
         .version(clap::crate_version!())
         .author("Benjamin Schilling <benjamin.schilling33@gmail.com>")
+        .arg(
+            Arg::with_name("config")
+                .help("TOML configuration file, e.g. /etc/kvs/kvsd.toml.\nArguments and KVSD_* environment variables override its settings.")
+                .required(false)
+                .long("config")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("check-config")
+                .help("Validate the configuration and exit.")
+                .long("check-config"),
+        )
+        .arg(
+            Arg::with_name("verify-audit-log")
+                .help("Verify the hash chain of the audit log given by \"audit-log\" and exit.")
+                .long("verify-audit-log"),
+        )
+        .arg(
+            Arg::with_name("audit-log")
+                .help("File to append an audit record of every request to, values are never recorded.")
+                .long("audit-log")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("otlp-endpoint")
+                .help("OTLP/gRPC endpoint of an OpenTelemetry collector to export request spans to, e.g. http://127.0.0.1:4317.")
+                .long("otlp-endpoint")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("ip")
                 .help("IP address the kvs daemon shall bind the gRPC interface to.")
@@ -65,137 +114,652 @@ fn main() {
             .required(false)
             .long("backend")
             .takes_value(true)
-            .possible_values(&["json", "file"]),
+            .possible_values(&config::BACKENDS),
         )
         .arg(
             Arg::with_name("tls")
                 .help("Set to enable TLS support for gRPC.\nIf set certificate and private key are expected as grpc.crt\nand grpc.key in the execution directory of kvsd binary.")
                 .long("tls"),
         )
+        .arg(
+            Arg::with_name("tls-path")
+                .help("Directory containing grpc.crt and grpc.key. Default: execution directory of kvsd binary")
+                .long("tls-path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-client-ca")
+                .help("CA certificate clients have to present a certificate of, enables TLS client authentication.")
+                .long("tls-client-ca")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("http")
+                .help("Set to enable the HTTP/JSON gateway serving GET, PUT and DELETE /v1/kv/{key}.\nThe gateway uses the TLS settings of the gRPC interface.")
+                .long("http"),
+        )
+        .arg(
+            Arg::with_name("http-ip")
+                .help("IP address the kvs daemon shall bind the HTTP gateway to. Default: 127.0.0.1")
+                .long("http-ip")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("http-port")
+                .help("Port the kvs daemon shall bind the HTTP gateway to. Default: 27080")
+                .long("http-port")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics")
+                .help("Set to enable the Prometheus metrics endpoint GET /metrics.")
+                .long("metrics"),
+        )
+        .arg(
+            Arg::with_name("metrics-ip")
+                .help("IP address the kvs daemon shall bind the metrics endpoint to. Default: 127.0.0.1")
+                .long("metrics-ip")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics-port")
+                .help("Port the kvs daemon shall bind the metrics endpoint to. Default: 27090")
+                .long("metrics-port")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-entries")
+            .help("Maximum number of key value pairs of the JSON and memory backend. Default: 10000")
+            .long("max-entries")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-key-length")
+            .help("Maximum key length of all backends. Default: 32")
+            .long("max-key-length")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-value-length")
+            .help("Maximum value length of the JSON and memory backend. Default: 1024")
+            .long("max-value-length")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-store-bytes")
+            .help("Maximum bytes of keys and encrypted values held in RAM by the JSON and memory backend.\nDefault: 0 (unlimited)")
+            .long("max-store-bytes")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("silent")
             .help("Supress all stdout and stderr messages.")
             .long("silent")
         )
+        .arg(
+            Arg::with_name("log-level")
+            .help("Log level, optionally followed by levels per module, e.g. \"info,kvsd::grpc=debug\".\nPossible levels: off, error, warn, info, debug, trace. Default: info\nSend SIGHUP to reload the level from the configuration.")
+            .long("log-level")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-format")
+            .help("Format of log events: text, json. Default: text")
+            .long("log-format")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-output")
+            .help("Output of log events: stderr, file, syslog, journald. Default: stderr")
+            .long("log-output")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-file")
+            .help("Log file of the file output, rotated at 10 MiB. Default: kvsd.log next to the executable")
+            .long("log-file")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("redact-keys")
+            .help("Replace keys in log events by \"<redacted>\".")
+            .long("redact-keys")
+        )
+        .arg(
+            Arg::with_name("rate-limit-client-rps")
+            .help("Maximum requests per second of a single client. Default: 0 (unlimited)")
+            .long("rate-limit-client-rps")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rate-limit-client-bps")
+            .help("Maximum request bytes per second of a single client. Default: 0 (unlimited)")
+            .long("rate-limit-client-bps")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rate-limit-global-rps")
+            .help("Maximum requests per second of all clients. Default: 0 (unlimited)")
+            .long("rate-limit-global-rps")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rate-limit-global-bps")
+            .help("Maximum request bytes per second of all clients. Default: 0 (unlimited)")
+            .long("rate-limit-global-bps")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutdown-timeout")
+            .help("Timeout in seconds for persisting all pending changes on shutdown. Default: 10")
+            .long("shutdown-timeout")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("queue-capacity")
+            .help("Maximum number of changes waiting to be persisted. Default: 1024")
+            .long("queue-capacity")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("queue-timeout")
+            .help("Milliseconds a request waits if the queue is full before it fails. Default: 1000")
+            .long("queue-timeout")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("wipe-on-shutdown")
+            .help("Set to overwrite all entries of the memory backend on shutdown.")
+            .long("wipe-on-shutdown"),
+        )
+        .arg(
+            Arg::with_name("compression-threshold")
+            .help("Minimum value length in bytes for compressing values before the encryption. Default: 0 (disabled)")
+            .long("compression-threshold")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dedup")
+            .help("Set to share a single value file between keys with identical values in the file backend, reveals which keys hold identical values.")
+            .long("dedup"),
+        )
+        .arg(
+            Arg::with_name("verify")
+            .help("Set to decrypt and authenticate all value files of the file backend on start-up instead of only checking that they exist.")
+            .long("verify"),
+        )
+        .arg(
+            Arg::with_name("stats-interval")
+            .help("Interval in seconds for logging the daemon stats. Default: 0 (disabled)")
+            .long("stats-interval")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup-path")
+            .help("Directory scheduled snapshots are written to. Default: none (disabled)")
+            .long("backup-path")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup-interval")
+            .help("Interval in seconds between two scheduled snapshots. Default: 86400")
+            .long("backup-interval")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup-retention")
+            .help("Number of scheduled snapshots kept, older snapshots are deleted. Default: 7")
+            .long("backup-retention")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup-key-file")
+            .help("File containing the Base64 encoded 32 byte key snapshots are encrypted with.")
+            .long("backup-key-file")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backup-passphrase-file")
+            .help("File containing the passphrase the key of snapshots is derived from, alternative for \"backup-key-file\".")
+            .long("backup-passphrase-file")
+            .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Migrate the store to another backend while kvsd is stopped and exit.\nThe original store directory is kept as <path>.<from>-backup.")
+                .arg(
+                    Arg::with_name("from")
+                        .help("Backend the store is using.")
+                        .long("from")
+                        .required(true)
+                        .takes_value(true)
+                        .possible_values(&config::BACKENDS),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .help("Backend to migrate the store to.")
+                        .long("to")
+                        .required(true)
+                        .takes_value(true)
+                        .possible_values(&config::BACKENDS),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .help("Directory of the store, only containing the store. Default: the configured path")
+                        .long("path")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Restore an archive written by \"kvsc backup\" or a scheduled snapshot into an empty store while kvsd is stopped and exit.")
+                .arg(
+                    Arg::with_name("archive")
+                        .help("Archive to restore.")
+                        .long("archive")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .help("Directory of the store, created if missing. Default: the configured path")
+                        .long("path")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("key-file")
+                        .help("File containing the Base64 encoded 32 byte key the archive is encrypted with. Default: the configured backup key file")
+                        .long("key-file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("passphrase-file")
+                        .help("File containing the passphrase the key of the archive is derived from, alternative for \"key-file\".")
+                        .long("passphrase-file")
+                        .takes_value(true)
+                        .conflicts_with("key-file"),
+                ),
+        )
         .get_matches();
 
+    // Load configuration file, environment variables and arguments
+    let config = match Config::load(&matches) {
+        Ok(config) => config,
+        Err(e) => {
+            log_error!("{}", e);
+            std::process::exit(0x0001);
+        }
+    };
+    if matches.is_present("check-config") {
+        log_info!("Configuration valid.");
+        std::process::exit(0x0000);
+    }
+    if matches.is_present("verify-audit-log") {
+        verify_audit_log(&config);
+    }
+    // Values written from now on are compressed if configured, also when migrating
+    crypto::set_compression_threshold(config.store.compression_threshold);
+    if let Some(migrate_matches) = matches.subcommand_matches("migrate") {
+        migrate_store(&config, migrate_matches);
+    }
+    if let Some(restore_matches) = matches.subcommand_matches("restore") {
+        restore_store(&config, restore_matches);
+    }
+
     // For for silent option
-    // For for silent option
-    if matches.is_present("silent") {
+    if config.logging.silent {
         set_log_silent(true);
     }
-    // Properly handle CTRL-C signals
-    ctrlc::set_handler(move || {
-        log("Received Ctrl+C, shutting down.".to_string(), LOG_STDOUT);
-        std::process::exit(0x0000);
-    })
-    .expect("Error setting Ctrl+C handler");
-    // Set IP and Port to default values
-    let mut ip: String = "127.0.0.1".to_string();
-    // Set IP to provided value if existing
-    if matches.is_present("ip") {
-        if input_validation::validate_ipv4(matches.value_of("ip").unwrap().to_string()) {
-            ip = matches.value_of("ip").unwrap().to_string();
-        } else {
-            log(
-                format!(
-                    "IP parameter \"{}\" invalid, only IPv4 allowed.",
-                    matches.value_of("ip").unwrap().to_string()
-                ),
-                LOG_STDERR,
-            );
-            std::process::exit(0x0001);
-        }
+    // Log level, format and output, validated by loading the configuration
+    if let Err(e) = logger::init(config.logging.log_settings().unwrap()) {
+        log_error!("{}", e);
+        std::process::exit(0x0001);
     }
-    // Set Port to provided value if existing
-    let mut port: String = "27001".to_string();
-    if matches.is_present("port") {
-        if input_validation::validate_port(matches.value_of("port").unwrap().to_string()) {
-            port = matches.value_of("port").unwrap().to_string();
-        } else {
-            log(
-                format!(
-                    "Port parameter \"{}\" invalid, only valid TCP port numbers allowed.",
-                    matches.value_of("port").unwrap().to_string()
-                ),
-                LOG_STDERR,
-            );
+    #[cfg(unix)]
+    reload_log_level_on_hangup(matches.clone());
+    // Properly handle SIGINT and SIGTERM signals, a second signal exits immediately
+    let (signal_tx, signal_rx) = mpsc::channel::<()>();
+    let handler_failed_tx = signal_tx.clone();
+    let signal_received = AtomicBool::new(false);
+    ctrlc::set_handler(move || {
+        if signal_received.swap(true, Ordering::SeqCst) {
+            log_warn!("Received second signal, exiting.");
             std::process::exit(0x0001);
         }
-    }
-    // Set path for persistent store file
-    let mut path: String = filesystem_wrapper::get_exec_dir();
-    if matches.is_present("path") {
-        if input_validation::validate_path(matches.value_of("path").unwrap().to_string()) {
-            path = matches.value_of("path").unwrap().to_string();
-        } else {
-            log(
-                format!(
-                    "Path parameter \"{}\" invalid, only valid filesystem paths using alphanumeric characters, \"\\\", \"/\", \".\", \":\", \"-\", \"_\" are allowed.",
-                matches.value_of("Path").unwrap().to_string()
-                ),
-                LOG_STDERR,
-            );
+        log_info!("Received signal, shutting down.");
+        let _ = signal_tx.send(());
+    })
+    .expect("Error setting Ctrl+C handler");
+    // Create the configured backend storing its data at the configured path
+    let backend = match create_backend(&config.store) {
+        Ok(backend) => backend,
+        Err(e) => {
+            log_error!("{}", e);
             std::process::exit(0x0001);
         }
-    }
-    // Set backend, json is default
-    let mut backend: u8 = BACKEND_JSON;
+    };
 
-    if matches.is_present("backend") {
-        let backend_value = matches.value_of("backend").unwrap();
-        if backend_value == "file" {
-            backend = BACKEND_FILE;
+    // Keep other kvsd processes and migrations away from the store until exiting,
+    // the memory backend has no files to protect
+    let _store_lock = if backend.files().is_empty() || !Path::new(&config.store.path).is_dir() {
+        None
+    } else {
+        match lock_store(&config.store.path) {
+            Ok(lock) => Some(lock),
+            Err(e) => {
+                log_error!("{}", e);
+                std::process::exit(0x0001);
+            }
         }
-    }
+    };
 
     // Read persistent store from file
-    if backend == BACKEND_JSON {
-        match json_store::initialize_store_from_file(path.clone()) {
-            Ok(ok) => log(format!("Finished loading file: {}", ok), LOG_STDOUT),
-            Err(e) => log(format!("Error loading file: {}", e), LOG_STDERR),
+    systemd::notify_status("Loading store".to_string());
+    match backend.load() {
+        Ok(ok) => {
+            log_info!("Finished loading {} backend: {}", backend.name(), ok);
+            systemd::notify_status(ok);
         }
-    } else if backend == BACKEND_FILE {
-        match file_store::load_meta_data_from_file(path.clone()) {
-            Ok(ok) => log(format!("Finished loading file: {}", ok), LOG_STDOUT),
-            Err(e) => log(format!("Error loading file: {}", e), LOG_STDERR),
+        Err(e) => {
+            log_error!("Error loading file: {}", e);
+            systemd::notify_status(format!("Error loading file: {}", e));
         }
     }
 
-    let (tx, rx) = two_lock_queue::unbounded::<QueueAction>();
+    // Continue the audit log if configured
+    let audit_log = match &config.audit.path {
+        Some(audit_path) => match audit::AuditLog::open(audit_path.clone()) {
+            Ok(audit_log) => Some(Arc::new(audit_log)),
+            Err(e) => {
+                log_error!("{}", e);
+                std::process::exit(0x0001);
+            }
+        },
+        None => None,
+    };
+
+    // Periodically log the stats if requested
+    let stats_interval = config.logging.stats_interval;
+    if stats_interval > 0 {
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(stats_interval));
+            stats::log_stats();
+        });
+    }
+
+    // Requests wait for free space or fail if the store handler falls behind
+    let (tx, mut rx) = queue::channel::<QueueAction>(config.store.queue_capacity);
+
+    // Take scheduled snapshots if configured
+    if let Some(backup_path) = &config.backup.path {
+        let secret = match config.backup.secret() {
+            Ok(secret) => secret,
+            Err(e) => {
+                log_error!("{}", e);
+                std::process::exit(0x0001);
+            }
+        };
+        snapshot::start_scheduled_snapshots(
+            backup_path.clone(),
+            config.backup.interval,
+            config.backup.retention,
+            secret,
+            backend.name(),
+            tx.clone(),
+        );
+    }
+
+    // Listening sockets passed by systemd replace the listeners bound to the same address
+    let activated_listeners = systemd::listen_sockets();
 
     // Start the gRPC Server in a thread
-    let grpc_path = path.clone();
-    thread::spawn(move || {
+    let grpc_config = config.clone();
+    let grpc_tx = tx.clone();
+    let grpc_backend = backend.clone();
+    let (grpc_shutdown_tx, grpc_shutdown_rx) = oneshot::channel::<()>();
+    let (listening_tx, listening_rx) = mpsc::channel::<()>();
+    let grpc_server = thread::spawn(move || {
         match grpc::start_grpc_server(
-            ip,
-            port,
-            matches.is_present("tls"),
-            tx.clone(),
-            backend,
-            grpc_path,
+            grpc_config,
+            activated_listeners,
+            grpc_tx,
+            grpc_backend,
+            audit_log,
+            grpc_shutdown_rx,
+            listening_tx,
         ) {
-            Ok(o) => {
-                log(format!("{:?}", o), LOG_STDOUT);
-                println!();
-            }
+            Ok(o) => log_debug!("gRPC server returned {:?}", o),
             Err(e) => {
-                log(format!("{}", e), LOG_STDERR);
+                log_error!("{}", e);
                 std::process::exit(0x0001);
             }
         }
     });
 
     // Start the store handler in a thread
-    let child = thread::spawn(move || loop {
-        let action = rx.recv().unwrap();
-        if backend == BACKEND_JSON {
-            json_store::handle_action(action, path.clone());
-        } else if backend == BACKEND_FILE {
-            file_store::handle_action(action, path.clone());
+    let child = thread::spawn(move || {
+        let _guard = StoreHandlerGuard(handler_failed_tx);
+        let mut rt = new_basic_runtime();
+        loop {
+            // Wake up regularly to signal liveness to the systemd watchdog
+            systemd::store_handler_heartbeat();
+            // The timeout has to be created within the runtime
+            let received =
+                rt.block_on(async { time::timeout(Duration::from_secs(1), rx.recv()).await });
+            let action = match received {
+                Ok(Some(action)) if action.action != ACTION_SHUTDOWN => action,
+                // All previously queued actions are handled, flush them to disk
+                Ok(_) => {
+                    if let Err(e) = backend.flush() {
+                        log_error!("Could not flush store: {}", e);
+                    }
+                    backend.close();
+                    break;
+                }
+                Err(_elapsed) => continue,
+            };
+            metrics::dequeued();
+            let start = Instant::now();
+            let queue_wait = action.queued.elapsed();
+            let context = action.context.clone();
+            let name = format!("persist {}", ACTION_NAMES[action.action as usize]);
+            handle_action(backend.as_ref(), action);
+            metrics::PERSISTENCE_WRITE_DURATION.observe(start.elapsed());
+            // The span covers waiting in the queue and persisting the action
+            if trace::enabled() {
+                trace::record_handler_span(
+                    &context,
+                    name,
+                    SystemTime::now() - queue_wait - start.elapsed(),
+                    vec![
+                        trace::int_attribute("kvs.queue_wait_us", queue_wait.as_micros() as i64),
+                        trace::string_attribute("kvs.request_id", context.request_id.clone()),
+                    ],
+                );
+            }
+        }
+    });
+
+    // Store is loaded and the store handler is running, ready once all listeners are bound.
+    // The gRPC server exits kvsd if a listener can not be bound.
+    systemd::start_watchdog();
+    if listening_rx.recv().is_ok() {
+        systemd::notify("READY=1");
+    }
+
+    //Run infinitely, until SIGINT or SIGTERM or the store handler failed
+    let _res = signal_rx.recv();
+    systemd::notify("STOPPING=1");
+    shutdown(
+        grpc_shutdown_tx,
+        grpc_server,
+        tx,
+        child,
+        config.store.shutdown_timeout,
+    );
+}
+
+// Reports the store handler thread failing and wakes up main, which shuts down with an error
+struct StoreHandlerGuard(mpsc::Sender<()>);
+
+impl Drop for StoreHandlerGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            log_error!("Store handler failed, changes can not be persisted anymore.");
+            systemd::notify_status("Store handler failed".to_string());
+            let _ = self.0.send(());
         }
+    }
+}
+
+// Single threaded runtime for waiting on the queue outside of the gRPC server
+fn new_basic_runtime() -> Runtime {
+    Builder::new()
+        .basic_scheduler()
+        .enable_time()
+        .build()
+        .expect("failed to obtain a new RunTime object")
+}
+
+// Reload the log level and key redaction from the configuration on SIGHUP
+#[cfg(unix)]
+fn reload_log_level_on_hangup(matches: clap::ArgMatches<'static>) {
+    thread::spawn(move || {
+        let mut rt = Runtime::new().expect("failed to obtain a new RunTime object");
+        rt.block_on(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => return log_error!("Could not handle SIGHUP: {}", e),
+            };
+            while hangup.recv().await.is_some() {
+                match Config::load(&matches) {
+                    Ok(config) => {
+                        let settings = config.logging.log_settings().unwrap();
+                        logger::set_filter(settings.filter, settings.redact_keys);
+                        log_info!("Log level changed to \"{}\".", config.logging.level);
+                    }
+                    Err(e) => log_error!("Keeping log level, configuration invalid: {}", e),
+                }
+            }
+        });
     });
+}
 
-    //Run infinitely, until CTRL+C
-    let _res = child.join();
+// Verify the hash chain of the configured audit log and exit
+fn verify_audit_log(config: &Config) {
+    let path = match &config.audit.path {
+        Some(path) => path.clone(),
+        None => {
+            log_error!("No audit log configured, use --audit-log.");
+            std::process::exit(0x0001);
+        }
+    };
+    match audit::verify(path) {
+        Ok((entries, hash)) => {
+            log_info!("Audit log valid, {} entries, last hash: {}", entries, hash);
+            std::process::exit(0x0000);
+        }
+        Err(e) => {
+            log_error!("Audit log invalid: {}", e);
+            std::process::exit(0x0001);
+        }
+    }
+}
+
+// Migrate the store to another backend and exit
+fn migrate_store(config: &Config, matches: &clap::ArgMatches) {
+    let from = matches.value_of("from").unwrap();
+    let to = matches.value_of("to").unwrap();
+    let path = matches.value_of("path").unwrap_or(&config.store.path);
+    match migrate::migrate(from, to, path, &config.store) {
+        Ok(backup_path) => {
+            log_info!(
+                "Migrated store to the {} backend, set backend = \"{}\" before starting kvsd. Previous store kept at \"{}\".",
+                to,
+                to,
+                backup_path
+            );
+            std::process::exit(0x0000);
+        }
+        Err(e) => {
+            log_error!("{}", e);
+            std::process::exit(0x0001);
+        }
+    }
+}
+
+// Restore an archive into an empty store and exit
+fn restore_store(config: &Config, matches: &clap::ArgMatches) {
+    let archive = matches.value_of("archive").unwrap();
+    let path = matches.value_of("path").unwrap_or(&config.store.path);
+    // Secret files given to the subcommand replace the configured ones
+    let backup_config = if matches.is_present("key-file") || matches.is_present("passphrase-file") {
+        BackupConfig {
+            key_file: matches.value_of("key-file").map(|s| s.to_string()),
+            passphrase_file: matches.value_of("passphrase-file").map(|s| s.to_string()),
+            ..config.backup.clone()
+        }
+    } else {
+        config.backup.clone()
+    };
+    let result = backup_config
+        .secret()
+        .and_then(|secret| snapshot::restore(&config.store, path, archive, &secret));
+    match result {
+        Ok(manifest) => {
+            log_info!(
+                "Restored {} key value pairs of a {} backend snapshot to \"{}\".",
+                manifest.entries,
+                manifest.backend,
+                path
+            );
+            std::process::exit(0x0000);
+        }
+        Err(e) => {
+            log_error!("{}", e);
+            std::process::exit(0x0001);
+        }
+    }
+}
+
+// Stop accepting requests, persist all queued actions and exit
+fn shutdown(
+    grpc_shutdown: oneshot::Sender<()>,
+    grpc_server: thread::JoinHandle<()>,
+    mut send_queue: queue::Sender<QueueAction>,
+    store_handler: thread::JoinHandle<()>,
+    timeout: u64,
+) {
+    // Abort if the shutdown sequence does not finish in time
+    thread::spawn(move || {
+        thread::sleep(Duration::from_secs(timeout));
+        log_error!(
+            "Shutdown did not finish within {} seconds, exiting.",
+            timeout
+        );
+        std::process::exit(0x0001);
+    });
+    // Stop the gRPC server, pending requests are answered before it returns
+    let _ = grpc_shutdown.send(());
+    let _ = grpc_server.join();
+    log_info!("gRPC server stopped.");
+    // Queue the shutdown action behind all pending actions and wait for the store handler
+    let action: QueueAction = QueueAction {
+        kv: KeyValuePair {
+            key: "".to_string(),
+            value: "".to_string(),
+        },
+        action: ACTION_SHUTDOWN,
+        context: RequestContext::new(None),
+        queued: Instant::now(),
+        snapshot: None,
+    };
+    let sent = new_basic_runtime().block_on(send_queue.send(action));
+    if sent.is_err() || store_handler.join().is_err() {
+        log_error!("Store handler failed during shutdown.");
+        std::process::exit(0x0001);
+    }
+    log_info!("Persisted all pending changes.");
+    stats::log_stats();
+    std::process::exit(0x0000);
 }
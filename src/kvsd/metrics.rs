@@ -0,0 +1,240 @@
+/*
+*  kvsd metrics module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// lazy static
+use lazy_static::lazy_static;
+
+// Tokio
+use tokio::sync::{mpsc, watch};
+
+// hyper
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+
+// tonic
+use tonic::Status;
+
+// kvs modules
+use crate::grpc::{broadcast_shutdown_signal, code_name};
+use crate::stats;
+use crate::store::backend::StorageBackend;
+use utils::crypto::{DECRYPTION_DURATION, ENCRYPTION_DURATION};
+use utils::log_info;
+use utils::metrics::Histogram;
+
+// RPCs of the Kvs service
+pub const RPC_STORE: u8 = 0;
+pub const RPC_GET: u8 = 1;
+pub const RPC_DELETE: u8 = 2;
+pub const RPC_SNAPSHOT: u8 = 3;
+pub const RPC_LIMITS: u8 = 4;
+pub const RPC_NAMES: [&str; 5] = ["store", "get", "delete", "snapshot", "limits"];
+
+// Actions queued for the store handler
+pub static QUEUE_DEPTH: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    // Request duration per RPC
+    static ref REQUEST_DURATION: Vec<Histogram> =
+        RPC_NAMES.iter().map(|_| Histogram::new()).collect();
+    // Failed requests per RPC and gRPC status code
+    static ref REQUEST_ERRORS: Mutex<BTreeMap<(u8, &'static str), u64>> =
+        Mutex::new(BTreeMap::new());
+    // Duration of handling a queued action including writing it to disk
+    pub static ref PERSISTENCE_WRITE_DURATION: Histogram = Histogram::new();
+}
+
+// Record the duration and the result of a request
+pub fn observe_request<T>(rpc: u8, start: Instant, result: &Result<T, Status>) {
+    REQUEST_DURATION[rpc as usize].observe(start.elapsed());
+    if let Err(status) = result {
+        let mut errors = REQUEST_ERRORS.lock().unwrap();
+        *errors.entry((rpc, code_name(status.code()))).or_insert(0) += 1;
+    }
+}
+
+// Account an action sent to the store handler
+pub fn queued() {
+    QUEUE_DEPTH.fetch_add(1, Ordering::Relaxed);
+}
+
+// Account an action received by the store handler
+pub fn dequeued() {
+    QUEUE_DEPTH.fetch_sub(1, Ordering::Relaxed);
+}
+
+// Serve the metrics on GET /metrics until shutdown is signaled,
+// bound is notified once the socket is bound
+pub async fn serve(
+    socket: SocketAddr,
+    backend: Arc<dyn StorageBackend>,
+    shutdown: watch::Receiver<bool>,
+    bound: mpsc::UnboundedSender<()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let make_service = make_service_fn(move |_connection| {
+        let backend = backend.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(backend.clone(), request)
+            }))
+        }
+    });
+    let server = Server::try_bind(&socket)?.serve(make_service);
+    log_info!("Metrics listening on {}", socket);
+    let _ = bound.send(());
+    server
+        .with_graceful_shutdown(broadcast_shutdown_signal(shutdown))
+        .await?;
+    Ok(())
+}
+
+async fn handle_request(
+    backend: Arc<dyn StorageBackend>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found, use GET /metrics.\n"))
+            .unwrap());
+    }
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(render(backend.as_ref())))
+        .unwrap())
+}
+
+// Render all metrics in Prometheus text format
+pub fn render(backend: &dyn StorageBackend) -> String {
+    let mut output = String::new();
+
+    write_header(
+        &mut output,
+        "kvsd_requests_total",
+        "counter",
+        "Requests per RPC.",
+    );
+    let requests = [
+        &stats::REQUESTS_STORE,
+        &stats::REQUESTS_GET,
+        &stats::REQUESTS_DELETE,
+        &stats::REQUESTS_SNAPSHOT,
+        &stats::REQUESTS_LIMITS,
+    ];
+    for (name, counter) in RPC_NAMES.iter().zip(requests.iter()) {
+        let _ = writeln!(
+            output,
+            "kvsd_requests_total{{rpc=\"{}\"}} {}",
+            name,
+            counter.load(Ordering::Relaxed)
+        );
+    }
+
+    write_header(
+        &mut output,
+        "kvsd_request_duration_seconds",
+        "histogram",
+        "Duration of handling a request per RPC.",
+    );
+    for (name, histogram) in RPC_NAMES.iter().zip(REQUEST_DURATION.iter()) {
+        histogram.render(
+            "kvsd_request_duration_seconds",
+            &format!("rpc=\"{}\"", name),
+            &mut output,
+        );
+    }
+
+    write_header(
+        &mut output,
+        "kvsd_request_errors_total",
+        "counter",
+        "Failed requests per RPC and gRPC status code.",
+    );
+    for ((rpc, code), count) in REQUEST_ERRORS.lock().unwrap().iter() {
+        let _ = writeln!(
+            output,
+            "kvsd_request_errors_total{{rpc=\"{}\",code=\"{}\"}} {}",
+            RPC_NAMES[*rpc as usize], code, count
+        );
+    }
+
+    write_header(
+        &mut output,
+        "kvsd_rate_limited_total",
+        "counter",
+        "Requests rejected by the rate limiter per scope.",
+    );
+    let _ = writeln!(
+        output,
+        "kvsd_rate_limited_total{{scope=\"client\"}} {}",
+        stats::RATE_LIMITED_CLIENT.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        output,
+        "kvsd_rate_limited_total{{scope=\"global\"}} {}",
+        stats::RATE_LIMITED_GLOBAL.load(Ordering::Relaxed)
+    );
+
+    write_header(
+        &mut output,
+        "kvsd_queue_depth",
+        "gauge",
+        "Actions waiting for the store handler.",
+    );
+    let _ = writeln!(
+        output,
+        "kvsd_queue_depth {}",
+        QUEUE_DEPTH.load(Ordering::Relaxed)
+    );
+
+    write_header(
+        &mut output,
+        "kvsd_store_entries",
+        "gauge",
+        "Key value pairs in the store.",
+    );
+    let _ = writeln!(output, "kvsd_store_entries {}", backend.size());
+
+    write_header(
+        &mut output,
+        "kvsd_persistence_write_duration_seconds",
+        "histogram",
+        "Duration of persisting a queued action.",
+    );
+    PERSISTENCE_WRITE_DURATION.render("kvsd_persistence_write_duration_seconds", "", &mut output);
+
+    write_header(
+        &mut output,
+        "kvsd_encryption_duration_seconds",
+        "histogram",
+        "Duration of encrypting a value including the key derivation.",
+    );
+    ENCRYPTION_DURATION.render("kvsd_encryption_duration_seconds", "", &mut output);
+
+    write_header(
+        &mut output,
+        "kvsd_decryption_duration_seconds",
+        "histogram",
+        "Duration of decrypting a value including the key derivation.",
+    );
+    DECRYPTION_DURATION.render("kvsd_decryption_duration_seconds", "", &mut output);
+
+    output
+}
+
+fn write_header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
@@ -0,0 +1,301 @@
+/*
+*  kvsd rate limit module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Constants
+// Number of tracked clients after which idle clients are dropped
+const CLIENTS_MAX: usize = 10000;
+
+// Scopes of a rate limit
+pub const SCOPE_CLIENT: u8 = 0;
+pub const SCOPE_GLOBAL: u8 = 1;
+
+// Configured limits, a value of 0 disables the respective limit
+#[derive(Clone, Copy, Default)]
+pub struct RateLimits {
+    pub client_requests_per_second: u64,
+    pub client_bytes_per_second: u64,
+    pub global_requests_per_second: u64,
+    pub global_bytes_per_second: u64,
+}
+
+// Returned if a request exceeds a limit
+pub struct RateLimitExceeded {
+    pub scope: u8,
+    pub retry_after: Duration,
+}
+
+// Token bucket holding at most one second worth of tokens
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            capacity: rate as f64,
+            tokens: rate as f64,
+            rate: rate as f64,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    // Time until the given amount can be taken, None if it can be taken right away.
+    // Amounts bigger than the capacity only require a full bucket and leave a debt.
+    fn wait_time(&self, amount: f64) -> Option<Duration> {
+        let required = amount.min(self.capacity);
+        if self.tokens >= required {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            (required - self.tokens) / self.rate,
+        ))
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.tokens -= amount;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.capacity
+    }
+}
+
+// Request and byte buckets of one scope
+struct Buckets {
+    requests: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl Buckets {
+    fn new(requests_per_second: u64, bytes_per_second: u64, now: Instant) -> Buckets {
+        Buckets {
+            requests: match requests_per_second {
+                0 => None,
+                rate => Some(TokenBucket::new(rate, now)),
+            },
+            bytes: match bytes_per_second {
+                0 => None,
+                rate => Some(TokenBucket::new(rate, now)),
+            },
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(bucket) = self.requests.as_mut() {
+            bucket.refill(now);
+        }
+        if let Some(bucket) = self.bytes.as_mut() {
+            bucket.refill(now);
+        }
+    }
+
+    fn wait_time(&self, bytes: usize) -> Option<Duration> {
+        let requests_wait = self.requests.as_ref().and_then(|b| b.wait_time(1.0));
+        let bytes_wait = self.bytes.as_ref().and_then(|b| b.wait_time(bytes as f64));
+        match (requests_wait, bytes_wait) {
+            (Some(r), Some(b)) => Some(r.max(b)),
+            (r, b) => r.or(b),
+        }
+    }
+
+    fn take(&mut self, bytes: usize) {
+        if let Some(bucket) = self.requests.as_mut() {
+            bucket.take(1.0);
+        }
+        if let Some(bucket) = self.bytes.as_mut() {
+            bucket.take(bytes as f64);
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.requests.iter().all(|b| b.is_full()) && self.bytes.iter().all(|b| b.is_full())
+    }
+}
+
+// Rate limiter shared by all gRPC handlers
+pub struct RateLimiter {
+    limits: RateLimits,
+    global: Mutex<Buckets>,
+    clients: Mutex<HashMap<Option<IpAddr>, Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> RateLimiter {
+        RateLimiter {
+            limits,
+            global: Mutex::new(Buckets::new(
+                limits.global_requests_per_second,
+                limits.global_bytes_per_second,
+                Instant::now(),
+            )),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn client_limits_enabled(&self) -> bool {
+        self.limits.client_requests_per_second > 0 || self.limits.client_bytes_per_second > 0
+    }
+
+    // Account a request of the given size for a peer.
+    // Tokens are only taken if neither the client nor the global limit is exceeded.
+    pub fn check(&self, peer: Option<IpAddr>, bytes: usize) -> Result<(), RateLimitExceeded> {
+        self.check_at(peer, bytes, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        peer: Option<IpAddr>,
+        bytes: usize,
+        now: Instant,
+    ) -> Result<(), RateLimitExceeded> {
+        let mut clients = self.clients.lock().unwrap();
+        let mut global = self.global.lock().unwrap();
+
+        global.refill(now);
+        if self.client_limits_enabled() {
+            // Drop clients which did not send requests for a while
+            if clients.len() >= CLIENTS_MAX {
+                clients.retain(|_, buckets| {
+                    buckets.refill(now);
+                    !buckets.is_idle()
+                });
+            }
+            let limits = self.limits;
+            let client = clients.entry(peer).or_insert_with(|| {
+                Buckets::new(
+                    limits.client_requests_per_second,
+                    limits.client_bytes_per_second,
+                    now,
+                )
+            });
+            client.refill(now);
+            if let Some(retry_after) = client.wait_time(bytes) {
+                return Err(RateLimitExceeded {
+                    scope: SCOPE_CLIENT,
+                    retry_after,
+                });
+            }
+            if let Some(retry_after) = global.wait_time(bytes) {
+                return Err(RateLimitExceeded {
+                    scope: SCOPE_GLOBAL,
+                    retry_after,
+                });
+            }
+            client.take(bytes);
+        } else if let Some(retry_after) = global.wait_time(bytes) {
+            return Err(RateLimitExceeded {
+                scope: SCOPE_GLOBAL,
+                retry_after,
+            });
+        }
+        global.take(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn client(last_octet: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([127, 0, 0, last_octet]))
+    }
+
+    // Limiter and a start time after the creation of its global buckets
+    fn limiter(limits: RateLimits) -> (RateLimiter, Instant) {
+        let limiter = RateLimiter::new(limits);
+        (limiter, Instant::now())
+    }
+
+    fn millis(duration: Duration) -> u64 {
+        (duration.as_secs_f64() * 1000.0).round() as u64
+    }
+
+    #[test]
+    fn client_requests_refill_ok() {
+        let (limiter, start) = limiter(RateLimits {
+            client_requests_per_second: 2,
+            ..RateLimits::default()
+        });
+        assert_eq!(limiter.check_at(client(1), 0, start).is_ok(), true);
+        assert_eq!(limiter.check_at(client(1), 0, start).is_ok(), true);
+        let exceeded = limiter.check_at(client(1), 0, start).err().unwrap();
+        assert_eq!(exceeded.scope, SCOPE_CLIENT);
+        assert_eq!(millis(exceeded.retry_after), 500);
+        // Other clients have their own bucket
+        assert_eq!(limiter.check_at(client(2), 0, start).is_ok(), true);
+        // One request is refilled after half a second
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.check_at(client(1), 0, later).is_ok(), true);
+        assert_eq!(limiter.check_at(client(1), 0, later).is_err(), true);
+    }
+
+    #[test]
+    fn global_requests_refill_ok() {
+        let (limiter, start) = limiter(RateLimits {
+            client_requests_per_second: 10,
+            global_requests_per_second: 1,
+            ..RateLimits::default()
+        });
+        assert_eq!(limiter.check_at(client(1), 0, start).is_ok(), true);
+        let exceeded = limiter.check_at(client(2), 0, start).err().unwrap();
+        assert_eq!(exceeded.scope, SCOPE_GLOBAL);
+        assert_eq!(millis(exceeded.retry_after), 1000);
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check_at(client(2), 0, later).is_ok(), true);
+    }
+
+    #[test]
+    fn bytes_retry_after_ok() {
+        let (limiter, start) = limiter(RateLimits {
+            client_bytes_per_second: 100,
+            ..RateLimits::default()
+        });
+        assert_eq!(limiter.check_at(client(1), 60, start).is_ok(), true);
+        // 40 bytes are left, 20 more are refilled within 200 ms
+        let exceeded = limiter.check_at(client(1), 60, start).err().unwrap();
+        assert_eq!(millis(exceeded.retry_after), 200);
+        let later = start + Duration::from_millis(250);
+        assert_eq!(limiter.check_at(client(1), 60, later).is_ok(), true);
+    }
+
+    #[test]
+    fn request_larger_than_capacity_ok() {
+        let (limiter, start) = limiter(RateLimits {
+            global_bytes_per_second: 100,
+            ..RateLimits::default()
+        });
+        // A full bucket admits a larger request and leaves a debt of 50 bytes
+        assert_eq!(limiter.check_at(None, 150, start).is_ok(), true);
+        let exceeded = limiter.check_at(None, 10, start).err().unwrap();
+        assert_eq!(exceeded.scope, SCOPE_GLOBAL);
+        assert_eq!(millis(exceeded.retry_after), 600);
+    }
+
+    #[test]
+    fn disabled_limits_ok() {
+        let (limiter, start) = limiter(RateLimits::default());
+        for _ in 0..1000 {
+            assert_eq!(limiter.check_at(client(1), 1_000_000, start).is_ok(), true);
+        }
+    }
+}
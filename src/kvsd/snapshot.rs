@@ -0,0 +1,329 @@
+/*
+*  kvsd snapshot Module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// Tokio
+use tokio::runtime::Builder;
+use tokio::sync::mpsc as queue;
+
+// kvs modules
+use crate::config::StoreConfig;
+use crate::grpc::kvs_api::KeyValuePair;
+use crate::metrics;
+use crate::store::backend::{create_backend, lock_store};
+use crate::store::store_actions::{
+    QueueAction, SnapshotBatch, ACTION_SNAPSHOT, SNAPSHOT_BATCHES_BUFFERED,
+};
+use crate::trace::RequestContext;
+use utils::backup::{read_archive, ArchiveWriter, BackupSecret, Manifest};
+use utils::filesystem_wrapper::{sync_directory, sync_file};
+use utils::{log_error, log_info, log_warn};
+
+// Constants
+// Scheduled snapshots are named kvsd-snapshot-<unix time>.kvsbak
+const SNAPSHOT_PREFIX: &str = "kvsd-snapshot-";
+const SNAPSHOT_SUFFIX: &str = ".kvsbak";
+
+// Copies of snapshot requests taken by this process, numbers the temporary archives
+static SNAPSHOT_COPIES: AtomicU64 = AtomicU64::new(0);
+
+// Point-in-time copy of the store taken for a snapshot request. The batches are written to an
+// archive in the temporary directory as fast as the store handler takes them, so the store
+// handler does not wait for the requester. The archive is encrypted with a random key held in
+// RAM and removed when the copy is dropped.
+pub struct SnapshotCopy {
+    path: String,
+    secret: BackupSecret,
+    pub manifest: Manifest,
+}
+
+impl SnapshotCopy {
+    // Write the batches of a snapshot while they are received
+    pub fn write(
+        backend: &str,
+        first: SnapshotBatch,
+        next: &mut dyn FnMut() -> Option<Result<SnapshotBatch, String>>,
+    ) -> Result<SnapshotCopy, String> {
+        let path = env::temp_dir()
+            .join(format!(
+                "kvsd-snapshot-{}-{}.tmp",
+                std::process::id(),
+                SNAPSHOT_COPIES.fetch_add(1, Ordering::Relaxed)
+            ))
+            .to_string_lossy()
+            .to_string();
+        // A file planted at the path is not followed or overwritten
+        let file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Could not create \"{}\": {}", path, e)),
+        };
+        let secret = BackupSecret::random_key();
+        match write_archive(file, &secret, backend, first, next, unix_time()) {
+            Ok(manifest) => Ok(SnapshotCopy {
+                path,
+                secret,
+                manifest,
+            }),
+            Err(e) => {
+                let _ = fs::remove_file(&path);
+                Err(e)
+            }
+        }
+    }
+
+    // Pass the entries to send one chunk at a time, fails if the archive was modified
+    pub fn read(
+        &self,
+        send: &mut dyn FnMut(Vec<(String, String)>) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let manifest = read_archive(open_archive(&self.path)?, &self.secret, send)?;
+        if manifest != self.manifest {
+            return Err("Snapshot copy changed while it was read.".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SnapshotCopy {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log_warn!("Could not remove snapshot copy \"{}\": {}", self.path, e);
+        }
+    }
+}
+
+// Take a snapshot every interval seconds and keep the latest retention snapshots in path.
+// The snapshots are queued like changes, so they see all changes persisted before.
+pub fn start_scheduled_snapshots(
+    path: String,
+    interval: u64,
+    retention: usize,
+    secret: BackupSecret,
+    backend: &'static str,
+    send_queue: queue::Sender<QueueAction>,
+) {
+    thread::spawn(move || {
+        let mut rt = Builder::new()
+            .basic_scheduler()
+            .build()
+            .expect("failed to obtain a new RunTime object");
+        let mut send_queue = send_queue;
+        loop {
+            thread::sleep(Duration::from_secs(interval));
+            let (reply_tx, mut reply_rx) = queue::channel(SNAPSHOT_BATCHES_BUFFERED);
+            let action = QueueAction {
+                kv: KeyValuePair {
+                    key: "".to_string(),
+                    value: "".to_string(),
+                },
+                action: ACTION_SNAPSHOT,
+                context: RequestContext::new(None),
+                queued: Instant::now(),
+                snapshot: Some(reply_tx),
+            };
+            // The queue and the reply are dropped once the store handler stopped
+            metrics::queued();
+            if rt.block_on(send_queue.send(action)).is_err() {
+                metrics::dequeued();
+                break;
+            }
+            let first = match rt.block_on(reply_rx.recv()) {
+                Some(Ok(batch)) => batch,
+                Some(Err(e)) => {
+                    log_error!("Could not take scheduled snapshot: {}", e);
+                    continue;
+                }
+                None => break,
+            };
+            let mut next = || rt.block_on(reply_rx.recv());
+            match write_snapshot(&path, &secret, backend, first, &mut next) {
+                Ok((file, manifest)) => log_info!(
+                    "Wrote snapshot of {} key value pairs to \"{}\".",
+                    manifest.entries,
+                    file
+                ),
+                Err(e) => log_error!("Could not write scheduled snapshot: {}", e),
+            }
+            prune_snapshots(&path, retention);
+        }
+    });
+}
+
+// Write the batches of a snapshot as archive into the snapshot directory while they are
+// received, returns the file and the manifest
+fn write_snapshot(
+    path: &str,
+    secret: &BackupSecret,
+    backend: &str,
+    first: SnapshotBatch,
+    next: &mut dyn FnMut() -> Option<Result<SnapshotBatch, String>>,
+) -> Result<(String, Manifest), String> {
+    let created = unix_time();
+    let file_path = format!("{}/{}{}{}", path, SNAPSHOT_PREFIX, created, SNAPSHOT_SUFFIX);
+    let temp_path = format!("{}.tmp", file_path);
+    if let Err(e) = fs::create_dir_all(path) {
+        return Err(format!("Could not create snapshot directory: {}", e));
+    }
+    let file = match File::create(&temp_path) {
+        Ok(file) => file,
+        Err(e) => return Err(format!("Could not create \"{}\": {}", temp_path, e)),
+    };
+    let result = write_archive(file, secret, backend, first, next, created).and_then(|manifest| {
+        match sync_file(temp_path.clone()).and_then(|()| fs::rename(&temp_path, &file_path)) {
+            Ok(()) => Ok(manifest),
+            Err(e) => Err(format!("Could not write \"{}\": {}", file_path, e)),
+        }
+    });
+    match result {
+        Ok(manifest) => {
+            // Persist the rename
+            let _ = sync_directory(path.to_string());
+            Ok((file_path, manifest))
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+fn write_archive(
+    file: File,
+    secret: &BackupSecret,
+    backend: &str,
+    first: SnapshotBatch,
+    next: &mut dyn FnMut() -> Option<Result<SnapshotBatch, String>>,
+    created: u64,
+) -> Result<Manifest, String> {
+    let mut writer = ArchiveWriter::new(BufWriter::new(file), secret)?;
+    let total_entries = first.total_entries;
+    let mut batch = Some(Ok(first));
+    while let Some(received) = batch {
+        let entries = match received {
+            Ok(received) => received.entries,
+            Err(e) => return Err(format!("Could not take snapshot: {}", e)),
+        };
+        for (key, value) in entries {
+            writer.add(key, value)?;
+        }
+        batch = next();
+    }
+    let manifest = writer.finish(backend, created)?;
+    // The batches end early if the store handler stopped
+    if manifest.entries != total_entries {
+        return Err("Snapshot incomplete, the store handler stopped sending entries.".to_string());
+    }
+    Ok(manifest)
+}
+
+// Delete all but the latest retention snapshots and leftovers of interrupted snapshots
+fn prune_snapshots(path: &str, retention: usize) {
+    let dir_entries = match fs::read_dir(path) {
+        Ok(dir_entries) => dir_entries,
+        Err(e) => return log_warn!("Could not read snapshot directory \"{}\": {}", path, e),
+    };
+    let mut snapshots: Vec<(u64, String)> = Vec::new();
+    let mut stale: Vec<String> = Vec::new();
+    for entry in dir_entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(SNAPSHOT_PREFIX) {
+            continue;
+        }
+        let created = name[SNAPSHOT_PREFIX.len()..].trim_end_matches(SNAPSHOT_SUFFIX);
+        if name.ends_with(SNAPSHOT_SUFFIX) {
+            if let Ok(created) = created.parse::<u64>() {
+                snapshots.push((created, name));
+            }
+        } else if name.ends_with(&format!("{}.tmp", SNAPSHOT_SUFFIX)) {
+            stale.push(name);
+        }
+    }
+    snapshots.sort();
+    let excess = snapshots.len().saturating_sub(retention);
+    let removed = snapshots
+        .into_iter()
+        .take(excess)
+        .map(|(_created, name)| name);
+    for name in removed.chain(stale) {
+        let file_path = format!("{}/{}", path, name);
+        if let Err(e) = fs::remove_file(&file_path) {
+            log_warn!("Could not remove snapshot \"{}\": {}", file_path, e);
+        }
+    }
+}
+
+// Restore an archive into an empty store at path while kvsd is stopped.
+// The archive is verified completely before the store is written, then it is read
+// a second time and restored one chunk at a time. The store is locked like by a running kvsd.
+pub fn restore(
+    config: &StoreConfig,
+    path: &str,
+    archive: &str,
+    secret: &BackupSecret,
+) -> Result<Manifest, String> {
+    if config.backend == "memory" {
+        return Err("The memory backend is not persisted and can not be restored.".to_string());
+    }
+    let manifest = read_archive(open_archive(archive)?, secret, &mut |_chunk| Ok(()))?;
+    if let Err(e) = fs::create_dir_all(path) {
+        return Err(format!("Could not create \"{}\": {}", path, e));
+    }
+    // Held until the store is closed, a running kvsd holds it as well
+    let _lock = lock_store(path)?;
+    let store = create_backend(&StoreConfig {
+        path: path.to_string(),
+        ..config.clone()
+    })?;
+    store.load()?;
+    if store.size() != 0 {
+        return Err(format!(
+            "Store at \"{}\" contains {} entries, only empty stores can be restored.",
+            path,
+            store.size()
+        ));
+    }
+    let result = open_archive(archive)
+        .and_then(|reader| read_archive(reader, secret, &mut |chunk| store.put_all(chunk)))
+        .and_then(|restored| {
+            if restored != manifest {
+                return Err(format!(
+                    "Archive \"{}\" changed while it was restored.",
+                    archive
+                ));
+            }
+            store.flush()
+        });
+    store.close();
+    if let Err(e) = result {
+        return Err(format!(
+            "{} The store at \"{}\" is partially restored, remove it before restoring again.",
+            e, path
+        ));
+    }
+    Ok(manifest)
+}
+
+// Seconds since the Unix epoch
+fn unix_time() -> u64 {
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_e) => 0,
+    }
+}
+
+fn open_archive(archive: &str) -> Result<BufReader<File>, String> {
+    match File::open(archive) {
+        Ok(file) => Ok(BufReader::new(file)),
+        Err(e) => Err(format!("Could not open \"{}\": {}", archive, e)),
+    }
+}
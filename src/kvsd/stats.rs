@@ -0,0 +1,42 @@
+/*
+*  kvsd stats module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// kvs modules
+use utils::log_info;
+
+// Handled requests per RPC
+pub static REQUESTS_STORE: AtomicU64 = AtomicU64::new(0);
+pub static REQUESTS_GET: AtomicU64 = AtomicU64::new(0);
+pub static REQUESTS_DELETE: AtomicU64 = AtomicU64::new(0);
+pub static REQUESTS_SNAPSHOT: AtomicU64 = AtomicU64::new(0);
+pub static REQUESTS_LIMITS: AtomicU64 = AtomicU64::new(0);
+// Requests rejected by the rate limiter
+pub static RATE_LIMITED_CLIENT: AtomicU64 = AtomicU64::new(0);
+pub static RATE_LIMITED_GLOBAL: AtomicU64 = AtomicU64::new(0);
+// Requests rejected because the queue of the store handler was full
+pub static QUEUE_FULL: AtomicU64 = AtomicU64::new(0);
+
+pub fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+// Log the current stats of the daemon
+pub fn log_stats() {
+    log_info!(
+        "Stats.";
+        store_requests = REQUESTS_STORE.load(Ordering::Relaxed),
+        get_requests = REQUESTS_GET.load(Ordering::Relaxed),
+        delete_requests = REQUESTS_DELETE.load(Ordering::Relaxed),
+        snapshot_requests = REQUESTS_SNAPSHOT.load(Ordering::Relaxed),
+        limits_requests = REQUESTS_LIMITS.load(Ordering::Relaxed),
+        rate_limited_client = RATE_LIMITED_CLIENT.load(Ordering::Relaxed),
+        rate_limited_global = RATE_LIMITED_GLOBAL.load(Ordering::Relaxed),
+        queue_full = QUEUE_FULL.load(Ordering::Relaxed)
+    );
+}
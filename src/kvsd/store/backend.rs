@@ -0,0 +1,321 @@
+/*
+*  kvsd storage backend Module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::fs::{File, OpenOptions, TryLockError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Tokio
+use tokio::sync::mpsc::error::TrySendError;
+
+// kvs modules
+use crate::config::StoreConfig;
+use crate::store::file_store::FileStore;
+use crate::store::json_store::JsonStore;
+use crate::store::log_store::LogStore;
+use crate::store::memory_store::MemoryStore;
+use crate::store::sled_store::SledStore;
+use crate::store::sqlite_store::SqliteStore;
+use crate::store::store_actions::{
+    QueueAction, SnapshotBatch, SnapshotReply, ACTION_DELETE, ACTION_SNAPSHOT, ACTION_STORE,
+    SNAPSHOT_BATCH_SIZE,
+};
+use crate::systemd;
+use utils::{log_debug, log_error};
+
+// Constants
+// Lock file in the store directory, held by kvsd and by migrations
+pub const LOCK_FILE: &str = "kvsd.lock";
+// Time the store handler waits for the requester of a snapshot to take the next batch
+const SNAPSHOT_SEND_TIMEOUT: Duration = Duration::from_secs(30);
+const SNAPSHOT_SEND_INTERVAL: Duration = Duration::from_millis(10);
+
+// Interface of a storage backend.
+// Reading is possible from any thread, modifications are only done by the store handler
+// in the order of the queue.
+pub trait StorageBackend: Send + Sync {
+    // Name of the backend as used in the configuration
+    fn name(&self) -> &'static str;
+    // Load the persisted store on start-up, returns a message describing the result
+    fn load(&self) -> Result<String, String>;
+    // Retrieve the decrypted value of a key
+    fn get(&self, key: &str) -> Result<String, String>;
+    // Store a value, an existing value of the key is replaced
+    fn put(&self, key: String, value: String) -> Result<(), String>;
+    // Store several values and persist them at once, e.g. when importing a store
+    fn put_all(&self, entries: Vec<(String, String)>) -> Result<(), String> {
+        for (key, value) in entries {
+            self.put(key, value)?;
+        }
+        Ok(())
+    }
+    // Delete a key and its value, fails with "Key not found!" if the key is not stored
+    fn delete(&self, key: &str) -> Result<(), String>;
+    // Check existence of key
+    fn exists(&self, key: &str) -> bool;
+    // All stored keys starting with prefix
+    fn list_prefix(&self, prefix: &str) -> Vec<String>;
+    // All stored keys
+    fn list(&self) -> Vec<String> {
+        self.list_prefix("")
+    }
+    // Number of entries in the store
+    fn size(&self) -> usize {
+        self.list().len()
+    }
+    // Bytes of the keys and encrypted values held in RAM, 0 if the values are not held in RAM
+    fn memory_usage(&self) -> usize {
+        0
+    }
+    // Pass all keys with their decrypted values to send in batches of about SNAPSHOT_BATCH_SIZE bytes,
+    // called by the store handler so that no change is persisted while the snapshot is taken
+    fn snapshot(
+        &self,
+        send: &mut dyn FnMut(SnapshotBatch) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let keys = self.list();
+        let total_entries = keys.len() as u64;
+        let mut entries = Vec::new();
+        let mut batch_size = 0;
+        for key in keys {
+            let value = self.get(&key)?;
+            batch_size += key.len() + value.len();
+            entries.push((key, value));
+            if batch_size >= SNAPSHOT_BATCH_SIZE {
+                send(SnapshotBatch {
+                    total_entries,
+                    entries: std::mem::take(&mut entries),
+                })?;
+                batch_size = 0;
+            }
+        }
+        if !entries.is_empty() || total_entries == 0 {
+            send(SnapshotBatch {
+                total_entries,
+                entries,
+            })?;
+        }
+        Ok(())
+    }
+    // Persist the store and flush it to the storage device
+    fn flush(&self) -> Result<(), String>;
+    // Release the store on shutdown, called after the last flush
+    fn close(&self) {}
+    // Names of all files of the store in its directory
+    fn files(&self) -> Vec<String>;
+    // Whether all values are held in RAM, the number of entries
+    // and the length of the values are limited then
+    fn holds_values_in_memory(&self) -> bool;
+}
+
+// Create a store according to the configuration, each store is independent of
+// all others as long as they use different paths
+pub fn create_backend(config: &StoreConfig) -> Result<Arc<dyn StorageBackend>, String> {
+    let path = config.path.clone();
+    match config.backend.as_str() {
+        "json" => Ok(Arc::new(JsonStore::new(path, config.read_only))),
+        "file" => Ok(Arc::new(FileStore::new(
+            path,
+            config.dedup,
+            config.verify,
+            config.read_only,
+        ))),
+        "log" => {
+            let store = Arc::new(LogStore::new(path, config.read_only));
+            if !config.read_only {
+                LogStore::start_compaction(&store);
+            }
+            Ok(store)
+        }
+        // sled has no read-only mode, opening the database recovers its own log
+        "sled" => Ok(Arc::new(SledStore::new(path))),
+        "sqlite" => Ok(Arc::new(SqliteStore::new(path, config.read_only))),
+        "memory" => Ok(Arc::new(MemoryStore::new(config.wipe_on_shutdown))),
+        _ => Err(format!("Backend \"{}\" is not supported.", config.backend)),
+    }
+}
+
+// Lock the store at path exclusively, the lock is released when the file is dropped
+pub fn lock_store(path: &str) -> Result<File, String> {
+    let lock_path = format!("{}/{}", path.trim_end_matches('/'), LOCK_FILE);
+    let file = match OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&lock_path)
+    {
+        Ok(file) => file,
+        Err(e) => return Err(format!("Could not open \"{}\": {}", lock_path, e)),
+    };
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(format!(
+            "Store at \"{}\" is in use by another kvsd process.",
+            path
+        )),
+        Err(TryLockError::Error(e)) => Err(format!("Could not lock \"{}\": {}", lock_path, e)),
+    }
+}
+
+// Send a batch of a snapshot, waits up to SNAPSHOT_SEND_TIMEOUT for the requester to
+// take the buffered batches so that a stalled requester does not hold up all changes.
+// The requesters write the batches to disk, the heartbeat continues while a large store is sent.
+fn send_snapshot_batch(
+    reply: &mut SnapshotReply,
+    batch: Result<SnapshotBatch, String>,
+) -> Result<(), String> {
+    let deadline = Instant::now() + SNAPSHOT_SEND_TIMEOUT;
+    let mut batch = batch;
+    loop {
+        systemd::store_handler_heartbeat();
+        batch = match reply.try_send(batch) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Closed(_batch)) => {
+                return Err("Snapshot was cancelled by the requester.".to_string())
+            }
+            Err(TrySendError::Full(batch)) => batch,
+        };
+        if Instant::now() >= deadline {
+            return Err("Snapshot timed out, the requester does not take the entries.".to_string());
+        }
+        thread::sleep(SNAPSHOT_SEND_INTERVAL);
+    }
+}
+
+// Handle a QueueAction
+pub fn handle_action(backend: &dyn StorageBackend, action: QueueAction) {
+    match action.action {
+        ACTION_STORE => {
+            log_debug!(
+                "Storing key.";
+                key = action.kv.key,
+                request_id = action.context.request_id
+            );
+            let key = action.kv.key.clone();
+            if let Err(e) = backend.put(action.kv.key, action.kv.value) {
+                log_error!(
+                    "Could not store key: {}", e;
+                    key = key,
+                    request_id = action.context.request_id
+                );
+            }
+        }
+        ACTION_DELETE => {
+            log_debug!(
+                "Deleting key.";
+                key = action.kv.key,
+                request_id = action.context.request_id
+            );
+            if let Err(e) = backend.delete(&action.kv.key) {
+                log_error!(
+                    "Could not delete key: {}", e;
+                    key = action.kv.key,
+                    request_id = action.context.request_id
+                );
+            }
+        }
+        ACTION_SNAPSHOT => {
+            log_debug!(
+                "Taking snapshot.";
+                request_id = action.context.request_id
+            );
+            let mut reply = match action.snapshot {
+                Some(reply) => reply,
+                None => return,
+            };
+            let result = backend.snapshot(&mut |batch| send_snapshot_batch(&mut reply, Ok(batch)));
+            if let Err(e) = result {
+                log_error!(
+                    "Could not take snapshot: {}", e;
+                    request_id = action.context.request_id
+                );
+                // The requester may have given up waiting
+                let _ = send_snapshot_batch(&mut reply, Err(e));
+            }
+        }
+        _ => {
+            log_error!("No matching action available.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc::kvs_api::KeyValuePair;
+    use crate::trace::RequestContext;
+    use tokio::sync::mpsc;
+    use utils::crypto::json_encrypted_len;
+
+    fn snapshot_action(reply: SnapshotReply) -> QueueAction {
+        QueueAction {
+            kv: KeyValuePair {
+                key: "".to_string(),
+                value: "".to_string(),
+            },
+            action: ACTION_SNAPSHOT,
+            context: RequestContext::new(None),
+            queued: Instant::now(),
+            snapshot: Some(reply),
+        }
+    }
+
+    #[test]
+    fn snapshot_batches_ok() {
+        let backend = MemoryStore::new(false);
+        for i in 0..3 {
+            backend
+                .put(format!("key_{}", i), "a".repeat(SNAPSHOT_BATCH_SIZE))
+                .unwrap();
+        }
+        let (reply, mut batches) = mpsc::channel(4);
+        handle_action(&backend, snapshot_action(reply));
+        let mut sizes = Vec::new();
+        while let Ok(batch) = batches.try_recv() {
+            let batch = batch.unwrap();
+            assert_eq!(batch.total_entries, 3);
+            sizes.push(batch.entries.len());
+        }
+        assert_eq!(sizes, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn snapshot_empty_ok() {
+        let backend = MemoryStore::new(false);
+        let (reply, mut batches) = mpsc::channel(4);
+        handle_action(&backend, snapshot_action(reply));
+        let batch = batches.try_recv().ok().unwrap().unwrap();
+        assert_eq!(batch.total_entries, 0);
+        assert_eq!(batch.entries.is_empty(), true);
+        assert_eq!(batches.try_recv().is_err(), true);
+    }
+
+    #[test]
+    fn snapshot_cancelled_ok() {
+        let backend = MemoryStore::new(false);
+        backend.put("key".to_string(), "value".to_string()).unwrap();
+        let (reply, batches) = mpsc::channel(4);
+        drop(batches);
+        let start = Instant::now();
+        handle_action(&backend, snapshot_action(reply));
+        assert_eq!(start.elapsed() < SNAPSHOT_SEND_TIMEOUT, true);
+    }
+
+    #[test]
+    fn memory_usage_ok() {
+        let backend = MemoryStore::new(false);
+        backend.put("first".to_string(), "a".repeat(100)).unwrap();
+        backend.put("second".to_string(), "b".repeat(10)).unwrap();
+        backend.put("first".to_string(), "c".repeat(1000)).unwrap();
+        backend.delete("second").unwrap();
+        let expected = "first".len() + json_encrypted_len(1000);
+        assert_eq!(backend.memory_usage(), expected);
+        backend.delete("first").unwrap();
+        assert_eq!(backend.memory_usage(), 0);
+    }
+}
@@ -5,222 +5,758 @@
 */
 
 // Rust Standard Library
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
-// lazy static
-use lazy_static::lazy_static;
-
 // json
 use serde::{Deserialize, Serialize};
 
 // kvs modules
-use crate::store::store_actions::{QueueAction, ACTION_DELETE, ACTION_STORE};
+use crate::store::backend::StorageBackend;
 use utils::crypto::{
-    file_decrypt, file_encrypt, generate_derivation_value, generate_initialization_vector,
-    json_decrypt, json_encrypt,
+    file_encrypt, file_try_decrypt, generate_derivation_value, generate_initialization_vector,
+    json_encrypt, json_try_decrypt, keyed_sha3_256_hex, sha3_256_hex, DV_LEN,
+};
+use utils::filesystem_wrapper::{
+    delete_file, previous_generation_path, read_file_to_string, sync_directory,
+    write_string_to_file, write_string_to_file_keep_previous,
 };
-use utils::filesystem_wrapper::{delete_file, read_file_to_string, write_string_to_file};
-use utils::log::{log, LOG_STDERR, LOG_STDOUT};
+use utils::{log_debug, log_error, log_info, log_warn};
+
+// Constants
+const META_DATA_FILE: &str = "kvsd-meta-data.json";
+// Directory for entries whose value file fails authentication
+const QUARANTINE_DIR: &str = "quarantine";
+// Directory of the value files, they are fanned out into two levels of subdirectories
+const VALUES_DIR: &str = "values";
+// Encrypted key of the hash identifying identical values
+const DEDUP_KEY_FILE: &str = "kvsd-dedup-key.json";
 
 // Value File Meta Data
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct ValueMetaData {
     filename: String,
     derivation_value: String,
     initialization_vector: String,
+    // Keyed hash of the value, set if the value file is shared with entries of the same value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_hash: Option<String>,
+}
+
+// Entry moved to the quarantine directory, kept encrypted for manual inspection
+#[derive(Serialize)]
+struct QuarantinedEntry<'a> {
+    key: &'a str,
+    meta_data: &'a ValueMetaData,
+    // Further entries sharing the value file
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    shared_with: &'a [String],
 }
 
-// Map Struct containing the HashMap of all entries
-#[derive(Deserialize, Serialize)]
-struct KeyValueMap {
-    elements: HashMap<String, ValueMetaData>,
+// State of a value file checked by the recovery
+#[derive(Clone, Copy)]
+enum ValueFileState {
+    // Present, and authentic if the value files are verified
+    Valid,
+    Missing,
+    Failed,
 }
 
-// Static HashMap containing all elements
-lazy_static! {
-    static ref STORE: RwLock<KeyValueMap> = RwLock::new(KeyValueMap {
-        elements: HashMap::new(),
-    });
+// Reference counts of the value files, a value file is deleted once no entry references it
+#[derive(Default)]
+struct SharedFiles {
+    // Number of entries referencing each value file
+    references: HashMap<String, usize>,
+    // Meta data of the value files by keyed hash of their value
+    by_hash: HashMap<String, ValueMetaData>,
 }
 
-// Handle a QueueAction
-pub fn handle_action(action: QueueAction, path: String) {
-    match action.action {
-        ACTION_STORE => {
-            log(format!("Storing key \"{}\".", action.kv.key), LOG_STDOUT);
-            store_action(action, path);
+impl SharedFiles {
+    // Count the references of all entries
+    fn from_elements(elements: &HashMap<String, ValueMetaData>) -> SharedFiles {
+        let mut shared_files = SharedFiles::default();
+        for value_meta_data in elements.values() {
+            shared_files.add_reference(value_meta_data);
         }
-        ACTION_DELETE => {
-            log(format!("Deleting key \"{}\".", action.kv.key), LOG_STDOUT);
-            delete_action(action, path);
+        shared_files
+    }
+
+    fn add_reference(&mut self, value_meta_data: &ValueMetaData) {
+        *self
+            .references
+            .entry(value_meta_data.filename.clone())
+            .or_insert(0) += 1;
+        if let Some(content_hash) = &value_meta_data.content_hash {
+            self.by_hash
+                .entry(content_hash.clone())
+                .or_insert_with(|| value_meta_data.clone());
+        }
+    }
+
+    // Remove the reference of an entry, returns the value file once it is no longer referenced
+    fn remove_reference(&mut self, value_meta_data: &ValueMetaData) -> Option<String> {
+        let filename = &value_meta_data.filename;
+        let references = self.references.get_mut(filename)?;
+        *references -= 1;
+        if *references > 0 {
+            return None;
         }
-        _ => {
-            log("No matching action available.".to_string(), LOG_STDERR);
+        self.references.remove(filename);
+        if let Some(content_hash) = &value_meta_data.content_hash {
+            if let Some(shared) = self.by_hash.get(content_hash) {
+                if &shared.filename == filename {
+                    self.by_hash.remove(content_hash);
+                }
+            }
         }
+        Some(filename.clone())
     }
 }
 
-fn store_action(action: QueueAction, path: String) {
-    // generate new derivation value
-    let derivation_value = generate_derivation_value();
-    // generate new iv
-    let iv = generate_initialization_vector();
-    // base64 encode IV for storage in JSON
-    let base64_iv = base64::encode(iv);
-    // encrypt value
-    let ciphertext = file_encrypt(action.kv.value, derivation_value.clone(), base64_iv.clone());
-    // if entry does not exist in hashmap
-    if !STORE.read().unwrap().elements.contains_key(&action.kv.key) {
-        // generate filename
-        let filename = generate_derivation_value();
-        // write file to filesystem
-        write_string_to_file(format!("{}/{}", path, filename), ciphertext);
+// File backend, each value is stored encrypted in a separate file,
+// the meta data of all values is held in RAM and persisted to an encrypted JSON file.
+// With dedup entries of identical values share a single value file.
+pub struct FileStore {
+    path: String,
+    // All entries by key
+    elements: RwLock<HashMap<String, ValueMetaData>>,
+    // Share the value file of identical values
+    dedup: bool,
+    // Key of the hash identifying identical values, loaded on start-up if dedup is enabled
+    dedup_key: RwLock<String>,
+    // Authenticate all value files on start-up instead of only when they are read
+    verify: bool,
+    // Reference counts of the value files
+    shared_files: RwLock<SharedFiles>,
+    // The store is checked but not recovered or written, e.g. for the source of a migration
+    read_only: bool,
+}
+
+impl FileStore {
+    pub fn new(path: String, dedup: bool, verify: bool, read_only: bool) -> FileStore {
+        FileStore {
+            path,
+            elements: RwLock::new(HashMap::new()),
+            dedup,
+            dedup_key: RwLock::new(String::new()),
+            verify,
+            shared_files: RwLock::new(SharedFiles::default()),
+            read_only,
+        }
+    }
+
+    // Path of a value file in its subdirectory
+    fn value_file_path(&self, filename: &str) -> String {
+        format!("{}/{}/{}", self.path, value_dir(filename), filename)
+    }
+
+    // Create the subdirectory of a value file if it does not exist.
+    // New directories are synced to their parents so that the value file is found after a power cut.
+    fn create_value_dir(&self, filename: &str) -> Result<(), String> {
+        let dir = value_dir(filename);
+        if Path::new(&format!("{}/{}", self.path, dir)).is_dir() {
+            return Ok(());
+        }
+        if let Err(e) = fs::create_dir_all(format!("{}/{}", self.path, dir)) {
+            return Err(format!("Could not create value directory: {}", e));
+        }
+        let mut parent = self.path.clone();
+        for component in dir.split('/') {
+            if let Err(e) = sync_directory(parent.clone()) {
+                return Err(format!("Could not sync value directory: {}", e));
+            }
+            parent = format!("{}/{}", parent, component);
+        }
+        Ok(())
+    }
+
+    // Store a value and update its meta data in the hashmap, with dedup the value file
+    // of an identical value is shared. Returns the file of the replaced value if it is
+    // no longer referenced, it is deleted once the meta data is saved.
+    fn write_value(&self, key: String, value: String) -> Result<Option<String>, String> {
+        let content_hash = if self.dedup {
+            Some(keyed_sha3_256_hex(
+                &self.dedup_key.read().unwrap(),
+                value.as_bytes(),
+            ))
+        } else {
+            None
+        };
+        let shared = match &content_hash {
+            Some(content_hash) => self
+                .shared_files
+                .read()
+                .unwrap()
+                .by_hash
+                .get(content_hash)
+                .cloned(),
+            None => None,
+        };
+        let value_meta_data = match shared {
+            Some(value_meta_data) => {
+                log_debug!("Sharing the value file of an identical value."; key = key);
+                value_meta_data
+            }
+            None => self.write_value_file(value, content_hash)?,
+        };
         // store meta data in hashmap
-        STORE.write().unwrap().elements.insert(
-            action.kv.key,
-            ValueMetaData {
-                filename,
-                derivation_value,
-                initialization_vector: base64_iv,
-            },
-        );
-    } else {
-        // retrieve filename from hashmap
-        let filename = match STORE.read().unwrap().elements.get(action.kv.key.as_str()) {
-            Some(value) => value.filename.clone(),
-            None => "".to_string(),
+        let mut shared_files = self.shared_files.write().unwrap();
+        shared_files.add_reference(&value_meta_data);
+        let replaced = self.elements.write().unwrap().insert(key, value_meta_data);
+        Ok(replaced.and_then(|value_meta_data| shared_files.remove_reference(&value_meta_data)))
+    }
+
+    // Encrypt a value and write it to a new file
+    fn write_value_file(
+        &self,
+        value: String,
+        content_hash: Option<String>,
+    ) -> Result<ValueMetaData, String> {
+        // generate new derivation value
+        let derivation_value = generate_derivation_value();
+        // generate new iv
+        let iv = generate_initialization_vector();
+        // base64 encode IV for storage in JSON
+        let base64_iv = base64::encode(iv);
+        // encrypt value
+        let ciphertext = file_encrypt(value, derivation_value.clone(), base64_iv.clone());
+        // generate a filename, the file of an existing entry is kept until the meta data is saved
+        let filename = generate_derivation_value();
+        // store value in file
+        self.create_value_dir(&filename)?;
+        if let Err(e) = write_string_to_file(self.value_file_path(&filename), ciphertext) {
+            return Err(format!("Could not write file of key: {}", e));
+        }
+        Ok(ValueMetaData {
+            filename,
+            derivation_value,
+            initialization_vector: base64_iv,
+            content_hash,
+        })
+    }
+
+    // Delete value files which are no longer referenced by the saved meta data,
+    // files that can not be deleted are removed by the recovery on the next start-up
+    fn delete_value_files(&self, filenames: Vec<String>) {
+        for filename in filenames {
+            if let Err(e) = fs::remove_file(self.value_file_path(&filename)) {
+                log_warn!("Could not delete replaced value file: {}", e);
+            }
+        }
+    }
+
+    // Serialize the meta data, encrypt it and store it
+    fn save_meta_data_to_file(&self) -> Result<(), String> {
+        // serialize HashMap
+        let json_string = match serde_json::to_string(&*self.elements.read().unwrap()) {
+            Ok(j) => j,
+            Err(_e) => return Err("Error serializing hashmap.".to_string()),
         };
-        if filename.is_empty() {
-            log(format!("Could not find key \"{}\" that should already be stored. Ommiting store action.",
-            action.kv.key), LOG_STDERR);
-            return;
+        // encrypt json
+        let encrypted_json = json_encrypt(json_string);
+        // write to file, the previous generation is kept
+        match write_string_to_file_keep_previous(
+            format!("{}/{}", self.path, META_DATA_FILE),
+            encrypted_json,
+        ) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Could not write meta-data file: {}", e)),
         }
-        // store value in file with filename from hashmap
-        write_string_to_file(format!("{}/{}", path, filename), ciphertext);
-        // store meta data in hashmap
-        STORE.write().unwrap().elements.insert(
-            action.kv.key,
-            ValueMetaData {
-                filename,
-                derivation_value,
-                initialization_vector: base64_iv,
-            },
-        );
     }
-    // serialize hashmap, encrypt it and store it
-    save_meta_data_to_file(path);
-}
 
-fn delete_action(action: QueueAction, path: String) {
-    // retrieve filename from hashmap
-    let filename = match STORE.read().unwrap().elements.get(action.kv.key.as_str()) {
-        Some(value) => value.filename.clone(),
-        None => "".to_string(),
-    };
-    // delete file
-    match delete_file(format!("{}/{}", path, filename)) {
-        Ok(_o) => log(format!("Deleting key \"{}\".", action.kv.key), LOG_STDOUT),
-        Err(_e) => {
-            log(
-                format!("Could not delete key \"{}\". Keeping it.", action.kv.key),
-                LOG_STDERR,
-            );
-            return;
+    // Read, decrypt and parse a meta-data file
+    fn read_meta_data_file(file_path: String) -> Result<HashMap<String, ValueMetaData>, String> {
+        // load encrypted string
+        let encrypted_string = match read_file_to_string(file_path) {
+            Ok(json) => json,
+            Err(e) => return Err(format!("Could not read stored meta-data file: {}", e)),
+        };
+        // Decrypt content
+        let json_string = match json_try_decrypt(&encrypted_string) {
+            Ok(json) => json,
+            Err(e) => return Err(format!("Could not decrypt meta-data file: {}", e)),
+        };
+        // parse decrypted string
+        match serde_json::from_str(json_string.as_str()) {
+            Ok(val) => Ok(val),
+            Err(e) => Err(format!("Could not parse json: {}", e)),
         }
     }
-    // delete entry in hashmap
-    STORE
-        .write()
-        .unwrap()
-        .elements
-        .remove(action.kv.key.as_str());
-    // serialize hashmap, encrypt it and store it
-    save_meta_data_to_file(path);
-}
 
-// Check existence of key
-pub fn key_exists(key: String) -> bool {
-    STORE.read().unwrap().elements.contains_key(key.as_str())
-}
+    // Load the meta data from the local json file.
+    // If it is missing or damaged, the previous generation is loaded.
+    fn load_meta_data(&self) -> Result<String, String> {
+        // Assemble file path
+        let file_path: String = format!("{}/{}", self.path, META_DATA_FILE);
+        let previous_path = previous_generation_path(&file_path);
+        // check whether file exists
+        let error = if Path::new(&file_path).exists() {
+            match FileStore::read_meta_data_file(file_path.clone()) {
+                Ok(v) => {
+                    *self.elements.write().unwrap() = v;
+                    return Ok("Loaded stored meta-data from file.".to_string());
+                }
+                Err(e) => e,
+            }
+        } else if Path::new(&previous_path).exists() {
+            "Meta-data file is missing.".to_string()
+        } else {
+            return Ok("No meta-data file available.".to_string());
+        };
+        if !Path::new(&previous_path).exists() {
+            return Err(error);
+        }
+        log_warn!("Recovering previous generation of the meta-data: {}", error);
+        let v = match FileStore::read_meta_data_file(previous_path) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Previous generation: {}", e)),
+        };
+        *self.elements.write().unwrap() = v;
+        if self.read_only {
+            return Ok("Loaded previous generation of the meta-data.".to_string());
+        }
+        // Persist the recovered meta-data, the damaged file is removed first so that
+        // it does not replace the previous generation
+        let _ = fs::remove_file(&file_path);
+        self.save_meta_data_to_file()?;
+        Ok("Recovered meta-data from previous generation of the file.".to_string())
+    }
 
-// Reading from the HashMap is possible without the queue
-pub fn get_value(key: String, path: String) -> Result<String, String> {
-    match STORE.read().unwrap().elements.get(key.as_str()) {
-        Some(_value) => {
-            // retrieve filename from hashmap
-            let filename = match STORE.read().unwrap().elements.get(key.as_str()) {
-                Some(value) => value.filename.clone(),
-                None => "".to_string(),
-            };
-            // retrieve dv from hashmap
-            let dv = match STORE.read().unwrap().elements.get(key.as_str()) {
-                Some(value) => value.derivation_value.clone(),
-                None => "".to_string(),
+    // Load the key of the hash identifying identical values, a new key is generated if it is
+    // missing or damaged. Values stored with another key are not shared with new values.
+    // The key file is only as protected as the meta data file, the derivation value is stored with it.
+    fn load_dedup_key(&self) -> Result<(), String> {
+        let file_path = format!("{}/{}", self.path, DEDUP_KEY_FILE);
+        if Path::new(&file_path).exists() {
+            match read_file_to_string(file_path.clone()) {
+                Ok(encrypted_key) => match json_try_decrypt(&encrypted_key) {
+                    Ok(key) => {
+                        *self.dedup_key.write().unwrap() = key;
+                        return Ok(());
+                    }
+                    Err(e) => log_warn!("Could not decrypt dedup key, generating a new key: {}", e),
+                },
+                Err(e) => log_warn!("Could not read dedup key, generating a new key: {}", e),
+            }
+        }
+        let key = generate_derivation_value();
+        if let Err(e) = write_string_to_file(file_path, json_encrypt(key.clone())) {
+            return Err(format!("Could not write dedup key file: {}", e));
+        }
+        *self.dedup_key.write().unwrap() = key;
+        Ok(())
+    }
+
+    // Check that a value file exists and is not empty, if verify is set it is read
+    // and its authenticity is checked. Otherwise it is authenticated when it is read.
+    fn check_value_file(&self, value_meta_data: &ValueMetaData) -> Result<ValueFileState, String> {
+        let file_path = self.value_file_path(&value_meta_data.filename);
+        if !self.verify {
+            return match fs::metadata(&file_path) {
+                Ok(metadata) if metadata.is_file() && metadata.len() > 0 => {
+                    Ok(ValueFileState::Valid)
+                }
+                Ok(_metadata) => {
+                    log_warn!(
+                        "Value file {} is empty or not a file.",
+                        value_meta_data.filename
+                    );
+                    Ok(ValueFileState::Failed)
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(ValueFileState::Missing),
+                Err(e) => Err(format!("Could not read value file: {}", e)),
             };
-            // retrieve iv from hashmap
-            let iv = match STORE.read().unwrap().elements.get(key.as_str()) {
-                Some(value) => value.initialization_vector.clone(),
-                None => "".to_string(),
+        }
+        match fs::read_to_string(&file_path) {
+            Ok(ciphertext) => match file_try_decrypt(
+                &ciphertext,
+                &value_meta_data.derivation_value,
+                &value_meta_data.initialization_vector,
+            ) {
+                Ok(_value) => Ok(ValueFileState::Valid),
+                Err(e) => {
+                    log_warn!(
+                        "Value file {} fails authentication: {}",
+                        value_meta_data.filename,
+                        e
+                    );
+                    Ok(ValueFileState::Failed)
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(ValueFileState::Missing),
+            Err(e) => Err(format!("Could not read value file: {}", e)),
+        }
+    }
+
+    // Whether value files of the flat layout of previous versions are left in the store directory
+    fn has_flat_layout(&self) -> Result<bool, String> {
+        Ok(read_dir_entries(Path::new(&self.path))?
+            .iter()
+            .any(|file| file.is_file() && is_value_file_name(&file_name(file))))
+    }
+
+    // Move the value files of the flat layout of previous versions from the store directory
+    // into their subdirectories. An interrupted migration is continued on the next start-up.
+    fn migrate_flat_layout(&self) -> Result<usize, String> {
+        let mut moved = 0;
+        let mut moved_to = HashSet::new();
+        for file in read_dir_entries(Path::new(&self.path))? {
+            let name = file_name(&file);
+            if !file.is_file() || !is_value_file_name(&name) {
+                continue;
+            }
+            self.create_value_dir(&name)?;
+            if let Err(e) = fs::rename(&file, self.value_file_path(&name)) {
+                return Err(format!("Could not move value file to its directory: {}", e));
+            }
+            moved_to.insert(value_dir(&name));
+            moved += 1;
+        }
+        if moved == 0 {
+            return Ok(0);
+        }
+        // Persist the renames in the target directories and the store directory
+        for dir in moved_to {
+            if let Err(e) = sync_directory(format!("{}/{}", self.path, dir)) {
+                return Err(format!("Could not sync value directory: {}", e));
+            }
+        }
+        if let Err(e) = sync_directory(self.path.clone()) {
+            return Err(format!("Could not sync store directory: {}", e));
+        }
+        Ok(moved)
+    }
+
+    // Reconcile the meta data with the value files, they are written at different moments.
+    // Entries whose value file is missing are dropped, entries whose value file fails
+    // authentication are quarantined and value files without entry are removed.
+    // A read-only store fails instead of dropping or quarantining entries.
+    fn recover(&self) -> Result<String, String> {
+        let mut elements = self.elements.write().unwrap();
+        let checked = elements.len();
+        let mut missing = Vec::new();
+        // Keys of the entries failing authentication by value file
+        let mut failed: HashMap<String, Vec<String>> = HashMap::new();
+        // Shared value files are only checked once
+        let mut checked_files: HashMap<String, ValueFileState> = HashMap::new();
+        for (key, value_meta_data) in elements.iter() {
+            let state = match checked_files.get(&value_meta_data.filename) {
+                Some(state) => *state,
+                None => {
+                    let state = self.check_value_file(value_meta_data)?;
+                    checked_files.insert(value_meta_data.filename.clone(), state);
+                    state
+                }
             };
+            match state {
+                ValueFileState::Valid => (),
+                ValueFileState::Missing => {
+                    log_warn!("Dropping entry, its value file is missing."; key = key);
+                    missing.push(key.clone());
+                }
+                ValueFileState::Failed => {
+                    log_warn!("Quarantining entry, its value file fails authentication."; key = key);
+                    failed
+                        .entry(value_meta_data.filename.clone())
+                        .or_default()
+                        .push(key.clone());
+                }
+            }
+        }
+        if self.read_only {
+            let damaged = missing.len() + failed.values().map(|keys| keys.len()).sum::<usize>();
+            if damaged > 0 {
+                return Err(format!(
+                    "{} entries have a missing or damaged value file, \
+                     start kvsd on the store once to recover it.",
+                    damaged
+                ));
+            }
+            *self.shared_files.write().unwrap() = SharedFiles::from_elements(&elements);
+            return Ok(format!("Checked {} entries.", checked));
+        }
+        for key in missing.iter() {
+            elements.remove(key);
+        }
+        let mut quarantined = 0;
+        for keys in failed.values() {
+            let value_meta_data = elements.remove(&keys[0]).unwrap();
+            for key in keys[1..].iter() {
+                elements.remove(key);
+            }
+            self.quarantine(&keys[0], &value_meta_data, &keys[1..])?;
+            quarantined += keys.len();
+        }
 
-            // load encrypted file
-            let base64_ciphertext = match read_file_to_string(format!("{}{}", path, filename)) {
-                Ok(o) => o,
-                Err(_e) => {
-                    log(
-                        format!(
-                            "Could not read file \"{}\" to string to retrieve it's value.",
-                            format!("{}{}", path, filename)
-                        ),
-                        LOG_STDERR,
-                    );
-                    return Err("File of key not found.".to_string());
+        // Remove value files and incomplete writes without entry, other files are kept
+        let shared_files = SharedFiles::from_elements(&elements);
+        let referenced: HashSet<String> = shared_files.references.keys().cloned().collect();
+        *self.shared_files.write().unwrap() = shared_files;
+        drop(elements);
+        let mut files = read_dir_entries(Path::new(&self.path))?;
+        for first_level in read_dir_entries(&Path::new(&self.path).join(VALUES_DIR))? {
+            for second_level in read_dir_entries(&first_level)? {
+                files.extend(read_dir_entries(&second_level)?);
+            }
+        }
+        let mut orphaned = 0;
+        for file in files.into_iter().filter(|file| file.is_file()) {
+            let name = file_name(&file);
+            let incomplete = match name.strip_suffix(".tmp") {
+                Some(stem) => {
+                    stem == META_DATA_FILE || stem == DEDUP_KEY_FILE || is_value_file_name(stem)
                 }
+                None => false,
             };
-            // decrypt using key and iv
-            let decrypted_value = file_decrypt(base64_ciphertext, dv, iv);
-            Ok(decrypted_value)
+            if incomplete || (is_value_file_name(&name) && !referenced.contains(&name)) {
+                match fs::remove_file(&file) {
+                    Ok(()) => orphaned += 1,
+                    Err(e) => log_warn!("Could not remove orphaned file {}: {}", name, e),
+                }
+            }
+        }
+
+        if !missing.is_empty() || quarantined > 0 {
+            self.save_meta_data_to_file()?;
+        }
+        let summary = format!(
+            "Recovery checked {} entries: {} dropped with missing value file, {} quarantined, \
+             {} orphaned files removed.",
+            checked,
+            missing.len(),
+            quarantined,
+            orphaned
+        );
+        if missing.len() + quarantined + orphaned > 0 {
+            log_warn!("{}", summary);
+        }
+        Ok(summary)
+    }
+
+    // Move the value file of an entry to the quarantine directory together with its
+    // encrypted meta data and the keys of the other entries sharing it
+    fn quarantine(
+        &self,
+        key: &str,
+        value_meta_data: &ValueMetaData,
+        shared_with: &[String],
+    ) -> Result<(), String> {
+        let quarantine_path = format!("{}/{}", self.path, QUARANTINE_DIR);
+        if let Err(e) = fs::create_dir_all(&quarantine_path) {
+            return Err(format!("Could not create quarantine directory: {}", e));
+        }
+        let json_string = match serde_json::to_string(&QuarantinedEntry {
+            key,
+            meta_data: value_meta_data,
+            shared_with,
+        }) {
+            Ok(j) => j,
+            Err(_e) => return Err("Error serializing quarantined entry.".to_string()),
+        };
+        if let Err(e) = write_string_to_file(
+            format!("{}/{}.json", quarantine_path, value_meta_data.filename),
+            json_encrypt(json_string),
+        ) {
+            return Err(format!("Could not write quarantined entry: {}", e));
+        }
+        if let Err(e) = fs::rename(
+            self.value_file_path(&value_meta_data.filename),
+            format!("{}/{}", quarantine_path, value_meta_data.filename),
+        ) {
+            return Err(format!("Could not move value file to quarantine: {}", e));
+        }
+        Ok(())
+    }
+}
+
+impl StorageBackend for FileStore {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    // Initializes the store from the local json file on start-up
+    // and reconciles it with the value files.
+    fn load(&self) -> Result<String, String> {
+        let message = self.load_meta_data()?;
+        if self.read_only {
+            // The dedup key is only needed for writing
+            if self.has_flat_layout()? {
+                return Err("Store uses the flat layout of previous versions, \
+                     start kvsd on the store once to move its value files."
+                    .to_string());
+            }
+            let summary = self.recover()?;
+            return Ok(format!("{} {}", message, summary));
+        }
+        if self.dedup {
+            self.load_dedup_key()?;
+        }
+        let moved = self.migrate_flat_layout()?;
+        if moved > 0 {
+            log_info!(
+                "Moved {} value files of the flat layout into \"{}\".",
+                moved,
+                VALUES_DIR
+            );
         }
-        None => Err("Key not found!".to_string()),
+        let summary = self.recover()?;
+        Ok(format!("{} {}", message, summary))
     }
+
+    // Reading from the HashMap is possible without the queue
+    fn get(&self, key: &str) -> Result<String, String> {
+        // retrieve filename, dv and iv from hashmap
+        let (filename, dv, iv) = match self.elements.read().unwrap().get(key) {
+            Some(value) => (
+                value.filename.clone(),
+                value.derivation_value.clone(),
+                value.initialization_vector.clone(),
+            ),
+            None => return Err("Key not found!".to_string()),
+        };
+        // load encrypted file
+        let file_path = self.value_file_path(&filename);
+        let base64_ciphertext = match read_file_to_string(file_path.clone()) {
+            Ok(o) => o,
+            Err(_e) => {
+                log_error!(
+                    "Could not read file \"{}\" to string to retrieve it's value.",
+                    file_path
+                );
+                return Err("File of key not found.".to_string());
+            }
+        };
+        // decrypt using key and iv
+        match file_try_decrypt(&base64_ciphertext, &dv, &iv) {
+            Ok(decrypted_value) => Ok(decrypted_value),
+            Err(e) => {
+                log_error!("Could not decrypt file \"{}\": {}", file_path, e);
+                Err("File of key is corrupted.".to_string())
+            }
+        }
+    }
+
+    fn put(&self, key: String, value: String) -> Result<(), String> {
+        let replaced = self.write_value(key, value)?;
+        // serialize hashmap, encrypt it and store it
+        self.save_meta_data_to_file()?;
+        self.delete_value_files(replaced.into_iter().collect());
+        Ok(())
+    }
+
+    fn put_all(&self, entries: Vec<(String, String)>) -> Result<(), String> {
+        let mut replaced = Vec::new();
+        for (key, value) in entries {
+            replaced.extend(self.write_value(key, value)?);
+        }
+        self.save_meta_data_to_file()?;
+        self.delete_value_files(replaced);
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        // delete entry in hashmap
+        let value_meta_data = match self.elements.write().unwrap().remove(key) {
+            Some(value) => value,
+            None => return Err("Key not found!".to_string()),
+        };
+        // the file is deleted once the meta data no longer references it
+        if let Err(e) = self.save_meta_data_to_file() {
+            self.elements
+                .write()
+                .unwrap()
+                .insert(key.to_string(), value_meta_data);
+            return Err(e);
+        }
+        let filename = match self
+            .shared_files
+            .write()
+            .unwrap()
+            .remove_reference(&value_meta_data)
+        {
+            Some(filename) => filename,
+            None => {
+                log_debug!("File of key is still shared with other keys."; key = key);
+                return Ok(());
+            }
+        };
+        // delete file, a remaining file is removed by the recovery on the next start-up
+        match delete_file(self.value_file_path(&filename)) {
+            Ok(_o) => log_debug!("Deleted file of key."; key = key),
+            Err(e) => log_warn!("Could not delete file of key: {}", e; key = key),
+        }
+        Ok(())
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.elements.read().unwrap().contains_key(key)
+    }
+
+    fn list_prefix(&self, prefix: &str) -> Vec<String> {
+        self.elements
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        self.elements.read().unwrap().len()
+    }
+
+    // Persist the meta data, all files are synced on each write
+    fn flush(&self) -> Result<(), String> {
+        self.save_meta_data_to_file()
+    }
+
+    fn files(&self) -> Vec<String> {
+        vec![
+            META_DATA_FILE.to_string(),
+            previous_generation_path(META_DATA_FILE),
+            format!("{}.tmp", META_DATA_FILE),
+            DEDUP_KEY_FILE.to_string(),
+            format!("{}.tmp", DEDUP_KEY_FILE),
+            QUARANTINE_DIR.to_string(),
+            VALUES_DIR.to_string(),
+        ]
+    }
+
+    fn holds_values_in_memory(&self) -> bool {
+        false
+    }
+}
+
+// Value files are named by a random alphanumeric string
+fn is_value_file_name(name: &str) -> bool {
+    name.len() == DV_LEN && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+// Subdirectory of a value file relative to the store directory, the first two bytes of the
+// hash of the file name give up to 256 directories on each level
+fn value_dir(filename: &str) -> String {
+    let hash = sha3_256_hex(filename.as_bytes());
+    format!("{}/{}/{}", VALUES_DIR, &hash[0..2], &hash[2..4])
 }
 
-// Initializes the store from the local json file on start-up.
-pub fn load_meta_data_from_file(path: String) -> Result<String, String> {
-    // Assemble file path
-    let file_path: String = format!("{}/kvsd-meta-data.json", path);
-    // check whether file exists
-    if !Path::new(&file_path).exists() {
-        return Ok("No meta-data file available.".to_string());
-    }
-    // load encrypted string
-    let encrypted_string = match read_file_to_string(file_path) {
-        Ok(json) => json,
-        Err(e) => return Err(format!("Could not read stored meta-data file: {}", e)),
-    };
-    // Decrypt content
-    let json_string = json_decrypt(encrypted_string);
-    // parse decrypted string
-    let v: HashMap<String, ValueMetaData> = match serde_json::from_str(json_string.as_str()) {
-        Ok(val) => val,
-        Err(e) => return Err(format!("Could not parse json: {}", e)),
-    };
-    // for each element in array
-    STORE.write().unwrap().elements = v;
-    // insert element in store
-    Ok("Loaded stored meta-data from file.".to_string())
+// Entries of a directory, a missing directory or a file has no entries
+fn read_dir_entries(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(Vec::new());
+    }
+    match fs::read_dir(path) {
+        Ok(entries) => Ok(entries.flatten().map(|entry| entry.path()).collect()),
+        Err(e) => Err(format!("Could not read \"{}\": {}", path.display(), e)),
+    }
 }
 
-fn save_meta_data_to_file(path: String) {
-    // serialize HashMap
-    let json_string = match serde_json::to_string(&STORE.write().unwrap().elements) {
-        Ok(j) => j,
-        Err(_e) => return log("Error serializing hashmap.".to_string(), LOG_STDERR),
-    };
-    // encrypt json
-    let encrypted_json = json_encrypt(json_string);
-    // write to file
-    write_string_to_file(format!("{}/kvsd-meta-data.json", path), encrypted_json);
+fn file_name(path: &Path) -> String {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => String::new(),
+    }
 }
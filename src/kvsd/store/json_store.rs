@@ -6,125 +6,224 @@
 
 // Rust Standard Library
 use std::collections::HashMap;
+use std::fs;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::RwLock;
 
-// lazy static
-use lazy_static::lazy_static;
-
-// json
-use serde::{Deserialize, Serialize};
-
 // kvs modules
-use crate::store::store_actions::{QueueAction, ACTION_DELETE, ACTION_STORE};
+use crate::store::backend::StorageBackend;
 use utils::crypto::{json_decrypt, json_encrypt};
 use utils::filesystem_wrapper::{
-    read_persistent_store_file_to_string, write_persistent_store_file_from_string,
+    previous_generation_path, read_file_to_string, read_persistent_store_file_to_string,
+    write_persistent_store_file_from_string,
 };
-use utils::log::{log, LOG_STDERR, LOG_STDOUT};
+use utils::log_warn;
 
 // Constants
-const MAP_SIZE_MAX: usize = 10000;
+// Default maximum number of entries
+pub const MAP_SIZE_MAX: usize = 10000;
 
-// Map Struct containing the HashMap of all entries
-#[derive(Deserialize, Serialize)]
-struct KeyValueMap {
-    elements: HashMap<String, String>,
+// JSON backend, all encrypted values are held in RAM and persisted to a single JSON file
+pub struct JsonStore {
+    path: String,
+    // All entries by key
+    elements: RwLock<HashMap<String, String>>,
+    // Bytes of the keys and encrypted values, updated with the entries
+    bytes: AtomicUsize,
+    // The store file is not repaired or written, e.g. for the source of a migration
+    read_only: bool,
 }
 
-// Static HashMap containing all elements
-lazy_static! {
-    static ref STORE: RwLock<KeyValueMap> = RwLock::new(KeyValueMap {
-        elements: HashMap::new(),
-    });
-}
+impl JsonStore {
+    pub fn new(path: String, read_only: bool) -> JsonStore {
+        JsonStore {
+            path,
+            elements: RwLock::new(HashMap::new()),
+            bytes: AtomicUsize::new(0),
+            read_only,
+        }
+    }
 
-// Check size of HashMap, more than 10k elements are not allowed.
-pub fn is_store_full() -> bool {
-    if STORE.read().unwrap().elements.len() >= MAP_SIZE_MAX {
-        return true;
+    // Replace all entries with the entries of the store file
+    fn set_elements(&self, elements: HashMap<String, String>) {
+        let bytes = elements
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum();
+        *self.elements.write().unwrap() = elements;
+        self.bytes.store(bytes, Ordering::SeqCst);
     }
-    false
-}
 
-// Handle a QueueAction
-pub fn handle_action(action: QueueAction, path: String) {
-    match action.action {
-        ACTION_STORE => {
-            log(format!("Storing key \"{}\".", action.kv.key), LOG_STDOUT);
-            // Forward to specific handle function.
-            store_action(action, path);
+    // Insert an encrypted value and account its bytes
+    fn insert(&self, elements: &mut HashMap<String, String>, key: String, value: String) {
+        self.bytes
+            .fetch_add(key.len() + value.len(), Ordering::SeqCst);
+        let key_length = key.len();
+        if let Some(replaced) = elements.insert(key, value) {
+            self.bytes
+                .fetch_sub(key_length + replaced.len(), Ordering::SeqCst);
         }
-        ACTION_DELETE => {
-            log(format!("Deleting key \"{}\".", action.kv.key), LOG_STDOUT);
-            // Forward to specific handle function.
-            delete_action(action, path);
+    }
+
+    // Serialize the HashMap and write it to the persistent store file
+    fn save(&self) -> Result<(), String> {
+        let j = match serde_json::to_string(&*self.elements.read().unwrap()) {
+            Ok(j) => j,
+            Err(_e) => return Err("Error serializing hashmap.".to_string()),
+        };
+        match write_persistent_store_file_from_string(self.path.clone(), j) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Could not write persistent data file: {}", e)),
         }
-        _ => {
-            log("No matching action available.".to_string(), LOG_STDERR);
+    }
+
+    // Load the previous generation of the persistent store file if the current one is damaged
+    fn recover_previous_generation(&self, error: String) -> Result<String, String> {
+        let file_path = format!("{}/store.json", self.path);
+        let previous_path = previous_generation_path(&file_path);
+        if !Path::new(&previous_path).exists() {
+            return Err(error);
         }
+        log_warn!("Recovering previous generation of the store: {}", error);
+        let json_string = match read_file_to_string(previous_path) {
+            Ok(json) => json,
+            Err(e) => {
+                return Err(format!(
+                    "Could not read previous persistent data file: {}",
+                    e
+                ))
+            }
+        };
+        let v: HashMap<String, String> = match serde_json::from_str(json_string.as_str()) {
+            Ok(val) => val,
+            Err(e) => return Err(format!("Could not parse previous json: {}", e)),
+        };
+        self.set_elements(v);
+        if self.read_only {
+            return Ok("Loaded previous generation of the file.".to_string());
+        }
+        // Persist the recovered store, the damaged file is removed first so that
+        // it does not replace the previous generation
+        let _ = fs::remove_file(&file_path);
+        self.save()?;
+        Ok("Recovered store from previous generation of the file.".to_string())
     }
 }
 
-// Handle store QueueAction
-fn store_action(action: QueueAction, path: String) {
-    STORE
-        .write()
-        .unwrap()
-        .elements
-        .insert(action.kv.key, json_encrypt(action.kv.value));
-    let j = match serde_json::to_string(&STORE.write().unwrap().elements) {
-        Ok(j) => j,
-        Err(_e) => return log("Error serializing hashmap.".to_string(), LOG_STDERR),
-    };
-    write_persistent_store_file_from_string(path, j);
-}
+impl StorageBackend for JsonStore {
+    fn name(&self) -> &'static str {
+        "json"
+    }
 
-// Handle delete QueueAction
-fn delete_action(action: QueueAction, path: String) {
-    STORE
-        .write()
-        .unwrap()
-        .elements
-        .remove(action.kv.key.as_str());
-    let j = match serde_json::to_string(&STORE.write().unwrap().elements) {
-        Ok(j) => j,
-        Err(_e) => return log("Error serializing hashmap.".to_string(), LOG_STDERR),
-    };
-    write_persistent_store_file_from_string(path, j);
-}
+    // Initializes the store from the local json file on start-up.
+    // If it is missing or damaged, the previous generation is loaded.
+    fn load(&self) -> Result<String, String> {
+        let file_path = format!("{}/store.json", self.path);
+        if !Path::new(&file_path).exists() {
+            if !Path::new(&previous_generation_path(&file_path)).exists() {
+                return Ok("No persistent file available.".to_string());
+            }
+            return self
+                .recover_previous_generation("Persistent data file is missing.".to_string());
+        }
+        let json_string = match read_persistent_store_file_to_string(self.path.clone()) {
+            Ok(json) => json,
+            Err(e) => {
+                return self.recover_previous_generation(format!(
+                    "Could not read persistent data file: {}",
+                    e
+                ))
+            }
+        };
+        let v: HashMap<String, String> = match serde_json::from_str(json_string.as_str()) {
+            Ok(val) => val,
+            Err(e) => {
+                return self.recover_previous_generation(format!("Could not parse json: {}", e))
+            }
+        };
+        // for each element in array
+        self.set_elements(v);
+        // insert element in store
+        Ok("Loaded store from file.".to_string())
+    }
 
-// Check existence of key
-pub fn key_exists(key: String) -> bool {
-    STORE.read().unwrap().elements.contains_key(key.as_str())
-}
+    // Reading from the HashMap is possible without the queue
+    fn get(&self, key: &str) -> Result<String, String> {
+        match self.elements.read().unwrap().get(key) {
+            Some(value) => {
+                let decrypted_value = json_decrypt(value.to_string());
+                Ok(decrypted_value)
+            }
+            None => Err("Key not found!".to_string()),
+        }
+    }
 
-// Reading from the HashMap is possible without the queue
-pub fn get_value(key: String) -> Result<String, String> {
-    match STORE.read().unwrap().elements.get(key.as_str()) {
-        Some(value) => {
-            let decrypted_value = json_decrypt(value.to_string());
-            Ok(decrypted_value)
+    fn put(&self, key: String, value: String) -> Result<(), String> {
+        self.insert(
+            &mut self.elements.write().unwrap(),
+            key,
+            json_encrypt(value),
+        );
+        self.save()
+    }
+
+    fn put_all(&self, entries: Vec<(String, String)>) -> Result<(), String> {
+        let mut elements = self.elements.write().unwrap();
+        for (key, value) in entries {
+            self.insert(&mut elements, key, json_encrypt(value));
         }
-        None => Err("Key not found!".to_string()),
+        drop(elements);
+        self.save()
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        match self.elements.write().unwrap().remove(key) {
+            Some(value) => {
+                self.bytes
+                    .fetch_sub(key.len() + value.len(), Ordering::SeqCst);
+            }
+            None => return Err("Key not found!".to_string()),
+        }
+        self.save()
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.elements.read().unwrap().contains_key(key)
+    }
+
+    fn list_prefix(&self, prefix: &str) -> Vec<String> {
+        self.elements
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
     }
-}
 
-// Initializes the store from the local json file on start-up.
-pub fn initialize_store_from_file(path: String) -> Result<String, String> {
-    if !Path::new(&format!("{}/store.json", path)).exists() {
-        return Ok("No persistent file available.".to_string());
-    }
-    let json_string = match read_persistent_store_file_to_string(path) {
-        Ok(json) => json,
-        Err(e) => return Err(format!("Could not read persistent data file: {}", e)),
-    };
-    let v: HashMap<String, String> = match serde_json::from_str(json_string.as_str()) {
-        Ok(val) => val,
-        Err(e) => return Err(format!("Could not parse json: {}", e)),
-    };
-    // for each element in array
-    STORE.write().unwrap().elements = v;
-    // insert element in store
-    Ok("Loaded store from file.".to_string())
+    fn size(&self) -> usize {
+        self.elements.read().unwrap().len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.bytes.load(Ordering::SeqCst)
+    }
+
+    // The store file is synced on each write
+    fn flush(&self) -> Result<(), String> {
+        self.save()
+    }
+
+    fn files(&self) -> Vec<String> {
+        vec![
+            "store.json".to_string(),
+            previous_generation_path("store.json"),
+            "store.json.tmp".to_string(),
+        ]
+    }
+
+    fn holds_values_in_memory(&self) -> bool {
+        true
+    }
 }
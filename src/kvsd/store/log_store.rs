@@ -0,0 +1,709 @@
+/*
+*  kvsd log store Module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+// json
+use serde::{Deserialize, Serialize};
+
+// kvs modules
+use crate::store::backend::StorageBackend;
+use utils::crypto::{json_encrypt, json_try_decrypt, sha3_256_hex};
+use utils::filesystem_wrapper::sync_directory;
+use utils::{log_error, log_info, log_warn};
+
+// Constants
+// Segments are closed once they reach this size, records are never appended to closed segments
+const SEGMENT_SIZE_MAX: u64 = 8 * 1024 * 1024;
+// Closed segments are compacted once superseded records make up half of them and at least this many bytes
+const COMPACTION_GARBAGE_MIN: u64 = 1024 * 1024;
+// Interval for checking whether a compaction is due
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(10);
+// Number of hex characters of the SHA3-256 checksum of a record
+const CHECKSUM_LEN: usize = 16;
+const SEGMENT_PREFIX: &str = "segment-";
+const SEGMENT_SUFFIX: &str = ".log";
+// Output of a running compaction, renamed to a segment once it is complete
+const COMPACTION_FILE: &str = "compaction.tmp";
+
+// Record of the log, a record without value deletes the key.
+// The sequence number orders all records independent of the segment they are stored in.
+#[derive(Deserialize, Serialize)]
+struct LogRecord {
+    sequence: u64,
+    key: String,
+    value: Option<String>,
+}
+
+// Location of the latest record of a key
+#[derive(Clone, Copy, PartialEq)]
+struct RecordLocation {
+    segment: u64,
+    offset: u64,
+    length: u64,
+    sequence: u64,
+}
+
+// Segment records are appended to
+struct ActiveSegment {
+    id: u64,
+    file: File,
+    size: u64,
+}
+
+// Size of a segment and the bytes of its records which are superseded or deleted
+#[derive(Default)]
+struct SegmentUsage {
+    size: u64,
+    garbage: u64,
+    // Sequence number of the newest deletion record, 0 if the segment contains none
+    tombstone: u64,
+}
+
+// Log backend, encrypted and checksummed records are appended to segment files.
+// Only the location of the latest record of each key is held in RAM.
+pub struct LogStore {
+    path: String,
+    index: RwLock<HashMap<String, RecordLocation>>,
+    active: Mutex<Option<ActiveSegment>>,
+    segments: Mutex<HashMap<u64, SegmentUsage>>,
+    next_segment: AtomicU64,
+    next_sequence: AtomicU64,
+    // Compaction starts after the index is loaded
+    loaded: AtomicBool,
+    // Only a single compaction runs at a time
+    compacting: Mutex<()>,
+    // Segments are not truncated or removed on load, e.g. for the source of a migration
+    read_only: bool,
+}
+
+impl LogStore {
+    pub fn new(path: String, read_only: bool) -> LogStore {
+        LogStore {
+            path,
+            index: RwLock::new(HashMap::new()),
+            active: Mutex::new(None),
+            segments: Mutex::new(HashMap::new()),
+            next_segment: AtomicU64::new(1),
+            next_sequence: AtomicU64::new(1),
+            loaded: AtomicBool::new(false),
+            compacting: Mutex::new(()),
+            read_only,
+        }
+    }
+
+    // Compact the closed segments in the background as long as the store is in use
+    pub fn start_compaction(store: &Arc<LogStore>) {
+        let weak_store = Arc::downgrade(store);
+        thread::spawn(move || loop {
+            thread::sleep(COMPACTION_INTERVAL);
+            let store = match weak_store.upgrade() {
+                Some(store) => store,
+                None => break,
+            };
+            if store.loaded.load(Ordering::Relaxed) && store.compaction_due() {
+                if let Err(e) = store.compact() {
+                    log_error!("Compaction of the log store failed: {}", e);
+                }
+            }
+        });
+    }
+
+    fn segment_name(id: u64) -> String {
+        format!("{}{:010}{}", SEGMENT_PREFIX, id, SEGMENT_SUFFIX)
+    }
+
+    fn segment_path(&self, id: u64) -> String {
+        format!("{}/{}", self.path, LogStore::segment_name(id))
+    }
+
+    // IDs of all segment files in the store directory in ascending order
+    fn segment_ids(&self) -> Result<Vec<u64>, String> {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) => return Err(format!("Could not read store directory: {}", e)),
+        };
+        let mut ids = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with(SEGMENT_PREFIX) && name.ends_with(SEGMENT_SUFFIX) {
+                let id = &name[SEGMENT_PREFIX.len()..name.len() - SEGMENT_SUFFIX.len()];
+                if let Ok(id) = id.parse::<u64>() {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    // Append a record to the active segment, a new segment is started if it is full
+    fn append(&self, record: &LogRecord) -> Result<RecordLocation, String> {
+        let data = encode_record(record)?;
+        let mut active = self.active.lock().unwrap();
+        let full = match active.as_ref() {
+            Some(segment) => segment.size >= SEGMENT_SIZE_MAX,
+            None => true,
+        };
+        if full {
+            if let Some(closed) = active.take() {
+                let _ = closed.file.sync_all();
+            }
+            let id = self.next_segment.fetch_add(1, Ordering::SeqCst);
+            let file = match OpenOptions::new()
+                .append(true)
+                .create_new(true)
+                .open(self.segment_path(id))
+            {
+                Ok(file) => file,
+                Err(e) => return Err(format!("Could not create segment: {}", e)),
+            };
+            self.segments
+                .lock()
+                .unwrap()
+                .insert(id, SegmentUsage::default());
+            *active = Some(ActiveSegment { id, file, size: 0 });
+        }
+        let segment = active.as_mut().unwrap();
+        if let Err(e) = segment.file.write_all(&data) {
+            // Continue in a new segment, the partial record is dropped on the next start-up
+            *active = None;
+            return Err(format!("Could not append record: {}", e));
+        }
+        let location = RecordLocation {
+            segment: segment.id,
+            offset: segment.size,
+            length: data.len() as u64,
+            sequence: record.sequence,
+        };
+        segment.size += location.length;
+        if let Some(usage) = self.segments.lock().unwrap().get_mut(&location.segment) {
+            usage.size += location.length;
+        }
+        Ok(location)
+    }
+
+    // Account a record that is superseded or deleted
+    fn add_garbage(&self, location: RecordLocation) {
+        if let Some(usage) = self.segments.lock().unwrap().get_mut(&location.segment) {
+            usage.garbage += location.length;
+        }
+    }
+
+    // Account a deletion record, it is garbage itself
+    fn add_tombstone(&self, location: RecordLocation) {
+        if let Some(usage) = self.segments.lock().unwrap().get_mut(&location.segment) {
+            usage.garbage += location.length;
+            usage.tombstone = usage.tombstone.max(location.sequence);
+        }
+    }
+
+    // Read the raw bytes of a record
+    fn read_raw(&self, location: RecordLocation) -> Result<Vec<u8>, String> {
+        let mut file = match File::open(self.segment_path(location.segment)) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Could not open segment: {}", e)),
+        };
+        let mut data = vec![0u8; location.length as usize];
+        match file
+            .seek(SeekFrom::Start(location.offset))
+            .and_then(|_| file.read_exact(&mut data))
+        {
+            Ok(()) => Ok(data),
+            Err(e) => Err(format!("Could not read record: {}", e)),
+        }
+    }
+
+    // Whether the closed segments contain enough superseded records to compact them
+    fn compaction_due(&self) -> bool {
+        let active_id = self.active.lock().unwrap().as_ref().map(|a| a.id);
+        let mut size = 0;
+        let mut garbage = 0;
+        for (id, usage) in self.segments.lock().unwrap().iter() {
+            if Some(*id) != active_id {
+                size += usage.size;
+                garbage += usage.garbage;
+            }
+        }
+        garbage >= COMPACTION_GARBAGE_MIN && garbage * 2 >= size
+    }
+
+    // Copy the latest records of all keys in closed segments to a new segment and delete the closed segments.
+    // Deletions are dropped, all older records of a deleted key are in the compacted segments as well.
+    fn compact(&self) -> Result<(), String> {
+        let _compacting = self.compacting.lock().unwrap();
+        let active_id = self.active.lock().unwrap().as_ref().map(|a| a.id);
+        let closed: Vec<u64> = self
+            .segments
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|id| Some(**id) != active_id)
+            .cloned()
+            .collect();
+        if closed.is_empty() {
+            return Ok(());
+        }
+        let live: Vec<(String, RecordLocation)> = self
+            .index
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_key, location)| closed.contains(&location.segment))
+            .map(|(key, location)| (key.clone(), *location))
+            .collect();
+
+        // Write the compacted segment, it only becomes visible once it is complete
+        let id = self.next_segment.fetch_add(1, Ordering::SeqCst);
+        let compaction_path = format!("{}/{}", self.path, COMPACTION_FILE);
+        let mut file = match File::create(&compaction_path) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Could not create compacted segment: {}", e)),
+        };
+        let mut moved = Vec::new();
+        let mut offset = 0;
+        for (key, location) in live {
+            let data = self.read_raw(location)?;
+            if let Err(e) = file.write_all(&data) {
+                return Err(format!("Could not write compacted segment: {}", e));
+            }
+            let new_location = RecordLocation {
+                segment: id,
+                offset,
+                ..location
+            };
+            offset += location.length;
+            moved.push((key, location, new_location));
+        }
+        if let Err(e) = file
+            .sync_all()
+            .and_then(|()| fs::rename(&compaction_path, self.segment_path(id)))
+        {
+            return Err(format!("Could not complete compacted segment: {}", e));
+        }
+        let _ = sync_directory(self.path.clone());
+
+        // Switch to the compacted records unless they were replaced in the meantime
+        let mut index = self.index.write().unwrap();
+        let mut garbage = 0;
+        for (key, location, new_location) in moved.iter() {
+            if index.get(key) == Some(location) {
+                index.insert(key.clone(), *new_location);
+            } else {
+                garbage += location.length;
+            }
+        }
+        let mut segments = self.segments.lock().unwrap();
+        segments.insert(
+            id,
+            SegmentUsage {
+                size: offset,
+                garbage,
+                tombstone: 0,
+            },
+        );
+        for closed_id in deletion_order(&segments, &closed) {
+            segments.remove(&closed_id);
+            if let Err(e) = fs::remove_file(self.segment_path(closed_id)) {
+                log_warn!("Could not delete compacted segment: {}", e);
+            }
+        }
+        let _ = sync_directory(self.path.clone());
+        log_info!(
+            "Compacted {} segments of the log store, {} entries kept.",
+            closed.len(),
+            moved.len()
+        );
+        Ok(())
+    }
+}
+
+impl StorageBackend for LogStore {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    // Rebuild the index from all segments on start-up, a partially written record at the end of
+    // a segment is removed
+    fn load(&self) -> Result<String, String> {
+        let compaction_path = format!("{}/{}", self.path, COMPACTION_FILE);
+        if !self.read_only && Path::new(&compaction_path).exists() {
+            let _ = fs::remove_file(&compaction_path);
+        }
+        let ids = self.segment_ids()?;
+        let mut index: HashMap<String, RecordLocation> = HashMap::new();
+        // Sequence numbers of deleted keys, older records of these keys may follow in later segments
+        let mut deleted: HashMap<String, u64> = HashMap::new();
+        let mut segments: HashMap<u64, SegmentUsage> = HashMap::new();
+        let mut corrupted = 0;
+        let mut next_sequence = 1;
+        for id in ids.iter() {
+            let data = match fs::read(self.segment_path(*id)) {
+                Ok(data) => data,
+                Err(e) => return Err(format!("Could not read segment {}: {}", id, e)),
+            };
+            let mut usage = SegmentUsage {
+                size: data.len() as u64,
+                garbage: 0,
+                tombstone: 0,
+            };
+            let mut offset = 0;
+            while offset < data.len() {
+                let end = match data[offset..].iter().position(|b| *b == b'\n') {
+                    Some(position) => offset + position + 1,
+                    None => data.len(),
+                };
+                let length = (end - offset) as u64;
+                let record = match decode_record(&data[offset..end]) {
+                    Some(record) => record,
+                    None if end == data.len() && self.read_only => {
+                        // Partially written record of an interrupted write, skipped
+                        usage.size = offset as u64;
+                        break;
+                    }
+                    None if end == data.len() => {
+                        // Partially written record of an interrupted write
+                        log_warn!("Removing incomplete record at the end of segment {}.", id);
+                        let truncated = OpenOptions::new()
+                            .write(true)
+                            .open(self.segment_path(*id))
+                            .and_then(|file| file.set_len(offset as u64));
+                        if let Err(e) = truncated {
+                            return Err(format!("Could not truncate segment {}: {}", id, e));
+                        }
+                        usage.size = offset as u64;
+                        break;
+                    }
+                    None => {
+                        corrupted += 1;
+                        usage.garbage += length;
+                        offset = end;
+                        continue;
+                    }
+                };
+                let location = RecordLocation {
+                    segment: *id,
+                    offset: offset as u64,
+                    length,
+                    sequence: record.sequence,
+                };
+                offset = end;
+                next_sequence = next_sequence.max(record.sequence + 1);
+                if record.value.is_none() {
+                    usage.tombstone = usage.tombstone.max(record.sequence);
+                }
+                let newer = match (index.get(&record.key), deleted.get(&record.key)) {
+                    (Some(existing), _) => existing.sequence >= record.sequence,
+                    (None, Some(sequence)) => *sequence >= record.sequence,
+                    (None, None) => false,
+                };
+                if newer {
+                    usage.garbage += length;
+                    continue;
+                }
+                let replaced = match record.value {
+                    Some(_value) => index.insert(record.key, location),
+                    None => {
+                        usage.garbage += length;
+                        deleted.insert(record.key.clone(), record.sequence);
+                        index.remove(&record.key)
+                    }
+                };
+                if let Some(replaced) = replaced {
+                    if replaced.segment == *id {
+                        usage.garbage += replaced.length;
+                    } else if let Some(replaced_usage) = segments.get_mut(&replaced.segment) {
+                        replaced_usage.garbage += replaced.length;
+                    }
+                }
+            }
+            segments.insert(*id, usage);
+        }
+        if corrupted > 0 {
+            log_error!(
+                "Skipped {} corrupted records of the log store, their keys may have older values.",
+                corrupted
+            );
+        }
+        let entries = index.len();
+        *self.index.write().unwrap() = index;
+        *self.segments.lock().unwrap() = segments;
+        // Records are always appended to a new segment
+        self.next_segment
+            .store(ids.last().map_or(1, |id| id + 1), Ordering::SeqCst);
+        self.next_sequence.store(next_sequence, Ordering::SeqCst);
+        self.loaded.store(true, Ordering::Relaxed);
+        Ok(format!(
+            "Loaded {} entries from {} segments.",
+            entries,
+            ids.len()
+        ))
+    }
+
+    fn get(&self, key: &str) -> Result<String, String> {
+        // The index is locked while reading, so a compaction can not delete the segment
+        let index = self.index.read().unwrap();
+        let location = match index.get(key) {
+            Some(location) => *location,
+            None => return Err("Key not found!".to_string()),
+        };
+        let data = self.read_raw(location)?;
+        match decode_record(&data).and_then(|record| record.value) {
+            Some(value) => Ok(value),
+            None => {
+                log_error!(
+                    "Record of key in segment {} is corrupted.",
+                    location.segment
+                );
+                Err("Record of key is corrupted.".to_string())
+            }
+        }
+    }
+
+    fn put(&self, key: String, value: String) -> Result<(), String> {
+        let record = LogRecord {
+            sequence: self.next_sequence.fetch_add(1, Ordering::SeqCst),
+            key,
+            value: Some(value),
+        };
+        let location = self.append(&record)?;
+        let replaced = self.index.write().unwrap().insert(record.key, location);
+        if let Some(replaced) = replaced {
+            self.add_garbage(replaced);
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        if !self.exists(key) {
+            return Err("Key not found!".to_string());
+        }
+        let record = LogRecord {
+            sequence: self.next_sequence.fetch_add(1, Ordering::SeqCst),
+            key: key.to_string(),
+            value: None,
+        };
+        let location = self.append(&record)?;
+        let replaced = self.index.write().unwrap().remove(key);
+        if let Some(replaced) = replaced {
+            self.add_garbage(replaced);
+        }
+        self.add_tombstone(location);
+        Ok(())
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.index.read().unwrap().contains_key(key)
+    }
+
+    fn list_prefix(&self, prefix: &str) -> Vec<String> {
+        self.index
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        self.index.read().unwrap().len()
+    }
+
+    // Flush the active segment to the storage device
+    fn flush(&self) -> Result<(), String> {
+        if let Some(active) = self.active.lock().unwrap().as_ref() {
+            if let Err(e) = active.file.sync_all() {
+                return Err(format!("Could not sync segment: {}", e));
+            }
+        }
+        match sync_directory(self.path.clone()) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Could not sync store directory: {}", e)),
+        }
+    }
+
+    fn files(&self) -> Vec<String> {
+        self.segments
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|id| LogStore::segment_name(*id))
+            .collect()
+    }
+
+    fn holds_values_in_memory(&self) -> bool {
+        false
+    }
+}
+
+// Encrypt a record and prefix it with its checksum, records are separated by newlines
+fn encode_record(record: &LogRecord) -> Result<Vec<u8>, String> {
+    let json = match serde_json::to_string(record) {
+        Ok(json) => json,
+        Err(e) => return Err(format!("Could not serialize record: {}", e)),
+    };
+    let encrypted = json_encrypt(json);
+    let checksum = sha3_256_hex(encrypted.as_bytes());
+    Ok(format!("{} {}\n", &checksum[..CHECKSUM_LEN], encrypted).into_bytes())
+}
+
+// Verify the checksum of a record and decrypt it, None if it is incomplete or corrupted
+fn decode_record(data: &[u8]) -> Option<LogRecord> {
+    let line = std::str::from_utf8(data).ok()?.strip_suffix('\n')?;
+    let (checksum, encrypted) = line.split_once(' ')?;
+    if checksum.len() != CHECKSUM_LEN
+        || checksum != &sha3_256_hex(encrypted.as_bytes())[..CHECKSUM_LEN]
+    {
+        return None;
+    }
+    serde_json::from_str(&json_try_decrypt(encrypted).ok()?).ok()
+}
+
+// Order the compacted segments are deleted in. A deletion record must outlive the older
+// records of its key, otherwise an interrupted compaction brings deleted keys back.
+// Segments without deletion records go first, the others in the order they were written.
+fn deletion_order(segments: &HashMap<u64, SegmentUsage>, closed: &[u64]) -> Vec<u64> {
+    let mut order: Vec<(u64, u64)> = closed
+        .iter()
+        .map(|id| (segments.get(id).map_or(0, |usage| usage.tombstone), *id))
+        .collect();
+    order.sort_unstable();
+    order.into_iter().map(|(_tombstone, id)| id).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Empty store directory for a test
+    fn store_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("kvsd-log-{}-{}", name, std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn open(path: &str) -> LogStore {
+        let store = LogStore::new(path.to_string(), false);
+        store.load().unwrap();
+        store
+    }
+
+    // Close the active segment as if it was full
+    fn close_segment(store: &LogStore) {
+        if let Some(closed) = store.active.lock().unwrap().take() {
+            closed.file.sync_all().unwrap();
+        }
+    }
+
+    #[test]
+    fn load_segments_ok() {
+        let path = store_path("segments");
+        let store = open(&path);
+        store.put("first".to_string(), "1".to_string()).unwrap();
+        store.put("second".to_string(), "2".to_string()).unwrap();
+        close_segment(&store);
+        store.delete("first").unwrap();
+        store.put("second".to_string(), "3".to_string()).unwrap();
+        close_segment(&store);
+        store.put("third".to_string(), "4".to_string()).unwrap();
+        drop(store);
+        let store = open(&path);
+        let second = store.get("second");
+        let third = store.get("third");
+        let _ = fs::remove_dir_all(&path);
+        assert_eq!(store.size(), 2);
+        assert_eq!(store.exists("first"), false);
+        assert_eq!(second, Ok("3".to_string()));
+        assert_eq!(third, Ok("4".to_string()));
+    }
+
+    #[test]
+    fn load_truncated_record_ok() {
+        let path = store_path("truncated");
+        let store = open(&path);
+        store.put("first".to_string(), "1".to_string()).unwrap();
+        store.flush().unwrap();
+        let segment = store.segment_path(1);
+        let size = fs::metadata(&segment).unwrap().len();
+        drop(store);
+        // Crash while the next record was appended
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(b"0123456789abcdef Zm9v").unwrap();
+        drop(file);
+        let store = open(&path);
+        let truncated_size = fs::metadata(&segment).unwrap().len();
+        store.put("second".to_string(), "2".to_string()).unwrap();
+        drop(store);
+        let store = open(&path);
+        let first = store.get("first");
+        let second = store.get("second");
+        let _ = fs::remove_dir_all(&path);
+        assert_eq!(truncated_size, size);
+        assert_eq!(first, Ok("1".to_string()));
+        assert_eq!(second, Ok("2".to_string()));
+    }
+
+    #[test]
+    fn compaction_restart_ok() {
+        let path = store_path("compaction");
+        let store = open(&path);
+        store.put("first".to_string(), "1".to_string()).unwrap();
+        store.put("second".to_string(), "2".to_string()).unwrap();
+        store.put("first".to_string(), "3".to_string()).unwrap();
+        store.put("deleted".to_string(), "4".to_string()).unwrap();
+        store.delete("deleted").unwrap();
+        close_segment(&store);
+        store.put("third".to_string(), "5".to_string()).unwrap();
+        store.compact().unwrap();
+        let segments = store.segment_ids().unwrap();
+        drop(store);
+        let store = open(&path);
+        let first = store.get("first");
+        let second = store.get("second");
+        let third = store.get("third");
+        let _ = fs::remove_dir_all(&path);
+        // The closed segment is replaced by the compacted one
+        assert_eq!(segments, vec![2, 3]);
+        assert_eq!(store.size(), 3);
+        assert_eq!(store.exists("deleted"), false);
+        assert_eq!(first, Ok("3".to_string()));
+        assert_eq!(second, Ok("2".to_string()));
+        assert_eq!(third, Ok("5".to_string()));
+    }
+
+    #[test]
+    fn compaction_interrupted_ok() {
+        let path = store_path("interrupted");
+        let store = open(&path);
+        store.put("deleted".to_string(), "1".to_string()).unwrap();
+        close_segment(&store);
+        // Segment 2 is active while the record is compacted into segment 3
+        store.put("kept".to_string(), "2".to_string()).unwrap();
+        store.compact().unwrap();
+        store.delete("deleted").unwrap();
+        close_segment(&store);
+        store.put("new".to_string(), "3".to_string()).unwrap();
+        // Interrupted after deleting the first of the closed segments 2 and 3
+        let order = deletion_order(&store.segments.lock().unwrap(), &[2, 3]);
+        fs::remove_file(store.segment_path(order[0])).unwrap();
+        drop(store);
+        let store = open(&path);
+        let _ = fs::remove_dir_all(&path);
+        assert_eq!(order, vec![3, 2]);
+        assert_eq!(store.exists("deleted"), false);
+    }
+}
@@ -0,0 +1,143 @@
+/*
+*  kvsd memory store Module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::collections::HashMap;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+// kvs modules
+use crate::store::backend::StorageBackend;
+use utils::crypto::{json_encrypt, json_try_decrypt};
+use utils::log_info;
+
+// Memory backend, the encrypted values are only held in RAM and lost on shutdown.
+// Intended for volatile data such as session tokens and for tests.
+pub struct MemoryStore {
+    // All encrypted values by key
+    elements: RwLock<HashMap<String, Vec<u8>>>,
+    // Bytes of the keys and encrypted values, updated with the entries
+    bytes: AtomicUsize,
+    // Overwrite all keys and values on shutdown
+    wipe_on_shutdown: bool,
+}
+
+impl MemoryStore {
+    pub fn new(wipe_on_shutdown: bool) -> MemoryStore {
+        MemoryStore {
+            elements: RwLock::new(HashMap::new()),
+            bytes: AtomicUsize::new(0),
+            wipe_on_shutdown,
+        }
+    }
+
+    // Overwrite and remove all entries
+    fn wipe(&self) {
+        let mut elements = self.elements.write().unwrap();
+        let entries = elements.len();
+        for (key, value) in elements.drain() {
+            overwrite(key.into_bytes());
+            overwrite(value);
+        }
+        self.bytes.store(0, Ordering::SeqCst);
+        log_info!("Wiped {} entries of the memory store.", entries);
+    }
+}
+
+// Overwrite the bytes before they are freed, volatile writes are not optimized away
+fn overwrite(mut bytes: Vec<u8>) {
+    for byte in bytes.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
+
+impl StorageBackend for MemoryStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    // Nothing is persisted, the store is always empty on start-up
+    fn load(&self) -> Result<String, String> {
+        Ok("Memory store is empty, entries are not persisted.".to_string())
+    }
+
+    fn get(&self, key: &str) -> Result<String, String> {
+        match self.elements.read().unwrap().get(key) {
+            Some(value) => json_try_decrypt(&String::from_utf8_lossy(value)),
+            None => Err("Key not found!".to_string()),
+        }
+    }
+
+    // A replaced value is overwritten
+    fn put(&self, key: String, value: String) -> Result<(), String> {
+        let encrypted_value = json_encrypt(value).into_bytes();
+        let mut elements = self.elements.write().unwrap();
+        self.bytes
+            .fetch_add(key.len() + encrypted_value.len(), Ordering::SeqCst);
+        let key_length = key.len();
+        if let Some(replaced) = elements.insert(key, encrypted_value) {
+            self.bytes
+                .fetch_sub(key_length + replaced.len(), Ordering::SeqCst);
+            overwrite(replaced);
+        }
+        Ok(())
+    }
+
+    // The key and value of the deleted entry are overwritten
+    fn delete(&self, key: &str) -> Result<(), String> {
+        match self.elements.write().unwrap().remove_entry(key) {
+            Some((key, value)) => {
+                self.bytes
+                    .fetch_sub(key.len() + value.len(), Ordering::SeqCst);
+                overwrite(key.into_bytes());
+                overwrite(value);
+                Ok(())
+            }
+            None => Err("Key not found!".to_string()),
+        }
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.elements.read().unwrap().contains_key(key)
+    }
+
+    fn list_prefix(&self, prefix: &str) -> Vec<String> {
+        self.elements
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        self.elements.read().unwrap().len()
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.bytes.load(Ordering::SeqCst)
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn close(&self) {
+        if self.wipe_on_shutdown {
+            self.wipe();
+        }
+    }
+
+    fn files(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn holds_values_in_memory(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,185 @@
+/*
+*  kvsd store migration Module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+// kvs modules
+use crate::config::StoreConfig;
+use crate::store::backend::{create_backend, lock_store, StorageBackend, LOCK_FILE};
+use utils::filesystem_wrapper::sync_directory;
+use utils::log_info;
+
+// Constants
+// Number of entries copied at once
+const BATCH_SIZE: usize = 1000;
+
+// Migrate the store at path from one backend to another while kvsd is stopped.
+// The entries are written to a staging directory next to path and verified,
+// afterwards the staging directory replaces path. The original directory is kept as backup.
+// The settings of config besides backend, path and read_only apply to both stores.
+// Returns the path of the backup.
+pub fn migrate(from: &str, to: &str, path: &str, config: &StoreConfig) -> Result<String, String> {
+    if from == to {
+        return Err(format!("Store is already using the {} backend.", from));
+    }
+    if from == "memory" || to == "memory" {
+        return Err("The memory backend is not persisted and can not be migrated.".to_string());
+    }
+    let path = path.trim_end_matches('/');
+    if path.is_empty() || !Path::new(path).is_dir() {
+        return Err(format!("Store directory \"{}\" does not exist.", path));
+    }
+    let staging_path = format!("{}.migrate", path);
+    let backup_path = format!("{}.{}-backup", path, from);
+    if Path::new(&backup_path).exists() {
+        return Err(format!(
+            "Backup directory \"{}\" of a previous migration exists, remove it first.",
+            backup_path
+        ));
+    }
+
+    // Held until the directories are swapped, a running kvsd holds it as well
+    let _lock = lock_store(path)?;
+
+    // Load the source store, it is not recovered or changed
+    let source = open(config, from, path, true)?;
+    check_dedicated_directory(source.as_ref(), path)?;
+    log_info!(
+        "Migrating {} entries from the {} to the {} backend.",
+        source.size(),
+        from,
+        to
+    );
+
+    // Write all entries to the staging directory, leftovers of an interrupted migration are removed
+    if Path::new(&staging_path).exists() {
+        if let Err(e) = fs::remove_dir_all(&staging_path) {
+            return Err(format!("Could not remove \"{}\": {}", staging_path, e));
+        }
+    }
+    if let Err(e) = fs::create_dir(&staging_path) {
+        return Err(format!("Could not create \"{}\": {}", staging_path, e));
+    }
+    if let Ok(metadata) = fs::metadata(path) {
+        let _ = fs::set_permissions(&staging_path, metadata.permissions());
+    }
+    let result = copy_entries(source.as_ref(), config, to, &staging_path)
+        .and_then(|()| verify(source.as_ref(), config, to, &staging_path));
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(&staging_path);
+        return Err(format!("Migration failed, store left unchanged: {}", e));
+    }
+
+    // Swap the directories, the store is restored if the second rename fails
+    if let Err(e) = fs::rename(path, &backup_path) {
+        let _ = fs::remove_dir_all(&staging_path);
+        return Err(format!(
+            "Could not move \"{}\" to \"{}\": {}",
+            path, backup_path, e
+        ));
+    }
+    if let Err(e) = fs::rename(&staging_path, path) {
+        let _ = fs::rename(&backup_path, path);
+        return Err(format!(
+            "Could not move \"{}\" to \"{}\": {}",
+            staging_path, path, e
+        ));
+    }
+    sync_parent_directory(path);
+    Ok(backup_path)
+}
+
+fn open(
+    config: &StoreConfig,
+    backend: &str,
+    path: &str,
+    read_only: bool,
+) -> Result<Arc<dyn StorageBackend>, String> {
+    let store = create_backend(&StoreConfig {
+        backend: backend.to_string(),
+        path: path.to_string(),
+        read_only,
+        ..config.clone()
+    })?;
+    store.load()?;
+    Ok(store)
+}
+
+// The whole directory is swapped, refuse to migrate if it contains anything besides the store
+fn check_dedicated_directory(store: &dyn StorageBackend, path: &str) -> Result<(), String> {
+    let mut files: HashSet<String> = store.files().into_iter().collect();
+    files.insert(LOCK_FILE.to_string());
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => return Err(format!("Could not read \"{}\": {}", path, e)),
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !files.contains(&name) {
+            return Err(format!(
+                "\"{}\" contains \"{}\" which does not belong to the store, \
+                 only dedicated store directories can be migrated.",
+                path, name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Decrypt the entries and store them in the target backend, BATCH_SIZE entries at a time
+fn copy_entries(
+    source: &dyn StorageBackend,
+    config: &StoreConfig,
+    to: &str,
+    staging_path: &str,
+) -> Result<(), String> {
+    let target = open(config, to, staging_path, false)?;
+    let keys = source.list();
+    for batch in keys.chunks(BATCH_SIZE) {
+        let mut entries = Vec::with_capacity(batch.len());
+        for key in batch {
+            entries.push((key.clone(), source.get(key)?));
+        }
+        target.put_all(entries)?;
+    }
+    target.flush()
+}
+
+// Load the written store again and compare every entry with the source
+fn verify(
+    source: &dyn StorageBackend,
+    config: &StoreConfig,
+    to: &str,
+    staging_path: &str,
+) -> Result<(), String> {
+    let target = open(config, to, staging_path, true)?;
+    if target.size() != source.size() {
+        return Err(format!(
+            "Migrated store contains {} instead of {} entries.",
+            target.size(),
+            source.size()
+        ));
+    }
+    for key in source.list() {
+        if target.get(&key)? != source.get(&key)? {
+            return Err(format!("Value of key \"{}\" differs after migration.", key));
+        }
+    }
+    Ok(())
+}
+
+// Persist the renames
+fn sync_parent_directory(path: &str) {
+    let parent = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().to_string(),
+        _ => ".".to_string(),
+    };
+    let _ = sync_directory(parent);
+}
@@ -4,6 +4,12 @@
 *  Copyright (C) 2020 Benjamin Schilling
 */
 
+pub mod backend;
 pub mod file_store;
 pub mod json_store;
+pub mod log_store;
+pub mod memory_store;
+pub mod migrate;
+pub mod sled_store;
+pub mod sqlite_store;
 pub mod store_actions;
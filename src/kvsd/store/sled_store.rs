@@ -0,0 +1,181 @@
+/*
+*  kvsd sled store Module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+// Embedded database
+use sled::{Batch, Db};
+
+// kvs modules
+use crate::store::backend::StorageBackend;
+use utils::crypto::{json_encrypt, json_try_decrypt};
+use utils::log_error;
+
+// Constants
+// Directory of the database in the store directory
+const DATABASE_DIR: &str = "kvsd.sled";
+// Bytes of the database cached in RAM
+const CACHE_CAPACITY: u64 = 64 * 1024 * 1024;
+
+// Sled backend, the entries are stored in an embedded B-tree database.
+// The keys are stored in plain-text form to allow range scans, the values are encrypted
+// like the values of the JSON backend.
+pub struct SledStore {
+    path: String,
+    // Opened on load
+    db: RwLock<Option<Db>>,
+    // Counting the entries of the database requires a full scan
+    entries: AtomicUsize,
+}
+
+impl SledStore {
+    pub fn new(path: String) -> SledStore {
+        SledStore {
+            path,
+            db: RwLock::new(None),
+            entries: AtomicUsize::new(0),
+        }
+    }
+
+    // Handle of the opened database
+    fn db(&self) -> Result<Db, String> {
+        match self.db.read().unwrap().as_ref() {
+            Some(db) => Ok(db.clone()),
+            None => Err("Database is not opened.".to_string()),
+        }
+    }
+
+    // Flush all changes to the storage device
+    fn sync(db: &Db) -> Result<(), String> {
+        match db.flush() {
+            Ok(_bytes) => Ok(()),
+            Err(e) => Err(format!("Could not flush database: {}", e)),
+        }
+    }
+}
+
+impl StorageBackend for SledStore {
+    fn name(&self) -> &'static str {
+        "sled"
+    }
+
+    // Open the database on start-up, it is created if it does not exist
+    fn load(&self) -> Result<String, String> {
+        let db = match sled::Config::new()
+            .path(format!("{}/{}", self.path, DATABASE_DIR))
+            .cache_capacity(CACHE_CAPACITY)
+            .open()
+        {
+            Ok(db) => db,
+            Err(e) => return Err(format!("Could not open database: {}", e)),
+        };
+        let entries = db.len();
+        self.entries.store(entries, Ordering::SeqCst);
+        *self.db.write().unwrap() = Some(db);
+        Ok(format!("Opened database with {} entries.", entries))
+    }
+
+    fn get(&self, key: &str) -> Result<String, String> {
+        let value = match self.db()?.get(key) {
+            Ok(Some(value)) => value,
+            Ok(None) => return Err("Key not found!".to_string()),
+            Err(e) => return Err(format!("Could not read database: {}", e)),
+        };
+        let decrypted_value = match std::str::from_utf8(&value) {
+            Ok(ciphertext) => json_try_decrypt(ciphertext),
+            Err(_e) => Err("Value is not valid UTF-8.".to_string()),
+        };
+        match decrypted_value {
+            Ok(decrypted_value) => Ok(decrypted_value),
+            Err(e) => {
+                log_error!("Could not decrypt value of key: {}", e; key = key);
+                Err("Value of key is corrupted.".to_string())
+            }
+        }
+    }
+
+    fn put(&self, key: String, value: String) -> Result<(), String> {
+        let db = self.db()?;
+        match db.insert(key, json_encrypt(value).as_bytes()) {
+            Ok(None) => {
+                self.entries.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(Some(_replaced)) => (),
+            Err(e) => return Err(format!("Could not write database: {}", e)),
+        }
+        SledStore::sync(&db)
+    }
+
+    // All entries are written in a single transaction
+    fn put_all(&self, entries: Vec<(String, String)>) -> Result<(), String> {
+        let db = self.db()?;
+        let mut batch = Batch::default();
+        // Keys added by this batch, a key may occur several times
+        let mut added = HashSet::new();
+        for (key, value) in entries {
+            if !added.contains(&key) && !self.exists(&key) {
+                added.insert(key.clone());
+            }
+            batch.insert(key.as_bytes(), json_encrypt(value).as_bytes());
+        }
+        if let Err(e) = db.apply_batch(batch) {
+            return Err(format!("Could not write database: {}", e));
+        }
+        self.entries.fetch_add(added.len(), Ordering::SeqCst);
+        SledStore::sync(&db)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let db = self.db()?;
+        match db.remove(key) {
+            Ok(Some(_removed)) => {
+                self.entries.fetch_sub(1, Ordering::SeqCst);
+            }
+            Ok(None) => return Err("Key not found!".to_string()),
+            Err(e) => return Err(format!("Could not write database: {}", e)),
+        }
+        SledStore::sync(&db)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        match self.db() {
+            Ok(db) => db.contains_key(key).unwrap_or(false),
+            Err(_e) => false,
+        }
+    }
+
+    // The keys are sorted, only the range of the prefix is scanned
+    fn list_prefix(&self, prefix: &str) -> Vec<String> {
+        let db = match self.db() {
+            Ok(db) => db,
+            Err(_e) => return Vec::new(),
+        };
+        db.scan_prefix(prefix)
+            .keys()
+            .flatten()
+            .map(|key| String::from_utf8_lossy(&key).to_string())
+            .collect()
+    }
+
+    fn size(&self) -> usize {
+        self.entries.load(Ordering::SeqCst)
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        SledStore::sync(&self.db()?)
+    }
+
+    fn files(&self) -> Vec<String> {
+        vec![DATABASE_DIR.to_string()]
+    }
+
+    fn holds_values_in_memory(&self) -> bool {
+        false
+    }
+}
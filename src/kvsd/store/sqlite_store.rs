@@ -0,0 +1,242 @@
+/*
+*  kvsd sqlite store Module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::sync::Mutex;
+
+// SQLite
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, NO_PARAMS};
+
+// kvs modules
+use crate::store::backend::StorageBackend;
+use utils::crypto::{json_encrypt, json_try_decrypt};
+use utils::log_error;
+
+// Constants
+const DATABASE_FILE: &str = "kvsd.sqlite";
+// Largest code point, appended to a prefix to get the end of its range of keys
+const CODE_POINT_MAX: u32 = 0x10FFFF;
+
+// SQLite backend, the entries are stored in a single database file.
+// The keys are stored in plain-text form, the values are encrypted like the values of the
+// JSON backend and stored together with their derivation value and IV.
+pub struct SqliteStore {
+    path: String,
+    // Opened on load, connections can not be shared between threads
+    connection: Mutex<Option<Connection>>,
+    // The database is opened read-only, e.g. for the source of a migration
+    read_only: bool,
+}
+
+impl SqliteStore {
+    pub fn new(path: String, read_only: bool) -> SqliteStore {
+        SqliteStore {
+            path,
+            connection: Mutex::new(None),
+            read_only,
+        }
+    }
+
+    // Run a function with the opened connection
+    fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>,
+    ) -> Result<T, String> {
+        match self.connection.lock().unwrap().as_mut() {
+            Some(connection) => match f(connection) {
+                Ok(result) => Ok(result),
+                Err(e) => Err(format!("Database error: {}", e)),
+            },
+            None => Err("Database is not opened.".to_string()),
+        }
+    }
+}
+
+// Insert or replace an entry, the encrypted value is split into derivation value, IV and ciphertext
+fn insert_entry(connection: &Connection, key: &str, value: String) -> rusqlite::Result<usize> {
+    let encrypted_value = json_encrypt(value);
+    let parts: Vec<&str> = encrypted_value.splitn(3, '$').collect();
+    connection.execute(
+        "INSERT OR REPLACE INTO entries (key, derivation_value, initialization_vector, value) \
+         VALUES (?1, ?2, ?3, ?4)",
+        params![key, parts[0], parts[1], parts[2]],
+    )
+}
+
+impl StorageBackend for SqliteStore {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    // Open the database on start-up, it is created if it does not exist.
+    // A read-only database must exist and is not initialized.
+    fn load(&self) -> Result<String, String> {
+        let file_path = format!("{}/{}", self.path, DATABASE_FILE);
+        let opened = if self.read_only {
+            Connection::open_with_flags(&file_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        } else {
+            Connection::open(&file_path)
+        };
+        let connection = match opened {
+            Ok(connection) => connection,
+            Err(e) => return Err(format!("Could not open database: {}", e)),
+        };
+        let initialized = if self.read_only {
+            Ok(())
+        } else {
+            connection
+                .query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |row| {
+                    row.get::<_, String>(0)
+                })
+                .and_then(|_mode| {
+                    connection.execute_batch(
+                        "PRAGMA synchronous = FULL;
+                         CREATE TABLE IF NOT EXISTS entries (
+                             key TEXT PRIMARY KEY NOT NULL,
+                             derivation_value TEXT NOT NULL,
+                             initialization_vector TEXT NOT NULL,
+                             value TEXT NOT NULL
+                         );",
+                    )
+                })
+        };
+        let result = initialized.and_then(|()| {
+            connection.query_row("SELECT COUNT(*) FROM entries", NO_PARAMS, |row| {
+                row.get::<_, i64>(0)
+            })
+        });
+        let entries = match result {
+            Ok(entries) => entries,
+            Err(e) => return Err(format!("Could not initialize database: {}", e)),
+        };
+        *self.connection.lock().unwrap() = Some(connection);
+        Ok(format!("Opened database with {} entries.", entries))
+    }
+
+    fn get(&self, key: &str) -> Result<String, String> {
+        let entry = self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT derivation_value, initialization_vector, value FROM entries \
+                     WHERE key = ?1",
+                    params![key],
+                    |row| {
+                        Ok(format!(
+                            "{}${}${}",
+                            row.get::<_, String>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?
+                        ))
+                    },
+                )
+                .optional()
+        })?;
+        let encrypted_value = match entry {
+            Some(encrypted_value) => encrypted_value,
+            None => return Err("Key not found!".to_string()),
+        };
+        match json_try_decrypt(&encrypted_value) {
+            Ok(decrypted_value) => Ok(decrypted_value),
+            Err(e) => {
+                log_error!("Could not decrypt value of key: {}", e; key = key);
+                Err("Value of key is corrupted.".to_string())
+            }
+        }
+    }
+
+    fn put(&self, key: String, value: String) -> Result<(), String> {
+        self.with_connection(|connection| {
+            let transaction = connection.transaction()?;
+            insert_entry(&transaction, &key, value)?;
+            transaction.commit()
+        })
+    }
+
+    // All entries are written in a single transaction
+    fn put_all(&self, entries: Vec<(String, String)>) -> Result<(), String> {
+        self.with_connection(|connection| {
+            let transaction = connection.transaction()?;
+            for (key, value) in entries {
+                insert_entry(&transaction, &key, value)?;
+            }
+            transaction.commit()
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let deleted = self.with_connection(|connection| {
+            let transaction = connection.transaction()?;
+            let deleted =
+                transaction.execute("DELETE FROM entries WHERE key = ?1", params![key])?;
+            transaction.commit()?;
+            Ok(deleted)
+        })?;
+        if deleted == 0 {
+            return Err("Key not found!".to_string());
+        }
+        Ok(())
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        let entry = self.with_connection(|connection| {
+            connection
+                .query_row(
+                    "SELECT 1 FROM entries WHERE key = ?1",
+                    params![key],
+                    |_row| Ok(()),
+                )
+                .optional()
+        });
+        matches!(entry, Ok(Some(())))
+    }
+
+    // Only the range of the prefix in the index of the keys is scanned
+    fn list_prefix(&self, prefix: &str) -> Vec<String> {
+        let end = format!("{}{}", prefix, std::char::from_u32(CODE_POINT_MAX).unwrap());
+        let keys = self.with_connection(|connection| {
+            let mut statement =
+                connection.prepare("SELECT key FROM entries WHERE key >= ?1 AND key < ?2")?;
+            let rows = statement.query_map(params![prefix, end], |row| row.get::<_, String>(0))?;
+            rows.collect()
+        });
+        match keys {
+            Ok(keys) => keys,
+            Err(e) => {
+                log_error!("Could not list keys: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        let entries = self.with_connection(|connection| {
+            connection.query_row("SELECT COUNT(*) FROM entries", NO_PARAMS, |row| {
+                row.get::<_, i64>(0)
+            })
+        });
+        entries.unwrap_or(0) as usize
+    }
+
+    // Transfer all changes from the write-ahead log to the database file
+    fn flush(&self) -> Result<(), String> {
+        self.with_connection(|connection| {
+            connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", NO_PARAMS, |_row| Ok(()))
+        })
+    }
+
+    fn files(&self) -> Vec<String> {
+        vec![
+            DATABASE_FILE.to_string(),
+            format!("{}-wal", DATABASE_FILE),
+            format!("{}-shm", DATABASE_FILE),
+            format!("{}-journal", DATABASE_FILE),
+        ]
+    }
+
+    fn holds_values_in_memory(&self) -> bool {
+        false
+    }
+}
@@ -4,15 +4,47 @@
 *  Copyright (C) 2020 Benjamin Schilling
 */
 
+// Rust Standard Library
+use std::time::Instant;
+
+// Tokio
+use tokio::sync::mpsc;
+
 // kvs modules
 use crate::grpc::kvs_api::KeyValuePair;
+use crate::trace::RequestContext;
 
 // Available Actions
 pub const ACTION_STORE: u8 = 0;
 pub const ACTION_DELETE: u8 = 1;
+// Persist all pending changes and stop the store handler
+pub const ACTION_SHUTDOWN: u8 = 2;
+// Take a snapshot of all entries, no change is persisted while it is taken
+pub const ACTION_SNAPSHOT: u8 = 3;
+pub const ACTION_NAMES: [&str; 4] = ["store", "delete", "shutdown", "snapshot"];
+
+// Bytes of the key value pairs of a snapshot batch, a larger value gets a batch of its own
+pub const SNAPSHOT_BATCH_SIZE: usize = 1024 * 1024;
+// Snapshot batches buffered for the requester, the store handler waits while the buffer is full
+pub const SNAPSHOT_BATCHES_BUFFERED: usize = 2;
+
+// Entries of a snapshot, sent by the store handler in batches.
+// A snapshot without entries is a single empty batch.
+pub struct SnapshotBatch {
+    pub total_entries: u64,
+    pub entries: Vec<(String, String)>,
+}
+
+// Sends the batches of a snapshot from the store handler, the requester receives them
+pub type SnapshotReply = mpsc::Sender<Result<SnapshotBatch, String>>;
 
-// Action for the two_lock_queue
+// Action for the queue of the store handler
 pub struct QueueAction {
     pub kv: KeyValuePair,
     pub action: u8,
+    // Request the action was queued by
+    pub context: RequestContext,
+    pub queued: Instant,
+    // Receiver of the batches of a snapshot action
+    pub snapshot: Option<SnapshotReply>,
 }
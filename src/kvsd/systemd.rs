@@ -0,0 +1,259 @@
+/*
+*  kvsd systemd module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::env;
+#[cfg(unix)]
+use std::io;
+use std::net::TcpListener;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(unix)]
+use std::os::unix::io::{FromRawFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::os::unix::net::SocketAddr;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// kvs modules
+use utils::{log_error, log_info};
+
+// First file descriptor passed by systemd socket activation
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+// Milliseconds since UNIX epoch of the last store handler loop iteration
+static STORE_HANDLER_HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+
+fn now_millis() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis() as u64,
+        Err(_e) => 0,
+    }
+}
+
+// Send a state to the systemd notification socket, does nothing if not run by systemd
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    let socket_path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_e) => return,
+    };
+    if let Err(e) = send_notification(&socket_path, state) {
+        log_error!("Could not notify systemd about \"{}\": {}", state, e);
+    }
+}
+
+#[cfg(unix)]
+fn send_notification(socket_path: &str, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    // Names of abstract sockets start with "@"
+    match socket_path.strip_prefix('@') {
+        Some(name) => send_to_abstract(&socket, name, state),
+        None => socket
+            .send_to(state.as_bytes(), socket_path)
+            .map(|_sent| ()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_to_abstract(socket: &UnixDatagram, name: &str, state: &str) -> io::Result<()> {
+    let address = SocketAddr::from_abstract_name(name.as_bytes())?;
+    socket
+        .send_to_addr(state.as_bytes(), &address)
+        .map(|_sent| ())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_to_abstract(_socket: &UnixDatagram, _name: &str, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "abstract notification sockets are only supported on Linux",
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+// Notify systemd about a human readable status
+pub fn notify_status(status: String) {
+    notify(format!("STATUS={}", status).as_str());
+}
+
+// Record that the store handler loop is alive
+pub fn store_handler_heartbeat() {
+    STORE_HANDLER_HEARTBEAT.store(now_millis(), Ordering::Relaxed);
+}
+
+// Milliseconds since the store handler loop was alive
+pub fn store_handler_heartbeat_age() -> u64 {
+    now_millis().saturating_sub(STORE_HANDLER_HEARTBEAT.load(Ordering::Relaxed))
+}
+
+// Watchdog interval requested by systemd, None if the watchdog is disabled
+fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog_interval(
+        env::var("WATCHDOG_USEC").ok(),
+        env::var("WATCHDOG_PID").ok(),
+        std::process::id(),
+    )
+}
+
+// The watchdog applies to all processes if WATCHDOG_PID is not set
+fn parse_watchdog_interval(
+    usec: Option<String>,
+    pid: Option<String>,
+    own_pid: u32,
+) -> Option<Duration> {
+    let usec: u64 = usec?.parse().ok()?;
+    if let Some(pid) = pid {
+        if pid != own_pid.to_string() {
+            return None;
+        }
+    }
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec))
+}
+
+// Ping the systemd watchdog as long as the store handler loop is alive.
+// The store handler has to call store_handler_heartbeat() at least once per second.
+pub fn start_watchdog() {
+    let interval = match watchdog_interval() {
+        Some(interval) => interval,
+        None => return,
+    };
+    log_info!("Systemd watchdog enabled, interval: {:?}", interval);
+    store_handler_heartbeat();
+    thread::spawn(move || loop {
+        thread::sleep(interval / 2);
+        let age = store_handler_heartbeat_age();
+        if age < interval.as_millis() as u64 {
+            notify("WATCHDOG=1");
+        } else {
+            log_error!("Store handler did not respond for {} ms.", age);
+            notify_status(format!("Store handler did not respond for {} ms", age));
+        }
+    });
+}
+
+// Listening sockets passed by systemd socket activation
+#[cfg(unix)]
+pub fn listen_sockets() -> Vec<TcpListener> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    // Do not pass the sockets on to child processes
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    let count = parse_listen_fds(pid, fds, std::process::id());
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        // The file descriptors are owned by this process from now on
+        .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+        .collect()
+}
+
+// Number of passed sockets, 0 if they are meant for another process
+#[cfg(unix)]
+fn parse_listen_fds(pid: Option<String>, fds: Option<String>, own_pid: u32) -> RawFd {
+    if pid != Some(own_pid.to_string()) {
+        return 0;
+    }
+    match fds.and_then(|fds| fds.parse().ok()) {
+        Some(count) if count > 0 => count,
+        _ => 0,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn listen_sockets() -> Vec<TcpListener> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn watchdog_interval_ok() {
+        let interval = parse_watchdog_interval(Some("2000000".to_string()), None, 42);
+        assert_eq!(interval, Some(Duration::from_secs(2)));
+        let interval =
+            parse_watchdog_interval(Some("2000000".to_string()), Some("42".to_string()), 42);
+        assert_eq!(interval, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn watchdog_interval_disabled() {
+        assert_eq!(parse_watchdog_interval(None, None, 42), None);
+        assert_eq!(
+            parse_watchdog_interval(Some("0".to_string()), None, 42),
+            None
+        );
+        assert_eq!(
+            parse_watchdog_interval(Some("a".to_string()), None, 42),
+            None
+        );
+        // Meant for another process
+        let interval =
+            parse_watchdog_interval(Some("2000000".to_string()), Some("7".to_string()), 42);
+        assert_eq!(interval, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listen_fds_ok() {
+        assert_eq!(
+            parse_listen_fds(Some("42".to_string()), Some("2".to_string()), 42),
+            2
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listen_fds_ignored() {
+        assert_eq!(parse_listen_fds(None, Some("2".to_string()), 42), 0);
+        assert_eq!(
+            parse_listen_fds(Some("7".to_string()), Some("2".to_string()), 42),
+            0
+        );
+        assert_eq!(parse_listen_fds(Some("42".to_string()), None, 42), 0);
+        assert_eq!(
+            parse_listen_fds(Some("42".to_string()), Some("-1".to_string()), 42),
+            0
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn notify_path_ok() {
+        let path = env::temp_dir().join(format!("kvsd-notify-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        send_notification(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buffer = [0; 64];
+        let received = receiver.recv(&mut buffer).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(&buffer[..received], b"READY=1");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn notify_abstract_ok() {
+        let name = format!("kvsd-notify-test-{}", std::process::id());
+        let address = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let receiver = UnixDatagram::bind_addr(&address).unwrap();
+        send_notification(&format!("@{}", name), "WATCHDOG=1").unwrap();
+        let mut buffer = [0; 64];
+        let received = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..received], b"WATCHDOG=1");
+    }
+}
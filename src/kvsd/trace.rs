@@ -0,0 +1,300 @@
+/*
+*  kvsd trace module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// lazy static
+use lazy_static::lazy_static;
+
+// Random IDs
+use rand::RngCore;
+
+// Tokio
+use tokio::sync::{mpsc, watch};
+use tokio::time;
+
+// tonic
+use tonic::transport::{Channel, Endpoint};
+
+// OTLP imports
+use otlp::span::SpanKind;
+use otlp::status::StatusCode;
+use otlp::trace_service_client::TraceServiceClient;
+use otlp::{
+    any_value, AnyValue, ExportTraceServiceRequest, InstrumentationLibrary,
+    InstrumentationLibrarySpans, KeyValue, Resource, ResourceSpans, Span,
+};
+// Generated code, variants are named after the OTLP specification
+#[allow(clippy::enum_variant_names)]
+pub mod otlp {
+    tonic::include_proto!("opentelemetry.proto.collector.trace.v1");
+}
+
+// kvs modules
+use crate::grpc::broadcast_shutdown_signal;
+use utils::crypto::sha3_256_hex;
+use utils::{log_info, log_warn};
+
+// Metadata key of the request ID in requests and responses, also the HTTP header of the gateway
+pub const REQUEST_ID_KEY: &str = "x-request-id";
+// Longer request IDs are replaced by a generated one
+const REQUEST_ID_LEN_MAX: usize = 128;
+// Finished spans waiting for export, further spans are dropped
+const EXPORT_QUEUE_LEN: usize = 4096;
+// Spans exported with a single request
+const EXPORT_BATCH_LEN: usize = 512;
+// Interval for exporting incomplete batches
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+// Timeout of a single export request
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Set while spans are exported
+static EXPORT_ENABLED: AtomicBool = AtomicBool::new(false);
+// Spans dropped because the export queue was full or the export failed
+static SPANS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref EXPORT_QUEUE: Mutex<Option<mpsc::Sender<Span>>> = Mutex::new(None);
+}
+
+// Identifies a request across the gRPC interface, the queue and the store handler
+#[derive(Clone)]
+pub struct RequestContext {
+    pub request_id: String,
+    pub trace_id: [u8; 16],
+    // Span of the request, parent of the span of the store handler
+    pub span_id: [u8; 8],
+}
+
+impl RequestContext {
+    // Context of a request with the request ID given by the client,
+    // a missing or invalid request ID is replaced by the hex encoded trace ID
+    pub fn new(request_id: Option<&str>) -> RequestContext {
+        let mut span_id = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut span_id);
+        match request_id.filter(|request_id| valid_request_id(request_id)) {
+            Some(request_id) => RequestContext {
+                request_id: request_id.to_string(),
+                trace_id: trace_id_of(request_id),
+                span_id,
+            },
+            None => {
+                let mut trace_id = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut trace_id);
+                RequestContext {
+                    request_id: to_hex(&trace_id),
+                    trace_id,
+                    span_id,
+                }
+            }
+        }
+    }
+}
+
+// Request IDs have to be valid metadata values, only printable ASCII without spaces is allowed
+fn valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= REQUEST_ID_LEN_MAX
+        && request_id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+// Trace ID of a request ID, request IDs which are a hex encoded trace ID are used as is,
+// others are hashed
+fn trace_id_of(request_id: &str) -> [u8; 16] {
+    if let Some(trace_id) = parse_trace_id(request_id) {
+        if trace_id.iter().any(|b| *b != 0) {
+            return trace_id;
+        }
+    }
+    parse_trace_id(&sha3_256_hex(request_id.as_bytes())[..32]).unwrap()
+}
+
+fn parse_trace_id(hex: &str) -> Option<[u8; 16]> {
+    if hex.len() != 32 || !hex.is_ascii() {
+        return None;
+    }
+    let mut trace_id = [0u8; 16];
+    for (i, byte) in trace_id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(trace_id)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Whether finished spans are exported
+pub fn enabled() -> bool {
+    EXPORT_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn string_attribute(key: &str, value: String) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value)),
+        }),
+    }
+}
+
+pub fn int_attribute(key: &str, value: i64) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::IntValue(value)),
+        }),
+    }
+}
+
+// Record the span of a request received by the gRPC interface or the HTTP gateway,
+// a span with an error message is marked as failed
+pub fn record_request_span(
+    context: &RequestContext,
+    name: String,
+    start: SystemTime,
+    attributes: Vec<KeyValue>,
+    error: Option<String>,
+) {
+    record_span(Span {
+        trace_id: context.trace_id.to_vec(),
+        span_id: context.span_id.to_vec(),
+        name,
+        kind: SpanKind::Server as i32,
+        start_time_unix_nano: unix_nanos(start),
+        end_time_unix_nano: unix_nanos(SystemTime::now()),
+        attributes,
+        status: error.map(|message| otlp::Status {
+            message,
+            code: StatusCode::Error as i32,
+        }),
+        ..Span::default()
+    });
+}
+
+// Record a span of the store handler as child of the span of the request
+pub fn record_handler_span(
+    context: &RequestContext,
+    name: String,
+    start: SystemTime,
+    attributes: Vec<KeyValue>,
+) {
+    let mut span_id = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut span_id);
+    record_span(Span {
+        trace_id: context.trace_id.to_vec(),
+        span_id: span_id.to_vec(),
+        parent_span_id: context.span_id.to_vec(),
+        name,
+        kind: SpanKind::Internal as i32,
+        start_time_unix_nano: unix_nanos(start),
+        end_time_unix_nano: unix_nanos(SystemTime::now()),
+        attributes,
+        ..Span::default()
+    });
+}
+
+// Queue a span for export, spans are dropped while the export is behind
+fn record_span(span: Span) {
+    if let Some(sender) = EXPORT_QUEUE.lock().unwrap().as_mut() {
+        if sender.try_send(span).is_err() {
+            SPANS_DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_nanos() as u64,
+        Err(_e) => 0,
+    }
+}
+
+// Export finished spans to an OTLP collector until shutdown is signaled
+pub async fn export_spans(
+    endpoint: String,
+    service_name: String,
+    shutdown: watch::Receiver<bool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // The collector may start after kvsd, connect on the first export
+    let channel = Endpoint::from_shared(endpoint.clone())?.connect_lazy()?;
+    let mut client = TraceServiceClient::new(channel);
+    let (sender, mut receiver) = mpsc::channel(EXPORT_QUEUE_LEN);
+    *EXPORT_QUEUE.lock().unwrap() = Some(sender);
+    EXPORT_ENABLED.store(true, Ordering::Relaxed);
+    log_info!("Exporting spans to {}", endpoint);
+
+    let signal = broadcast_shutdown_signal(shutdown);
+    tokio::pin!(signal);
+    let mut interval = time::interval(EXPORT_INTERVAL);
+    let mut batch = Vec::new();
+    loop {
+        tokio::select! {
+            _ = &mut signal => break,
+            span = receiver.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < EXPORT_BATCH_LEN {
+                        continue;
+                    }
+                }
+                None => break,
+            },
+            _ = interval.tick() => (),
+        }
+        export_batch(&mut client, &service_name, &mut batch).await;
+    }
+    // Export the spans of all requests answered before the shutdown
+    EXPORT_ENABLED.store(false, Ordering::Relaxed);
+    *EXPORT_QUEUE.lock().unwrap() = None;
+    while let Ok(span) = receiver.try_recv() {
+        batch.push(span);
+    }
+    export_batch(&mut client, &service_name, &mut batch).await;
+    let dropped = SPANS_DROPPED.load(Ordering::Relaxed);
+    if dropped > 0 {
+        log_warn!("{} spans were dropped and not exported.", dropped);
+    }
+    Ok(())
+}
+
+async fn export_batch(
+    client: &mut TraceServiceClient<Channel>,
+    service_name: &str,
+    batch: &mut Vec<Span>,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    let count = batch.len();
+    let request = ExportTraceServiceRequest {
+        resource_spans: vec![ResourceSpans {
+            resource: Some(Resource {
+                attributes: vec![
+                    string_attribute("service.name", service_name.to_string()),
+                    string_attribute("service.version", clap::crate_version!().to_string()),
+                ],
+            }),
+            instrumentation_library_spans: vec![InstrumentationLibrarySpans {
+                instrumentation_library: Some(InstrumentationLibrary {
+                    name: "kvsd".to_string(),
+                    version: clap::crate_version!().to_string(),
+                }),
+                spans: batch.split_off(0),
+            }],
+        }],
+    };
+    let error = match time::timeout(EXPORT_TIMEOUT, client.export(request)).await {
+        Ok(Ok(_response)) => return,
+        Ok(Err(status)) => status.message().to_string(),
+        Err(_elapsed) => "timeout".to_string(),
+    };
+    SPANS_DROPPED.fetch_add(count as u64, Ordering::Relaxed);
+    log_warn!("Could not export {} spans: {}", count, error);
+}
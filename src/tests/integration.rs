@@ -68,6 +68,7 @@ mod tests {
         let mut _result: bool = false;
         // Store key
         _result = run_kvsc_store(key.clone(), value);
+        wait_for_store_handler();
         // Get key
         _result = run_kvsc_get(key.clone());
         // Delete key
@@ -76,13 +77,354 @@ mod tests {
         kvsd_process.kill().expect("command wasn't running");
         assert_eq!(_result, true);
     }
+    // Tests that orphaned files are removed and damaged entries are quarantined on start-up
+    #[test]
+    fn integration_file_store_recovery() {
+        let mut kvsd_process = match init_for_file() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        run_kvsc_store("firstkey".to_string(), "value".to_string());
+        run_kvsc_store("secondkey".to_string(), "value".to_string());
+        wait_for_store_handler();
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+
+        // Damage one of the value files and add an orphaned one
+        let damaged_path = find_value_files("test_temp_dir")[0].clone();
+        let damaged = std::path::Path::new(&damaged_path)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        std::fs::write(&damaged_path, "AAAA").unwrap();
+        let orphaned = "test_temp_dir/0123456789abcdef0123456789abcdef";
+        std::fs::write(orphaned, "AAAA").unwrap();
+
+        // Without verify the damaged value file is only detected when it is read
+        let mut kvsd_process = match restart_for_file() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let unverified_first = run_kvsc_get("firstkey".to_string());
+        let unverified_second = run_kvsc_get("secondkey".to_string());
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+        let quarantine_path = format!("test_temp_dir/quarantine/{}", damaged);
+        let unverified_quarantined = std::path::Path::new(&quarantine_path).exists();
+
+        let mut kvsd_process = match restart_for_file_verify() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let result_first = run_kvsc_get("firstkey".to_string());
+        let result_second = run_kvsc_get("secondkey".to_string());
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(std::path::Path::new(orphaned).exists(), false);
+        assert_eq!(unverified_quarantined, false);
+        assert_eq!(unverified_first != unverified_second, true);
+        assert_eq!(std::path::Path::new(&quarantine_path).exists(), true);
+        // Only the entry with the damaged value file is gone
+        assert_eq!(result_first != result_second, true);
+    }
+
+    // Tests that the value files of a store using the flat layout are moved to the subdirectories
+    #[test]
+    fn integration_file_store_flat_layout_migration() {
+        let mut kvsd_process = match init_for_file() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        run_kvsc_store("firstkey".to_string(), "first".to_string());
+        run_kvsc_store("secondkey".to_string(), "second".to_string());
+        wait_for_store_handler();
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+
+        // Move the value files back to the store directory
+        for file in find_value_files("test_temp_dir") {
+            let name = std::path::Path::new(&file).file_name().unwrap().to_owned();
+            std::fs::rename(&file, std::path::Path::new("test_temp_dir").join(name)).unwrap();
+        }
+        std::fs::remove_dir_all("test_temp_dir/values").unwrap();
+
+        let mut kvsd_process = match restart_for_file() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let result_first = run_kvsc_get("firstkey".to_string());
+        let result_second = run_kvsc_get("secondkey".to_string());
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(result_first, true);
+        assert_eq!(result_second, true);
+        assert_eq!(find_value_files("test_temp_dir").len(), 2);
+    }
+
+    // Tests that identical values share a value file which is deleted with the last key,
+    // the references are restored on start-up
+    #[test]
+    fn integration_file_store_dedup() {
+        let mut kvsd_process = match init_for_dedup() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        run_kvsc_store("firstkey".to_string(), "certificate".to_string());
+        run_kvsc_store("secondkey".to_string(), "certificate".to_string());
+        run_kvsc_store("thirdkey".to_string(), "firmware".to_string());
+        wait_for_store_handler();
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+        let files_stored = find_value_files("test_temp_dir").len();
+
+        let mut kvsd_process = match restart_for_file() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        run_kvsc_delete("firstkey".to_string());
+        wait_for_store_handler();
+        let result_second = run_kvsc_get("secondkey".to_string());
+        let files_first_deleted = find_value_files("test_temp_dir").len();
+        run_kvsc_delete("secondkey".to_string());
+        wait_for_store_handler();
+        let files_second_deleted = find_value_files("test_temp_dir").len();
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(files_stored, 2);
+        assert_eq!(result_second, true);
+        assert_eq!(files_first_deleted, 2);
+        assert_eq!(files_second_deleted, 1);
+    }
+
+    // Tests that SIGTERM persists all acknowledged changes before kvsd exits
+    #[cfg(unix)]
+    #[test]
+    fn integration_shutdown_drains_queue() {
+        let kvsd_process = match init_for_file() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let stored = run_kvsc_store_burst(20);
+        let terminated = terminate_kvsd(kvsd_process);
+
+        let mut kvsd_process = match restart_for_file() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let mut all_found = true;
+        for x in 0..20 {
+            all_found &= run_kvsc_get(format!("burstkey{}", x));
+        }
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+        assert_eq!(stored, true);
+        assert_eq!(terminated, true);
+        assert_eq!(all_found, true);
+    }
+
+    // Tests that compressed values are flagged and still decrypted once compression is disabled
+    #[test]
+    fn integration_file_store_compression() {
+        let mut kvsd_process = match init_for_compression(64) {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let stored = run_kvsc_store("compressed".to_string(), "a".repeat(1000));
+        wait_for_store_handler();
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+        let value_file = find_value_files("test_temp_dir")[0].clone();
+        let content = std::fs::read_to_string(value_file).unwrap();
+
+        let mut kvsd_process = match restart_for_file() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let result = run_kvsc_get("compressed".to_string());
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(stored, true);
+        assert_eq!(content.starts_with("z:"), true);
+        assert_eq!(content.len() < 1000, true);
+        assert_eq!(result, true);
+    }
+
+    // ============== Basic Functionality Log Backend ==============
+    // This sections contains end to end tests that verify specific
+    // log backend behaviour when using kvsc and kvsd
+
+    // Test all kvsc subcommands
+    #[test]
+    fn integration_log_store_get_delete() {
+        let mut kvsd_process = match init_for_log() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        // Key Value Pair
+        let key: String = "testkey".to_string();
+        let value: String = "testvalue".to_string();
+
+        // Result
+        let mut _result: bool = false;
+        // Store key
+        _result = run_kvsc_store(key.clone(), value);
+        wait_for_store_handler();
+        // Get key
+        _result = run_kvsc_get(key.clone());
+        // Delete key
+        _result = run_kvsc_delete(key.clone());
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(_result, true);
+    }
+
+    // Tests that the index is rebuilt from the segments after a restart
+    #[test]
+    fn integration_log_store_restart() {
+        let mut kvsd_process = match init_for_log() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        run_kvsc_store("keptkey".to_string(), "first".to_string());
+        run_kvsc_store("keptkey".to_string(), "second".to_string());
+        run_kvsc_store("deletedkey".to_string(), "value".to_string());
+        run_kvsc_delete("deletedkey".to_string());
+        wait_for_store_handler();
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+
+        let mut kvsd_process = match restart_for_log() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let result_kept = run_kvsc_get("keptkey".to_string());
+        let result_deleted = run_kvsc_get("deletedkey".to_string());
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(result_kept, true);
+        assert_eq!(result_deleted, false);
+    }
+    // ============== Basic Functionality Sled Backend ==============
+    // This sections contains end to end tests that verify specific
+    // sled backend behaviour when using kvsc and kvsd
+
+    // Test all kvsc subcommands
+    #[test]
+    fn integration_sled_store_get_delete() {
+        let mut kvsd_process = match init_for_backend("sled") {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        // Key Value Pair
+        let key: String = "testkey".to_string();
+        let value: String = "testvalue".to_string();
+
+        // Result
+        let mut _result: bool = false;
+        // Store key
+        _result = run_kvsc_store(key.clone(), value);
+        wait_for_store_handler();
+        // Get key
+        _result = run_kvsc_get(key.clone());
+        // Delete key
+        _result = run_kvsc_delete(key.clone());
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(_result, true);
+    }
+    // ============== Basic Functionality SQLite Backend ==============
+    // This sections contains end to end tests that verify specific
+    // sqlite backend behaviour when using kvsc and kvsd
+
+    // Test all kvsc subcommands
+    #[test]
+    fn integration_sqlite_store_get_delete() {
+        let mut kvsd_process = match init_for_backend("sqlite") {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        // Key Value Pair
+        let key: String = "testkey".to_string();
+        let value: String = "testvalue".to_string();
+
+        // Result
+        let mut _result: bool = false;
+        // Store key
+        _result = run_kvsc_store(key.clone(), value);
+        wait_for_store_handler();
+        // Get key
+        _result = run_kvsc_get(key.clone());
+        // Delete key
+        _result = run_kvsc_delete(key.clone());
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(_result, true);
+    }
+    // ============== Basic Functionality Memory Backend ==============
+    // This sections contains end to end tests that verify specific
+    // memory backend behaviour when using kvsc and kvsd
+
+    // Test all kvsc subcommands
+    #[test]
+    fn integration_memory_store_get_delete() {
+        let mut kvsd_process = match init_for_memory() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        // Key Value Pair
+        let key: String = "testkey".to_string();
+        let value: String = "testvalue".to_string();
+
+        // Result
+        let mut _result: bool = false;
+        // Store key
+        _result = run_kvsc_store(key.clone(), value);
+        wait_for_store_handler();
+        // Get key
+        _result = run_kvsc_get(key.clone());
+        // Delete key
+        _result = run_kvsc_delete(key.clone());
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(_result, true);
+    }
+    // Tests that the entries of the memory backend are not persisted
+    #[test]
+    fn integration_memory_store_restart() {
+        let mut kvsd_process = match init_for_memory() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let key: String = "testkey".to_string();
+        let stored: bool = run_kvsc_store(key.clone(), "testvalue".to_string());
+        wait_for_store_handler();
+        kvsd_process.kill().expect("command wasn't running");
+        kvsd_process.wait().expect("command wasn't running");
+
+        let mut kvsd_process = match init_for_memory() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let result: bool = run_kvsc_get(key);
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(stored, true);
+        assert_eq!(result, false);
+    }
     // ============== Client Tests ==============
     // This section contains test that verify specific kvsc behaviour
 
     // Tests that the client returns a failed status if the to be returned key is not found
     #[test]
     fn integration_client_get_not_found() {
-        let mut kvsd_process = match init_for_json() {
+        let mut kvsd_process = match init_for_memory() {
             Ok(child) => child,
             Err(()) => return,
         };
@@ -97,7 +439,7 @@ mod tests {
     // Tests that the client returns a failed status if the to be deleted key is not found
     #[test]
     fn integration_client_delete_not_found() {
-        let mut kvsd_process = match init_for_json() {
+        let mut kvsd_process = match init_for_memory() {
             Ok(child) => child,
             Err(()) => return,
         };
@@ -110,6 +452,39 @@ mod tests {
         assert_eq!(result, false);
     }
 
+    // Tests that kvsc reports and applies the limits configured in kvsd
+    #[test]
+    fn integration_client_limits() {
+        let mut kvsd_process = match init_for_limits(40, 16, 1000) {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let limits = run_kvsc_limits().unwrap_or_default();
+        // Keys longer than the default limit are accepted up to the configured limit
+        let long_key = run_kvsc_store("k".repeat(40), "value".to_string());
+        let too_long_key = run_kvsc_store("k".repeat(41), "value".to_string());
+        let too_long_value = run_kvsc_store("testkey".to_string(), "v".repeat(17));
+        wait_for_store_handler();
+        // Further entries are refused once the memory budget is exhausted
+        let mut refused = false;
+        for x in 0..20 {
+            if !run_kvsc_store(format!("key_{}", x), "value".to_string()) {
+                refused = true;
+                break;
+            }
+            wait_for_store_handler();
+        }
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(limits.contains("Backend: memory"), true);
+        assert_eq!(limits.contains("Maximum key length: 40"), true);
+        assert_eq!(limits.contains("Maximum store bytes: 1000"), true);
+        assert_eq!(long_key, true);
+        assert_eq!(too_long_key, false);
+        assert_eq!(too_long_value, false);
+        assert_eq!(refused, true);
+    }
+
     // Test piping a file in kvsc store, kvsc getting it and writing it to the filesystem again.
     // Afterwards the original and retrieved file have to be identical.
     #[test]
@@ -128,6 +503,7 @@ mod tests {
             "test_config_file_ini".to_string(),
             "src/tests/data/test_config_file.ini".to_string(),
         );
+        wait_for_store_handler();
         // If storing it was successful, retrieve the value and write it to file
         println!("Retrieve file and store it in test_temp_dir/retrieved_config.ini.");
         if _result {
@@ -187,4 +563,268 @@ mod tests {
         kvsd_process.kill().expect("command wasn't running");
         assert_eq!(diff_files(&mut original, &mut retrieved), true);
     }
+
+    // ============== Configuration Tests ==============
+    // This section contains test that verify the kvsd configuration file handling
+
+    // Tests that a valid configuration file is accepted
+    #[test]
+    fn integration_check_config_valid() {
+        let result: bool =
+            run_kvsd_check_config("src/tests/data/test_kvsd_config.toml".to_string());
+        assert_eq!(result, true);
+    }
+    // Tests that an invalid configuration file is rejected
+    #[test]
+    fn integration_check_config_invalid() {
+        let result: bool =
+            run_kvsd_check_config("src/tests/data/test_kvsd_config_invalid.toml".to_string());
+        assert_eq!(result, false);
+    }
+    // Tests that a missing configuration file is rejected
+    #[test]
+    fn integration_check_config_missing() {
+        let result: bool = run_kvsd_check_config("src/tests/data/does_not_exist.toml".to_string());
+        assert_eq!(result, false);
+    }
+
+    // ============== HTTP Gateway Tests ==============
+    // This section contains test that verify the HTTP/JSON gateway of kvsd
+
+    // Tests storing, getting and deleting a key via HTTP
+    #[test]
+    fn integration_http_put_get_delete() {
+        let mut kvsd_process = match init_for_http() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let key: String = "httpkey".to_string();
+        let put = run_curl(
+            "PUT",
+            key.clone(),
+            Some("{\"value\": \"httpvalue\"}".to_string()),
+        );
+        let get = run_curl("GET", key.clone(), None);
+        let delete = run_curl("DELETE", key.clone(), None);
+        let get_deleted = run_curl("GET", key, None);
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(put, 200);
+        assert_eq!(get, 200);
+        assert_eq!(delete, 200);
+        assert_eq!(get_deleted, 404);
+    }
+    // Tests that requests over the client rate limit are rejected with a retry hint
+    #[test]
+    fn integration_rate_limit_client() {
+        let mut kvsd_process = match init_for_rate_limit() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        // kvsc requests the limits before storing, which takes both requests of the second
+        let stored = run_kvsc_store("ratekey".to_string(), "ratevalue".to_string());
+        let (limited, retry_after) = run_curl_retry_after("ratekey".to_string());
+        // The bucket is refilled after a second
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        let (refilled, _retry_after) = run_curl_retry_after("ratekey".to_string());
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(stored, true);
+        assert_eq!(limited, 429);
+        assert_eq!(retry_after.is_some(), true);
+        assert_eq!(retry_after.unwrap() <= 1001, true);
+        assert_eq!(refilled, 200);
+    }
+    // Tests that invalid requests are rejected with the mirrored gRPC status
+    #[test]
+    fn integration_http_invalid_request() {
+        let mut kvsd_process = match init_for_http() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let invalid_body = run_curl("PUT", "httpkey".to_string(), Some("value".to_string()));
+        let invalid_key = run_curl("GET", "http$key".to_string(), None);
+        let invalid_method = run_curl("POST", "httpkey".to_string(), None);
+        let too_large = format!("{{\"value\": \"{}\"}}", "x".repeat(10000));
+        let too_large_body = run_curl("PUT", "httpkey".to_string(), Some(too_large));
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(invalid_body, 400);
+        assert_eq!(invalid_key, 400);
+        assert_eq!(invalid_method, 405);
+        assert_eq!(too_large_body, 413);
+    }
+    // Tests that the request ID is returned and generated if missing or invalid
+    #[test]
+    fn integration_http_request_id() {
+        let mut kvsd_process = match init_for_http() {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let given = run_curl_request_id("httpkey".to_string(), Some("client-request-1"));
+        let generated = run_curl_request_id("httpkey".to_string(), None);
+        let too_long = "x".repeat(200);
+        let replaced = run_curl_request_id("httpkey".to_string(), Some(too_long.as_str()));
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+        assert_eq!(given, Some("client-request-1".to_string()));
+        assert_eq!(generated.map(|id| id.len()), Some(32));
+        assert_eq!(replaced.map(|id| id.len()), Some(32));
+    }
+
+    // ============== Audit Log Tests ==============
+    // This section contains test that verify the audit log of kvsd
+
+    // Tests that the audit log records requests without values and detects tampering
+    #[test]
+    fn integration_audit_log_verify() {
+        let audit_log = "test_temp_dir/audit.log";
+        let mut kvsd_process = match init_for_audit(audit_log) {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let key: String = "auditkey".to_string();
+        run_kvsc_store(key.clone(), "auditvalue".to_string());
+        run_kvsc_get(key.clone());
+        run_kvsc_delete(key.clone());
+        run_kvsc_get(key);
+        // Kill kvsd
+        kvsd_process.kill().expect("command wasn't running");
+
+        let content = std::fs::read_to_string(audit_log).unwrap();
+        assert_eq!(content.lines().count(), 4);
+        assert_eq!(content.contains("auditvalue"), false);
+        assert_eq!(content.contains("\"result\":\"NOT_FOUND\""), true);
+        assert_eq!(run_kvsd_verify_audit_log(audit_log), true);
+
+        // Change the key of the first entry
+        let tampered = content.replacen("auditkey", "otherkey", 1);
+        std::fs::write(audit_log, tampered).unwrap();
+        assert_eq!(run_kvsd_verify_audit_log(audit_log), false);
+    }
+
+    // ============== Migration Tests ==============
+    // This section contains tests that verify migrating a store between backends
+
+    // Tests that a store migrated to the file backend and back keeps its entries
+    #[test]
+    fn integration_migrate_json_file() {
+        let path = "test_temp_dir_migrate";
+        init_for_migrate(path);
+        let key: String = "migratekey".to_string();
+        let mut kvsd_process = match run_kvsd_with_store("json", path) {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        assert_eq!(
+            run_kvsc_store(key.clone(), "migratevalue".to_string()),
+            true
+        );
+        wait_for_store_handler();
+        // Migrating the store of a running kvsd fails
+        assert_eq!(run_kvsd_migrate("json", "file", path), false);
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+
+        // Migrating to the same backend fails
+        assert_eq!(run_kvsd_migrate("json", "json", path), false);
+        assert_eq!(run_kvsd_migrate("json", "file", path), true);
+        assert_eq!(
+            std::path::Path::new("test_temp_dir_migrate.json-backup").exists(),
+            true
+        );
+        let mut kvsd_process = match run_kvsd_with_store("file", path) {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let result_file = run_kvsc_get(key.clone());
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+
+        assert_eq!(run_kvsd_migrate("file", "json", path), true);
+        let mut kvsd_process = match run_kvsd_with_store("json", path) {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let result_json = run_kvsc_get(key);
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+        init_for_migrate(path);
+        std::fs::remove_dir(path).unwrap();
+
+        assert_eq!(result_file, true);
+        assert_eq!(result_json, true);
+    }
+
+    // ============== Backup ==============
+    // Tests that an archive of kvsc backup and a scheduled snapshot restore all entries
+    #[cfg(unix)]
+    #[test]
+    fn integration_backup_restore() {
+        let backup_path = "test_temp_dir_backup";
+        let restore_path = "test_temp_dir_restore";
+        for dir in &[backup_path, restore_path] {
+            if std::path::Path::new(dir).exists() {
+                std::fs::remove_dir_all(dir).unwrap();
+            }
+        }
+        std::fs::create_dir(backup_path).unwrap();
+        let key_file = "test_temp_dir_backup/backup.key";
+        let archive = "test_temp_dir_backup/kvs.kvsbak";
+        let snapshot_path = "test_temp_dir_backup/snapshots";
+        let socket = "test_temp_dir_backup/kvsd.sock";
+        write_backup_key(key_file);
+        let mut kvsd_process = match init_for_scheduled_snapshots(snapshot_path, key_file, socket) {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        run_kvsc_store("backupkey".to_string(), "backupvalue".to_string());
+        run_kvsc_store("largebackupkey".to_string(), "b".repeat(10000));
+        wait_for_store_handler();
+        // Snapshots are not served on the plaintext TCP listener
+        let backed_up_tcp = run_kvsc_backup("test_temp_dir_backup/tcp.kvsbak", key_file, None);
+        let backed_up = run_kvsc_backup(archive, key_file, Some(socket));
+        // Wait for further scheduled snapshots, older ones are pruned
+        std::thread::sleep(std::time::Duration::from_millis(3500));
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+        let mut snapshots: Vec<String> = std::fs::read_dir(snapshot_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().path().to_string_lossy().to_string())
+            .collect();
+        snapshots.sort();
+
+        // Only empty stores can be restored
+        let restored_existing = run_kvsd_restore(archive, "test_temp_dir", key_file);
+        let restored_archive = run_kvsd_restore(archive, restore_path, key_file);
+        let mut kvsd_process = match run_kvsd_with_store("file", restore_path) {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let result_archive =
+            run_kvsc_get("backupkey".to_string()) && run_kvsc_get("largebackupkey".to_string());
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+
+        std::fs::remove_dir_all(restore_path).unwrap();
+        let restored_snapshot = run_kvsd_restore(&snapshots[1], restore_path, key_file);
+        let mut kvsd_process = match run_kvsd_with_store("file", restore_path) {
+            Ok(child) => child,
+            Err(()) => return,
+        };
+        let result_snapshot = run_kvsc_get("largebackupkey".to_string());
+        kvsd_process.kill().expect("command wasn't running");
+        let _ = kvsd_process.wait();
+        std::fs::remove_dir_all(backup_path).unwrap();
+        std::fs::remove_dir_all(restore_path).unwrap();
+
+        assert_eq!(backed_up_tcp, false);
+        assert_eq!(backed_up, true);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(restored_existing, false);
+        assert_eq!(restored_archive, true);
+        assert_eq!(result_archive, true);
+        assert_eq!(restored_snapshot, true);
+        assert_eq!(result_snapshot, true);
+    }
 }
@@ -247,4 +247,47 @@ mod tests {
     println!("Test took: {:?}", instant_overall.elapsed());
   }
   // Test the performance of the file store with 10.000 entries (each biggest size)
+
+  // ============== Load Tests LOG Backend ==============
+
+  // Test that the log store accepts more entries and longer values than a JSON store
+  #[test]
+  fn performance_log_beyond_json_limits() {
+    let instant_overall = Instant::now();
+    let mut kvsd_process = match init_for_log() {
+      Ok(child) => child,
+      Err(()) => return,
+    };
+    // Result
+    let mut _result: bool = false;
+
+    println!("Adding 10.001 entries, this may take a while.");
+    // Add 10.001 entries
+    for x in 0..10001 {
+      // Key Value Pair
+      let mut key: String = "testkey".to_string();
+      let value: String = "testvalue".to_string();
+      key = key + &format!("{}", x);
+      // Store key
+      _result = run_kvsc_store(key.clone(), value);
+      // Check that all additions were successfull
+      if _result == false {
+        println!("Failed adding key: {}", key.clone());
+      }
+    }
+    let result_entries = run_kvsc_get("testkey10000".to_string());
+
+    // Add a value of 1025 characters
+    let mut value: String = String::new();
+    for _x in 0..1025 {
+      value = value + "a";
+    }
+    let result_value_length = run_kvsc_store("longkey".to_string(), value);
+
+    // Kill kvsd
+    kvsd_process.kill().expect("command wasn't running");
+    println!("Test took: {:?}", instant_overall.elapsed());
+    assert_eq!(result_entries, true);
+    assert_eq!(result_value_length, true);
+  }
 }
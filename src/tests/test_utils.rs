@@ -17,6 +17,7 @@ const TEST_DIR_PATH: &str = "./test_temp_dir/";
 // Supported backends
 const BACKEND_JSON: u8 = 0;
 const BACKEND_FILE: u8 = 1;
+const BACKEND_LOG: u8 = 2;
 
 // ============== Test Utils ==============
 
@@ -41,8 +42,8 @@ fn clean_up(backend: u8, path: String) {
             Ok(_o) => println!("Clean-up store at {} done.", store_path),
             Err(_e) => eprintln!("Cleaning up store failed."),
         }
-    } else if backend == BACKEND_FILE {
-        // for File backend delete meta data and files
+    } else if backend == BACKEND_FILE || backend == BACKEND_LOG {
+        // for File and log backend delete meta data, files and segments
         if !Path::new(&path).exists() {
             println!("Nothing to clean up.");
             return;
@@ -70,6 +71,12 @@ fn run_kvsd(backend: u8, path: String) -> Result<Child, ()> {
             .spawn()
             .expect("Failed to start kvsd process.");
         return Ok(kvsd_process);
+    } else if backend == BACKEND_LOG {
+        let kvsd_process = Command::new("target/release/kvsd")
+            .args(&["--backend", "log", "--path", path.as_str()])
+            .spawn()
+            .expect("Failed to start kvsd process.");
+        return Ok(kvsd_process);
     } else {
         eprintln!("Invalid Backend.");
         Err(())
@@ -89,12 +96,31 @@ fn runs_kvsd_silent(backend: u8, path: String) -> Result<Child, ()> {
             .spawn()
             .expect("Failed to start kvsd process.");
         return Ok(kvsd_process);
+    } else if backend == BACKEND_LOG {
+        let kvsd_process = Command::new("target/release/kvsd")
+            .args(&["--silent", "--backend", "log", "--path", path.as_str()])
+            .spawn()
+            .expect("Failed to start kvsd process.");
+        return Ok(kvsd_process);
     } else {
         eprintln!("Invalid Backend.");
         Err(())
     }
 }
 
+// Run kvsd with the check-config option
+pub fn run_kvsd_check_config(config_file: String) -> bool {
+    let status = Command::new("target/release/kvsd")
+        .args(&["--config", config_file.as_str(), "--check-config"])
+        .status()
+        .expect("Failed to start kvsd process.");
+    if status.success() {
+        true
+    } else {
+        false
+    }
+}
+
 // Run kvsc with the store subcommand
 pub fn run_kvsc_store(key: String, value: String) -> bool {
     let status = Command::new("target/release/kvsc")
@@ -169,6 +195,19 @@ pub fn run_kvsc_get(key: String) -> bool {
     }
 }
 
+// Run kvsc with the limits subcommand, returns the printed limits
+pub fn run_kvsc_limits() -> Option<String> {
+    let output = Command::new("target/release/kvsc")
+        .args(&["--silent", "limits"])
+        .output()
+        .expect("Failed to start kvsc process.");
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        None
+    }
+}
+
 // Run kvsc with the delete subcommand
 pub fn run_kvsc_delete(key: String) -> bool {
     let status = Command::new("target/release/kvsc")
@@ -204,8 +243,455 @@ pub fn init_for_file() -> Result<Child, ()> {
     return child;
 }
 
+// Initialize the kvsd with a file backend compressing values of at least threshold bytes
+pub fn init_for_compression(threshold: usize) -> Result<Child, ()> {
+    clean_up(BACKEND_FILE, TEST_DIR_PATH.to_string());
+    init_dir(TEST_DIR_PATH.to_string());
+    let child = Command::new("target/release/kvsd")
+        .args(&[
+            "--silent",
+            "--backend",
+            "file",
+            "--path",
+            TEST_DIR_PATH,
+            "--compression-threshold",
+            threshold.to_string().as_str(),
+        ])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Initialize the kvsd with a file backend sharing the value file of identical values
+pub fn init_for_dedup() -> Result<Child, ()> {
+    clean_up(BACKEND_FILE, TEST_DIR_PATH.to_string());
+    init_dir(TEST_DIR_PATH.to_string());
+    let child = Command::new("target/release/kvsd")
+        .args(&[
+            "--silent",
+            "--backend",
+            "file",
+            "--path",
+            TEST_DIR_PATH,
+            "--dedup",
+        ])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Restart the kvsd with a file backend keeping the existing store
+pub fn restart_for_file() -> Result<Child, ()> {
+    let child = runs_kvsd_silent(BACKEND_FILE, TEST_DIR_PATH.to_string());
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    return child;
+}
+
+// Restart the kvsd with a file backend authenticating all value files on start-up
+pub fn restart_for_file_verify() -> Result<Child, ()> {
+    let child = Command::new("target/release/kvsd")
+        .args(&[
+            "--silent",
+            "--backend",
+            "file",
+            "--path",
+            TEST_DIR_PATH,
+            "--verify",
+        ])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Initialize the kvsd with a log backend
+pub fn init_for_log() -> Result<Child, ()> {
+    // deletes directory, clean up is called before creating directory
+    clean_up(BACKEND_LOG, TEST_DIR_PATH.to_string());
+    init_dir(TEST_DIR_PATH.to_string());
+    let child = runs_kvsd_silent(BACKEND_LOG, TEST_DIR_PATH.to_string());
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    return child;
+}
+
+// Restart the kvsd with a log backend keeping the existing store
+pub fn restart_for_log() -> Result<Child, ()> {
+    let child = runs_kvsd_silent(BACKEND_LOG, TEST_DIR_PATH.to_string());
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    return child;
+}
+
+// Initialize the kvsd with the given backend in an empty store directory
+pub fn init_for_backend(backend: &str) -> Result<Child, ()> {
+    if Path::new(TEST_DIR_PATH).exists() {
+        fs::remove_dir_all(TEST_DIR_PATH).expect("Failed to remove store directory.");
+    }
+    init_dir(TEST_DIR_PATH.to_string());
+    run_kvsd_with_store(backend, TEST_DIR_PATH)
+}
+
+// Initialize the kvsd with a memory backend, no store directory is required
+pub fn init_for_memory() -> Result<Child, ()> {
+    let child = Command::new("target/release/kvsd")
+        .args(&["--silent", "--backend", "memory"])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Initialize the kvsd with a memory backend and the given limits
+pub fn init_for_limits(
+    max_key_length: usize,
+    max_value_length: usize,
+    max_store_bytes: usize,
+) -> Result<Child, ()> {
+    let child = Command::new("target/release/kvsd")
+        .args(&[
+            "--silent",
+            "--backend",
+            "memory",
+            "--max-key-length",
+            max_key_length.to_string().as_str(),
+            "--max-value-length",
+            max_value_length.to_string().as_str(),
+            "--max-store-bytes",
+            max_store_bytes.to_string().as_str(),
+        ])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Initialize the kvsd with a memory backend and the HTTP gateway enabled
+pub fn init_for_http() -> Result<Child, ()> {
+    let child = Command::new("target/release/kvsd")
+        .args(&["--silent", "--http", "--backend", "memory"])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Initialize the kvsd with a memory backend, the HTTP gateway and a limit of two requests
+// per second and client
+pub fn init_for_rate_limit() -> Result<Child, ()> {
+    let child = Command::new("target/release/kvsd")
+        .args(&[
+            "--silent",
+            "--http",
+            "--backend",
+            "memory",
+            "--rate-limit-client-rps",
+            "2",
+        ])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Initialize the kvsd with a memory backend and an audit log
+pub fn init_for_audit(audit_log: &str) -> Result<Child, ()> {
+    init_dir(TEST_DIR_PATH.to_string());
+    if Path::new(audit_log).exists() {
+        fs::remove_file(audit_log).expect("Failed to remove audit log.");
+    }
+    let child = Command::new("target/release/kvsd")
+        .args(&["--silent", "--backend", "memory", "--audit-log", audit_log])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Run kvsd with the verify-audit-log option
+pub fn run_kvsd_verify_audit_log(audit_log: &str) -> bool {
+    let status = Command::new("target/release/kvsd")
+        .args(&["--silent", "--audit-log", audit_log, "--verify-audit-log"])
+        .status()
+        .expect("Failed to start kvsd process.");
+    status.success()
+}
+
+// Initialize the directories of a store to be migrated, the store and all backups are removed
+pub fn init_for_migrate(path: &str) {
+    for dir in &[
+        path.to_string(),
+        format!("{}.json-backup", path),
+        format!("{}.file-backup", path),
+    ] {
+        if Path::new(dir).exists() {
+            fs::remove_dir_all(dir).expect("Failed to remove store directory.");
+        }
+    }
+    init_dir(path.to_string());
+}
+
+// Run the kvsd with the given backend on an existing store
+pub fn run_kvsd_with_store(backend: &str, path: &str) -> Result<Child, ()> {
+    let child = Command::new("target/release/kvsd")
+        .args(&["--silent", "--backend", backend, "--path", path])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Run the migrate subcommand of kvsd
+pub fn run_kvsd_migrate(from: &str, to: &str, path: &str) -> bool {
+    let status = Command::new("target/release/kvsd")
+        .args(&[
+            "--silent", "migrate", "--from", from, "--to", to, "--path", path,
+        ])
+        .status()
+        .expect("Failed to start kvsd process.");
+    status.success()
+}
+
+// Write a Base64 encoded 32 byte key for encrypting archives
+#[cfg(unix)]
+pub fn write_backup_key(key_file: &str) {
+    fs::write(key_file, "S3ZzQmFja3VwVGVzdEtleU9mVGhpcnR5VHdvQnl0ZXM=\n")
+        .expect("Failed to write backup key.");
+}
+
+// Run kvsc with the backup subcommand, connecting to the Unix domain socket if given
+#[cfg(unix)]
+pub fn run_kvsc_backup(output: &str, key_file: &str, unix: Option<&str>) -> bool {
+    let mut kvsc = Command::new("target/release/kvsc");
+    kvsc.arg("--silent");
+    if let Some(path) = unix {
+        kvsc.args(&["--unix", path]);
+    }
+    let status = kvsc
+        .args(&["backup", "--output", output, "--key-file", key_file])
+        .status()
+        .expect("Failed to start kvsc process.");
+    status.success()
+}
+
+// Run the restore subcommand of kvsd restoring the archive into a file backend store at path
+#[cfg(unix)]
+pub fn run_kvsd_restore(archive: &str, path: &str, key_file: &str) -> bool {
+    let status = Command::new("target/release/kvsd")
+        .args(&[
+            "--silent",
+            "--backend",
+            "file",
+            "restore",
+            "--archive",
+            archive,
+            "--path",
+            path,
+            "--key-file",
+            key_file,
+        ])
+        .status()
+        .expect("Failed to start kvsd process.");
+    status.success()
+}
+
+// Initialize the kvsd with a file backend taking a snapshot every second, keeping two.
+// Snapshots are only served on the additional listener at the Unix domain socket unix.
+#[cfg(unix)]
+pub fn init_for_scheduled_snapshots(
+    backup_path: &str,
+    key_file: &str,
+    unix: &str,
+) -> Result<Child, ()> {
+    clean_up(BACKEND_FILE, TEST_DIR_PATH.to_string());
+    init_dir(TEST_DIR_PATH.to_string());
+    let config_file = Path::new(unix).with_extension("toml");
+    fs::write(
+        &config_file,
+        format!("[[listeners]]\nunix = \"{}\"\n", unix),
+    )
+    .expect("Failed to write configuration file.");
+    let child = Command::new("target/release/kvsd")
+        .args(&[
+            "--silent",
+            "--config",
+            config_file.to_str().unwrap(),
+            "--backend",
+            "file",
+            "--path",
+            TEST_DIR_PATH,
+            "--backup-path",
+            backup_path,
+            "--backup-interval",
+            "1",
+            "--backup-retention",
+            "2",
+            "--backup-key-file",
+            key_file,
+        ])
+        .spawn()
+        .expect("Failed to start kvsd process.");
+    let sleep_time = time::Duration::from_millis(1000);
+    thread::sleep(sleep_time);
+    Ok(child)
+}
+
+// Send a request to the HTTP gateway using curl, returns the HTTP status code
+pub fn run_curl(method: &str, key: String, body: Option<String>) -> u16 {
+    let url = format!("http://127.0.0.1:27080/v1/kv/{}", key);
+    let mut args = vec![
+        "--silent",
+        "--output",
+        "/dev/null",
+        "--write-out",
+        "%{http_code}",
+        "--request",
+        method,
+        url.as_str(),
+    ];
+    if let Some(body) = body.as_ref() {
+        args.push("--data");
+        args.push(body.as_str());
+    }
+    let output = Command::new("curl")
+        .args(&args)
+        .output()
+        .expect("Failed to start curl process.");
+    String::from_utf8_lossy(&output.stdout).parse().unwrap_or(0)
+}
+
+// Get a key via the HTTP gateway, returns the status code and the retry-after-ms header
+pub fn run_curl_retry_after(key: String) -> (u16, Option<u64>) {
+    let url = format!("http://127.0.0.1:27080/v1/kv/{}", key);
+    let output = Command::new("curl")
+        .args(&[
+            "--silent",
+            "--output",
+            "/dev/null",
+            "--dump-header",
+            "-",
+            url.as_str(),
+        ])
+        .output()
+        .expect("Failed to start curl process.");
+    let headers = String::from_utf8_lossy(&output.stdout).to_string();
+    let status = headers
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0);
+    let retry_after = headers
+        .lines()
+        .find(|line| line.to_lowercase().starts_with("retry-after-ms:"))
+        .and_then(|line| line["retry-after-ms:".len()..].trim().parse().ok());
+    (status, retry_after)
+}
+
+// Get a key via the HTTP gateway and return the request ID of the response
+pub fn run_curl_request_id(key: String, request_id: Option<&str>) -> Option<String> {
+    let url = format!("http://127.0.0.1:27080/v1/kv/{}", key);
+    let header = format!("x-request-id: {}", request_id.unwrap_or(""));
+    let mut args = vec![
+        "--silent",
+        "--output",
+        "/dev/null",
+        "--dump-header",
+        "-",
+        url.as_str(),
+    ];
+    if request_id.is_some() {
+        args.push("--header");
+        args.push(header.as_str());
+    }
+    let output = Command::new("curl")
+        .args(&args)
+        .output()
+        .expect("Failed to start curl process.");
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.to_lowercase().starts_with("x-request-id:"))
+        .map(|line| line["x-request-id:".len()..].trim().to_string())
+}
+
+// Paths of all value files of a file store, they are stored in two levels of subdirectories
+pub fn find_value_files(path: &str) -> Vec<String> {
+    let mut files = Vec::new();
+    let values = match fs::read_dir(format!("{}/values", path)) {
+        Ok(values) => values,
+        Err(_e) => return files,
+    };
+    for first_level in values.flatten() {
+        for second_level in fs::read_dir(first_level.path()).unwrap().flatten() {
+            for file in fs::read_dir(second_level.path()).unwrap().flatten() {
+                files.push(file.path().to_string_lossy().to_string());
+            }
+        }
+    }
+    files
+}
+
+// Changes are acknowledged once they are queued, wait until the store handler persisted them
+pub fn wait_for_store_handler() {
+    let sleep_time = time::Duration::from_millis(200);
+    thread::sleep(sleep_time);
+}
+
 // Add a defined number of entries to the store
 // the keys follow the format key_<number> for easy retrival
+// Store count key value pairs with concurrently running kvsc processes, returns once all
+// of them are acknowledged
+pub fn run_kvsc_store_burst(count: u16) -> bool {
+    let children: Vec<Child> = (0..count)
+        .map(|x| {
+            Command::new("target/release/kvsc")
+                .args(&[
+                    "--silent",
+                    "store",
+                    "--key",
+                    format!("burstkey{}", x).as_str(),
+                    "--value",
+                    format!("burstvalue{}", x).as_str(),
+                ])
+                .spawn()
+                .expect("Failed to start kvsc process.")
+        })
+        .collect();
+    let mut success = true;
+    for mut child in children {
+        success &= child.wait().map(|status| status.success()).unwrap_or(false);
+    }
+    success
+}
+
+// Send SIGTERM to kvsd and wait for it, returns whether it exited successfully
+#[cfg(unix)]
+pub fn terminate_kvsd(mut kvsd_process: Child) -> bool {
+    let status = Command::new("kill")
+        .args(&["-TERM", kvsd_process.id().to_string().as_str()])
+        .status()
+        .expect("Failed to start kill process.");
+    if !status.success() {
+        return false;
+    }
+    match kvsd_process.wait() {
+        Ok(status) => status.success(),
+        Err(_e) => false,
+    }
+}
+
 // the size specifies the number of characters of each entry
 pub fn add_entries(number_of_entries: u16, size: u16) {
     for x in 0..number_of_entries {
@@ -0,0 +1,510 @@
+/*
+*  backup utils module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::io::{BufRead, Write};
+
+// json
+use serde::{Deserialize, Serialize};
+
+//Crypto libraries
+use sha3::{Digest, Sha3_256};
+
+// kvs modules
+use crate::crypto::{
+    derive_key_from_passphrase, generate_key, generate_salt, key_encrypt, key_try_decrypt, KEY_LEN,
+};
+use crate::filesystem_wrapper::read_file_to_string;
+
+// Constants
+// Format and version in the header of an archive
+const FORMAT: &str = "kvs-backup";
+const VERSION: u32 = 1;
+// Key derivation of archives encrypted with a passphrase
+const KDF_PBKDF2: &str = "pbkdf2-hmac-sha3-256";
+// Archives encrypted with a key use it directly
+const KDF_NONE: &str = "none";
+// PBKDF2 iterations of new archives encrypted with a passphrase, archives store their iterations
+#[cfg(not(test))]
+const PASSPHRASE_ITERATIONS: u32 = 200_000;
+#[cfg(test)]
+const PASSPHRASE_ITERATIONS: u32 = 1_000;
+// Range of PBKDF2 iterations accepted from the unauthenticated header of an archive
+const ITERATIONS_MIN: u32 = 1_000;
+const ITERATIONS_MAX: u32 = 10_000_000;
+// Plaintext bytes of the entries of a chunk, a larger value gets a chunk of its own
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+// Kinds of chunks, authenticated as associated data
+const CHUNK_ENTRIES: u8 = 0;
+const CHUNK_MANIFEST: u8 = 1;
+
+// Secret an archive is encrypted with
+pub enum BackupSecret {
+    Key([u8; KEY_LEN]),
+    // The key is derived from the passphrase with a random salt per archive
+    Passphrase(String),
+}
+
+impl BackupSecret {
+    // Read a Base64 encoded 32 byte key from a file, surrounding whitespace is ignored
+    pub fn from_key_file(path: String) -> Result<BackupSecret, String> {
+        let content = match read_file_to_string(path.clone()) {
+            Ok(content) => content,
+            Err(e) => return Err(format!("Could not read backup key \"{}\": {}", path, e)),
+        };
+        let decoded = match base64::decode(content.trim()) {
+            Ok(decoded) => decoded,
+            Err(_e) => return Err(format!("Backup key \"{}\" is not Base64 encoded.", path)),
+        };
+        if decoded.len() != KEY_LEN {
+            return Err(format!(
+                "Backup key \"{}\" has to be {} bytes long.",
+                path, KEY_LEN
+            ));
+        }
+        let mut key: [u8; KEY_LEN] = [0; KEY_LEN];
+        key.copy_from_slice(&decoded);
+        Ok(BackupSecret::Key(key))
+    }
+
+    // Read a passphrase from a file, a trailing line break is ignored
+    pub fn from_passphrase_file(path: String) -> Result<BackupSecret, String> {
+        let content = match read_file_to_string(path.clone()) {
+            Ok(content) => content,
+            Err(e) => {
+                return Err(format!(
+                    "Could not read backup passphrase \"{}\": {}",
+                    path, e
+                ))
+            }
+        };
+        let passphrase = content.trim_end_matches(&['\n', '\r'][..]);
+        if passphrase.is_empty() {
+            return Err(format!("Backup passphrase \"{}\" is empty.", path));
+        }
+        Ok(BackupSecret::Passphrase(passphrase.to_string()))
+    }
+
+    // Random key for archives only read by this process
+    pub fn random_key() -> BackupSecret {
+        BackupSecret::Key(generate_key())
+    }
+}
+
+// First line of an archive, stored in plain-text form and authenticated with every chunk
+#[derive(Deserialize, Serialize)]
+struct Header {
+    format: String,
+    version: u32,
+    kdf: String,
+    // Base64 encoded salt and iterations of the key derivation
+    #[serde(default)]
+    salt: String,
+    #[serde(default)]
+    iterations: u32,
+}
+
+// Integrity manifest, the last chunk of an archive
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Manifest {
+    // Seconds since the Unix epoch the snapshot was taken at
+    pub created: u64,
+    // Backend of the store the snapshot was taken from
+    pub backend: String,
+    pub entries: u64,
+    // Number of chunks containing entries
+    pub chunks: u64,
+    // SHA3-256 hash of the plaintext of all chunks containing entries
+    pub sha3_256: String,
+}
+
+// Writes an archive, the entries are encrypted in chunks of about CHUNK_SIZE bytes.
+// Each chunk is a line with the Base64 encoded IV and ciphertext.
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+    key: [u8; KEY_LEN],
+    header_hash: Vec<u8>,
+    // Entries of the next chunk and their plaintext bytes
+    pending: Vec<(String, String)>,
+    pending_bytes: usize,
+    entries: u64,
+    chunks: u64,
+    hasher: Sha3_256,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    // Start an archive by writing its header
+    pub fn new(mut writer: W, secret: &BackupSecret) -> Result<ArchiveWriter<W>, String> {
+        let (header, key) = match secret {
+            BackupSecret::Key(key) => (
+                Header {
+                    format: FORMAT.to_string(),
+                    version: VERSION,
+                    kdf: KDF_NONE.to_string(),
+                    salt: String::new(),
+                    iterations: 0,
+                },
+                *key,
+            ),
+            BackupSecret::Passphrase(passphrase) => {
+                let salt = generate_salt();
+                (
+                    Header {
+                        format: FORMAT.to_string(),
+                        version: VERSION,
+                        kdf: KDF_PBKDF2.to_string(),
+                        salt: base64::encode(salt),
+                        iterations: PASSPHRASE_ITERATIONS,
+                    },
+                    derive_key_from_passphrase(passphrase, &salt, PASSPHRASE_ITERATIONS),
+                )
+            }
+        };
+        let line = match serde_json::to_string(&header) {
+            Ok(line) => line,
+            Err(_e) => return Err("Error serializing archive header.".to_string()),
+        };
+        write_line(&mut writer, &line)?;
+        Ok(ArchiveWriter {
+            writer,
+            key,
+            header_hash: Sha3_256::digest(line.as_bytes()).to_vec(),
+            pending: Vec::new(),
+            pending_bytes: 0,
+            entries: 0,
+            chunks: 0,
+            hasher: Sha3_256::new(),
+        })
+    }
+
+    // Add an entry, a chunk is written once enough entries are pending
+    pub fn add(&mut self, key: String, value: String) -> Result<(), String> {
+        self.pending_bytes += key.len() + value.len();
+        self.pending.push((key, value));
+        self.entries += 1;
+        if self.pending_bytes >= CHUNK_SIZE {
+            self.write_entries()?;
+        }
+        Ok(())
+    }
+
+    // Write the pending entries and the manifest, returns the manifest
+    pub fn finish(mut self, backend: &str, created: u64) -> Result<Manifest, String> {
+        if !self.pending.is_empty() {
+            self.write_entries()?;
+        }
+        let manifest = Manifest {
+            created,
+            backend: backend.to_string(),
+            entries: self.entries,
+            chunks: self.chunks,
+            sha3_256: format!("{:x}", self.hasher.clone().finalize()),
+        };
+        let plaintext = match serde_json::to_vec(&manifest) {
+            Ok(plaintext) => plaintext,
+            Err(_e) => return Err("Error serializing archive manifest.".to_string()),
+        };
+        self.write_chunk(&plaintext, CHUNK_MANIFEST)?;
+        if let Err(e) = self.writer.flush() {
+            return Err(format!("Could not write archive: {}", e));
+        }
+        Ok(manifest)
+    }
+
+    // Write the pending entries as a chunk
+    fn write_entries(&mut self) -> Result<(), String> {
+        let plaintext = match serde_json::to_vec(&self.pending) {
+            Ok(plaintext) => plaintext,
+            Err(_e) => return Err("Error serializing archive entries.".to_string()),
+        };
+        self.hasher.update(&plaintext);
+        self.write_chunk(&plaintext, CHUNK_ENTRIES)?;
+        self.chunks += 1;
+        self.pending.clear();
+        self.pending_bytes = 0;
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, plaintext: &[u8], kind: u8) -> Result<(), String> {
+        let aad = chunk_aad(&self.header_hash, self.chunks, kind);
+        let encrypted = key_encrypt(&self.key, plaintext, &aad);
+        write_line(&mut self.writer, &base64::encode(encrypted))
+    }
+}
+
+// Read and verify an archive, the entries are passed to restore one chunk at a time.
+// Returns the manifest, fails if a chunk is not authentic, missing or reordered.
+// As the manifest is verified last, restore may have received entries of a damaged archive.
+pub fn read_archive<R: BufRead>(
+    reader: R,
+    secret: &BackupSecret,
+    restore: &mut dyn FnMut(Vec<(String, String)>) -> Result<(), String>,
+) -> Result<Manifest, String> {
+    let mut lines = reader.lines();
+    let header_line = match lines.next() {
+        Some(Ok(line)) => line,
+        Some(Err(e)) => return Err(format!("Could not read archive: {}", e)),
+        None => return Err("Archive is empty.".to_string()),
+    };
+    let header: Header = match serde_json::from_str(&header_line) {
+        Ok(header) => header,
+        Err(_e) => return Err("Archive header invalid, not a kvs backup.".to_string()),
+    };
+    if header.format != FORMAT || header.version != VERSION {
+        return Err(format!(
+            "Archive format \"{}\" version {} is not supported.",
+            header.format, header.version
+        ));
+    }
+    let key = match (secret, header.kdf.as_str()) {
+        (BackupSecret::Key(key), KDF_NONE) => *key,
+        (BackupSecret::Passphrase(passphrase), KDF_PBKDF2) => {
+            if header.iterations < ITERATIONS_MIN || header.iterations > ITERATIONS_MAX {
+                return Err(format!(
+                    "Archive header invalid, {} iterations of the key derivation are not between {} and {}.",
+                    header.iterations, ITERATIONS_MIN, ITERATIONS_MAX
+                ));
+            }
+            let salt = match base64::decode(&header.salt) {
+                Ok(salt) => salt,
+                Err(_e) => {
+                    return Err("Archive header invalid, salt is not Base64 encoded.".to_string())
+                }
+            };
+            derive_key_from_passphrase(passphrase, &salt, header.iterations)
+        }
+        (BackupSecret::Key(_key), _) => {
+            return Err("Archive is encrypted with a passphrase, not a key.".to_string())
+        }
+        (BackupSecret::Passphrase(_passphrase), _) => {
+            return Err("Archive is encrypted with a key, not a passphrase.".to_string())
+        }
+    };
+    let header_hash = Sha3_256::digest(header_line.as_bytes()).to_vec();
+
+    let mut entries: u64 = 0;
+    let mut hasher = Sha3_256::new();
+    let mut index: u64 = 0;
+    while let Some(line) = lines.next() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Err(format!("Could not read archive: {}", e)),
+        };
+        let encrypted = match base64::decode(line.trim()) {
+            Ok(encrypted) => encrypted,
+            Err(_e) => return Err(format!("Chunk {} is not Base64 encoded.", index)),
+        };
+        // Chunks of entries are followed by the manifest
+        let aad = chunk_aad(&header_hash, index, CHUNK_ENTRIES);
+        if let Ok(plaintext) = key_try_decrypt(&key, &encrypted, &aad) {
+            hasher.update(&plaintext);
+            let chunk: Vec<(String, String)> = match serde_json::from_slice(&plaintext) {
+                Ok(chunk) => chunk,
+                Err(_e) => return Err(format!("Chunk {} invalid.", index)),
+            };
+            entries += chunk.len() as u64;
+            restore(chunk)?;
+            index += 1;
+            continue;
+        }
+        let aad = chunk_aad(&header_hash, index, CHUNK_MANIFEST);
+        let plaintext = match key_try_decrypt(&key, &encrypted, &aad) {
+            Ok(plaintext) => plaintext,
+            Err(_e) => {
+                return Err(format!(
+                    "Chunk {} fails authentication, the secret is wrong or the archive is damaged.",
+                    index
+                ))
+            }
+        };
+        let manifest: Manifest = match serde_json::from_slice(&plaintext) {
+            Ok(manifest) => manifest,
+            Err(_e) => return Err("Archive manifest invalid.".to_string()),
+        };
+        if lines.next().is_some() {
+            return Err("Archive contains data after the manifest.".to_string());
+        }
+        if manifest.chunks != index
+            || manifest.entries != entries
+            || manifest.sha3_256 != format!("{:x}", hasher.finalize())
+        {
+            return Err("Archive does not match its manifest.".to_string());
+        }
+        return Ok(manifest);
+    }
+    Err("Archive is incomplete, the manifest is missing.".to_string())
+}
+
+// Associated data of a chunk: the hash of the header, the index and the kind of the chunk
+fn chunk_aad(header_hash: &[u8], index: u64, kind: u8) -> Vec<u8> {
+    let mut aad = header_hash.to_vec();
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.push(kind);
+    aad
+}
+
+fn write_line<W: Write>(writer: &mut W, line: &str) -> Result<(), String> {
+    match writer
+        .write_all(line.as_bytes())
+        .and_then(|()| writer.write_all(b"\n"))
+    {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Could not write archive: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn test_entries() -> Vec<(String, String)> {
+        vec![
+            ("first".to_string(), "value".to_string()),
+            ("second".to_string(), "other value".to_string()),
+        ]
+    }
+
+    fn write_archive(secret: &BackupSecret, entries: Vec<(String, String)>) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut writer = ArchiveWriter::new(&mut archive, secret).unwrap();
+        for (key, value) in entries {
+            writer.add(key, value).unwrap();
+        }
+        writer.finish("json", 1600000000).unwrap();
+        archive
+    }
+
+    // Read an archive and collect its entries
+    fn read_entries(
+        archive: &[u8],
+        secret: &BackupSecret,
+    ) -> Result<(Manifest, Vec<(String, String)>), String> {
+        let mut entries = Vec::new();
+        let manifest = read_archive(archive, secret, &mut |chunk| {
+            entries.extend(chunk);
+            Ok(())
+        })?;
+        Ok((manifest, entries))
+    }
+
+    // Replace the lines of an archive
+    fn modify_lines(archive: Vec<u8>, modify: fn(&mut Vec<&str>)) -> Vec<u8> {
+        let archive = String::from_utf8(archive).unwrap();
+        let mut lines: Vec<&str> = archive.lines().collect();
+        modify(&mut lines);
+        format!("{}\n", lines.join("\n")).into_bytes()
+    }
+
+    // ============== Archives ===============================
+    #[test]
+    fn archive_key_ok() {
+        let secret = BackupSecret::Key([3; KEY_LEN]);
+        let archive = write_archive(&secret, test_entries());
+        let (manifest, entries) = read_entries(&archive, &secret).unwrap();
+        assert_eq!(manifest.entries, 2);
+        assert_eq!(manifest.chunks, 1);
+        assert_eq!(manifest.backend, "json");
+        assert_eq!(manifest.created, 1600000000);
+        assert_eq!(entries, test_entries());
+    }
+
+    #[test]
+    fn archive_passphrase_ok() {
+        let secret = BackupSecret::Passphrase("correct horse".to_string());
+        let archive = write_archive(&secret, test_entries());
+        let (_manifest, entries) = read_entries(&archive, &secret).unwrap();
+        assert_eq!(entries, test_entries());
+    }
+
+    #[test]
+    fn archive_empty_ok() {
+        let secret = BackupSecret::Key([3; KEY_LEN]);
+        let archive = write_archive(&secret, Vec::new());
+        let (manifest, entries) = read_entries(&archive, &secret).unwrap();
+        assert_eq!(manifest.entries, 0);
+        assert_eq!(manifest.chunks, 0);
+        assert_eq!(entries.is_empty(), true);
+    }
+
+    #[test]
+    fn archive_chunks_ok() {
+        let secret = BackupSecret::Key([3; KEY_LEN]);
+        let large_entries: Vec<(String, String)> = (0..3)
+            .map(|i| (format!("key_{}", i), "a".repeat(CHUNK_SIZE)))
+            .collect();
+        let archive = write_archive(&secret, large_entries.clone());
+        let (manifest, entries) = read_entries(&archive, &secret).unwrap();
+        assert_eq!(manifest.chunks, 3);
+        assert_eq!(entries, large_entries);
+        // Each chunk is restored on its own
+        let mut chunks = Vec::new();
+        read_archive(archive.as_slice(), &secret, &mut |chunk| {
+            chunks.push(chunk.len());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(chunks, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn archive_wrong_secret_failed() {
+        let archive = write_archive(&BackupSecret::Key([3; KEY_LEN]), test_entries());
+        let wrong_key = read_entries(&archive, &BackupSecret::Key([4; KEY_LEN]));
+        let passphrase = read_entries(
+            &archive,
+            &BackupSecret::Passphrase("passphrase".to_string()),
+        );
+        assert_eq!(wrong_key.is_err(), true);
+        assert_eq!(passphrase.is_err(), true);
+    }
+
+    #[test]
+    fn archive_truncated_failed() {
+        let secret = BackupSecret::Key([3; KEY_LEN]);
+        let archive = modify_lines(write_archive(&secret, test_entries()), |lines| {
+            lines.pop();
+        });
+        assert_eq!(
+            read_entries(&archive, &secret).err().unwrap(),
+            "Archive is incomplete, the manifest is missing."
+        );
+    }
+
+    #[test]
+    fn archive_reordered_failed() {
+        let secret = BackupSecret::Key([3; KEY_LEN]);
+        let large_entries: Vec<(String, String)> = (0..2)
+            .map(|i| (format!("key_{}", i), "a".repeat(CHUNK_SIZE)))
+            .collect();
+        let archive = modify_lines(write_archive(&secret, large_entries), |lines| {
+            lines.swap(1, 2);
+        });
+        assert_eq!(read_entries(&archive, &secret).is_err(), true);
+    }
+
+    #[test]
+    fn archive_header_modified_failed() {
+        let secret = BackupSecret::Key([3; KEY_LEN]);
+        let archive = modify_lines(write_archive(&secret, test_entries()), |lines| {
+            lines[0] =
+                r#"{"format":"kvs-backup","version":1,"kdf":"none","salt":"","iterations":1}"#;
+        });
+        assert_eq!(read_entries(&archive, &secret).is_err(), true);
+    }
+
+    #[test]
+    fn archive_iterations_failed() {
+        let secret = BackupSecret::Passphrase("correct horse".to_string());
+        let archive = modify_lines(write_archive(&secret, test_entries()), |lines| {
+            lines[0] = r#"{"format":"kvs-backup","version":1,"kdf":"pbkdf2-hmac-sha3-256","salt":"","iterations":4000000000}"#;
+        });
+        assert_eq!(
+            read_entries(&archive, &secret).err().unwrap(),
+            "Archive header invalid, 4000000000 iterations of the key derivation are not between 1000 and 10000000."
+        );
+    }
+}
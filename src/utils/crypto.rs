@@ -5,18 +5,50 @@
 */
 
 // Rust Standard Library
-use std::str;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 
 //Crypto libraries
-use aes_gcm_siv::aead::{generic_array::GenericArray, Aead, NewAead};
+use aes_gcm_siv::aead::{generic_array::GenericArray, Aead, NewAead, Payload};
 use aes_gcm_siv::Aes256GcmSiv;
+use hmac::Hmac;
+use pbkdf2::pbkdf2;
 use rand::{Rng, RngCore};
-use sha3::{Digest, Sha3_512};
+use sha3::{Digest, Sha3_256, Sha3_512};
+
+// Compression
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+// kvs modules
+use crate::metrics::Histogram;
+
+// Duration of encryptions and decryptions including the key derivation
+lazy_static! {
+    pub static ref ENCRYPTION_DURATION: Histogram = Histogram::new();
+    pub static ref DECRYPTION_DURATION: Histogram = Histogram::new();
+}
 
 // Derivation Value length
 pub const DV_LEN: usize = 32;
 // AES 256 GCM Initialization Vector length in bytes according to BSI TR-02102-1 (Version 2020-1)
 pub const IV_LEN: usize = 12;
+// AES 256 key length in bytes
+pub const KEY_LEN: usize = 32;
+// Salt length in bytes of keys derived from a passphrase
+pub const SALT_LEN: usize = 16;
+// AES 256 GCM SIV authentication tag length in bytes
+const TAG_LEN: usize = 16;
+// Prefix of the Base64 encoded ciphertext of a plaintext compressed before the encryption,
+// ":" is not part of the Base64 alphabet
+pub const COMPRESSED_PREFIX: &str = "z:";
+// Associated data authenticating the compression flag, uncompressed plaintexts have none
+const COMPRESSED_AAD: &[u8] = b"deflate";
+
+// Minimum plaintext length in bytes to compress it before the encryption, 0 disables compression
+static COMPRESSION_THRESHOLD: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Debug)]
 pub struct Credentials {
@@ -26,11 +58,19 @@ pub struct Credentials {
 
 impl Credentials {
     pub fn new(path: String) -> Result<Credentials, Box<dyn std::error::Error>> {
-        let cert = match std::fs::read_to_string(format!("{}/grpc.crt", path)) {
+        Credentials::from_files(format!("{}/grpc.crt", path), format!("{}/grpc.key", path))
+    }
+
+    // Load certificate and private key from the given files
+    pub fn from_files(
+        certificate_file: String,
+        private_key_file: String,
+    ) -> Result<Credentials, Box<dyn std::error::Error>> {
+        let cert = match std::fs::read_to_string(certificate_file) {
             Ok(c) => c,
             Err(e) => return Err(Box::new(e)),
         };
-        let key = match std::fs::read_to_string(format!("{}/grpc.key", path)) {
+        let key = match std::fs::read_to_string(private_key_file) {
             Ok(k) => k,
             Err(e) => return Err(Box::new(e)),
         };
@@ -93,6 +133,27 @@ pub fn generate_initialization_vector() -> [u8; IV_LEN] {
     derivation_value
 }
 
+// Generate a random salt for deriving a key from a passphrase
+pub fn generate_salt() -> [u8; SALT_LEN] {
+    let mut salt: [u8; SALT_LEN] = [0; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    salt
+}
+
+// Generate a random key, e.g. for temporary data only read by this process
+pub fn generate_key() -> [u8; KEY_LEN] {
+    let mut key: [u8; KEY_LEN] = [0; KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut key);
+    key
+}
+
+// Derive a key from a passphrase using PBKDF2-HMAC-SHA3-256
+pub fn derive_key_from_passphrase(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; KEY_LEN] {
+    let mut key: [u8; KEY_LEN] = [0; KEY_LEN];
+    pbkdf2::<Hmac<Sha3_256>>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
 // Derive password from given string.
 // TODO: Currently implemented by using a hash, later libuta will be used
 pub fn derive_password(derivation_value: String) -> String {
@@ -109,8 +170,82 @@ pub fn derive_password(derivation_value: String) -> String {
     _password
 }
 
-// Encrypt plaintext string using provided secret and IV
-fn aes_256_gcm_siv_encrypt(secret: String, iv: Vec<u8>, plaintext: String) -> Vec<u8> {
+// SHA3-256 hash of data as hex string, e.g. to identify certificates or chain audit log entries
+pub fn sha3_256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha3_256::digest(data))
+}
+
+// Keyed SHA3-256 hash of data as hex string, equal data only gives equal hashes for the same key.
+// The key is prepended to the data, SHA3 is not prone to length extension attacks.
+pub fn keyed_sha3_256_hex(key: &str, data: &[u8]) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update(key.as_bytes());
+    hasher.update(data);
+    format!("{:x}", hasher.finalize())
+}
+
+// Set the minimum plaintext length for compressing plaintexts before the encryption,
+// 0 disables compression. Compressed and uncompressed ciphertexts can always be decrypted.
+pub fn set_compression_threshold(threshold: usize) {
+    COMPRESSION_THRESHOLD.store(threshold, Ordering::Relaxed);
+}
+
+// Compress a plaintext if it reaches the threshold and gets smaller,
+// returns the bytes to encrypt and whether they are compressed
+fn compress(plaintext: String) -> (Vec<u8>, bool) {
+    let threshold = COMPRESSION_THRESHOLD.load(Ordering::Relaxed);
+    if threshold == 0 || plaintext.len() < threshold {
+        return (plaintext.into_bytes(), false);
+    }
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    if encoder.write_all(plaintext.as_bytes()).is_err() {
+        return (plaintext.into_bytes(), false);
+    }
+    match encoder.finish() {
+        Ok(compressed) if compressed.len() < plaintext.len() => (compressed, true),
+        _ => (plaintext.into_bytes(), false),
+    }
+}
+
+// Decompress a decrypted plaintext if it was compressed and check that it is valid UTF-8
+fn decode_plaintext(plaintext: Vec<u8>, compressed: bool) -> Result<String, String> {
+    let plaintext = if compressed {
+        let mut decompressed = Vec::new();
+        if DeflateDecoder::new(plaintext.as_slice())
+            .read_to_end(&mut decompressed)
+            .is_err()
+        {
+            return Err("Decompression of plaintext failed.".to_string());
+        }
+        decompressed
+    } else {
+        plaintext
+    };
+    match String::from_utf8(plaintext) {
+        Ok(plaintext) => Ok(plaintext),
+        Err(_e) => Err("Decrypted text is not valid UTF-8.".to_string()),
+    }
+}
+
+// Associated data of a ciphertext depending on the compression of its plaintext
+fn associated_data(compressed: bool) -> &'static [u8] {
+    if compressed {
+        COMPRESSED_AAD
+    } else {
+        b""
+    }
+}
+
+// Split the compression flag from a Base64 encoded ciphertext
+fn split_compression_flag(base64_ciphertext: &str) -> (&str, bool) {
+    match base64_ciphertext.strip_prefix(COMPRESSED_PREFIX) {
+        Some(base64_ciphertext) => (base64_ciphertext, true),
+        None => (base64_ciphertext, false),
+    }
+}
+
+// Encrypt plaintext using provided secret, IV and associated data
+fn aes_256_gcm_siv_encrypt(secret: String, iv: Vec<u8>, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
     // load key from secret
     let key = GenericArray::from_slice(secret.as_bytes());
     // Initialize AES256GCM
@@ -119,83 +254,204 @@ fn aes_256_gcm_siv_encrypt(secret: String, iv: Vec<u8>, plaintext: String) -> Ve
     let nonce = GenericArray::from_slice(&iv);
     // Encrypt returning the result
     cipher
-        .encrypt(nonce, plaintext.as_bytes().as_ref())
+        .encrypt(
+            nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
         .expect("encryption failure!")
 }
 
-// Decrypt ciphertext string using provided secret and IV
-fn aes_256_gcm_siv_decrypt(secret: String, iv: Vec<u8>, ciphertext: Vec<u8>) -> Vec<u8> {
-    // load key from secret
+// Decrypt ciphertext using provided secret, IV and associated data,
+// fails if the ciphertext is not authentic
+fn aes_256_gcm_siv_try_decrypt(
+    secret: String,
+    iv: Vec<u8>,
+    ciphertext: Vec<u8>,
+    aad: &[u8],
+) -> Result<Vec<u8>, String> {
     let key = GenericArray::from_slice(secret.as_bytes());
-    // Initialize AES256GCM
     let cipher = Aes256GcmSiv::new(key);
-    // Set the noce to the IV
     let nonce = GenericArray::from_slice(&iv);
-    // Decrypt returning the result
-    cipher
-        .decrypt(nonce, ciphertext.as_ref())
-        .expect("decryption failure!")
+    match cipher.decrypt(
+        nonce,
+        Payload {
+            msg: ciphertext.as_ref(),
+            aad,
+        },
+    ) {
+        Ok(plaintext) => Ok(plaintext),
+        Err(_e) => Err("Authentication of ciphertext failed.".to_string()),
+    }
+}
+
+// Encrypt plaintext using a key and a random IV, returns the IV followed by the ciphertext.
+// The associated data is authenticated but not part of the result.
+pub fn key_encrypt(key: &[u8; KEY_LEN], plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+    let iv = generate_initialization_vector();
+    let cipher = Aes256GcmSiv::new(GenericArray::from_slice(key));
+    let ciphertext = cipher
+        .encrypt(
+            GenericArray::from_slice(&iv),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("encryption failure!");
+    let mut encrypted = iv.to_vec();
+    encrypted.extend(ciphertext);
+    encrypted
+}
+
+// Decrypt the IV followed by the ciphertext created by key_encrypt,
+// fails if it is not authentic for the key and the associated data
+pub fn key_try_decrypt(
+    key: &[u8; KEY_LEN],
+    encrypted: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, String> {
+    if encrypted.len() < IV_LEN {
+        return Err("Ciphertext is too short.".to_string());
+    }
+    let (iv, ciphertext) = encrypted.split_at(IV_LEN);
+    let cipher = Aes256GcmSiv::new(GenericArray::from_slice(key));
+    match cipher.decrypt(
+        GenericArray::from_slice(iv),
+        Payload {
+            msg: ciphertext,
+            aad,
+        },
+    ) {
+        Ok(plaintext) => Ok(plaintext),
+        Err(_e) => Err("Authentication of ciphertext failed.".to_string()),
+    }
 }
 
 // Encrypt function wrapper for JSON Backend
 pub fn json_encrypt(plaintext: String) -> String {
+    let start = Instant::now();
     // Generate derivation value
     let derivation_value = generate_derivation_value();
     // Derive password using derivation value
     let derived_password = derive_password(derivation_value.clone());
     // Generate IV
     let iv = generate_initialization_vector();
+    // Compress if enabled, the compression flag is authenticated as associated data
+    let (plaintext, compressed) = compress(plaintext);
     // Encrypt
-    let ciphertext = aes_256_gcm_siv_encrypt(derived_password, iv.to_vec(), plaintext);
+    let ciphertext = aes_256_gcm_siv_encrypt(
+        derived_password,
+        iv.to_vec(),
+        &plaintext,
+        associated_data(compressed),
+    );
+    ENCRYPTION_DURATION.observe(start.elapsed());
     // Return formatted string for storage in JSON
     format!(
-        "{}${}${}",
+        "{}${}${}{}",
         derivation_value,
         base64::encode(iv),
+        if compressed { COMPRESSED_PREFIX } else { "" },
         base64::encode(ciphertext)
     )
 }
 
+// Length of the output of json_encrypt for an uncompressed plaintext of the given length,
+// an estimate for compressed plaintexts as these are only compressed if it shortens them
+pub fn json_encrypted_len(plaintext_len: usize) -> usize {
+    let base64_len = |len: usize| len.div_ceil(3) * 4;
+    DV_LEN + 1 + base64_len(IV_LEN) + 1 + base64_len(plaintext_len + TAG_LEN)
+}
+
 // Decrypt function wrapper for JSON Backend
 pub fn json_decrypt(ciphertext: String) -> String {
+    json_try_decrypt(&ciphertext).expect("decryption failure!")
+}
+
+// Decrypt function wrapper for JSON Backend, fails instead of panicking if the ciphertext
+// is malformed or not authentic, e.g. when reading a damaged file
+pub fn json_try_decrypt(ciphertext: &str) -> Result<String, String> {
+    let start = Instant::now();
     // Split ciphertext to three sections
     let v: Vec<&str> = ciphertext.split('$').collect();
-    // Derived password is in first section
-    let derived_password = derive_password(v[0].to_string());
-    // Second section contains IV
-    let decoded_iv: Vec<u8> = base64::decode(v[1]).unwrap();
-    // Third section contains encrypted text
-    let encrypted_text = base64::decode(v[2]).unwrap();
-    // Decrypt
-    let plaintext = aes_256_gcm_siv_decrypt(derived_password, decoded_iv, encrypted_text);
-    // Return decrypted text
-    return str::from_utf8(&plaintext).unwrap().to_string();
+    if v.len() != 3 {
+        return Err("Malformed ciphertext.".to_string());
+    }
+    let decoded_iv: Vec<u8> = match base64::decode(v[1]) {
+        Ok(iv) if iv.len() == IV_LEN => iv,
+        _ => return Err("Malformed initialization vector.".to_string()),
+    };
+    let (encoded_text, compressed) = split_compression_flag(v[2]);
+    let encrypted_text = match base64::decode(encoded_text) {
+        Ok(text) => text,
+        Err(_e) => return Err("Malformed ciphertext.".to_string()),
+    };
+    let plaintext = aes_256_gcm_siv_try_decrypt(
+        derive_password(v[0].to_string()),
+        decoded_iv,
+        encrypted_text,
+        associated_data(compressed),
+    )?;
+    let plaintext = decode_plaintext(plaintext, compressed);
+    DECRYPTION_DURATION.observe(start.elapsed());
+    plaintext
 }
 
 // Encrypt function wrapper for File Backend
 pub fn file_encrypt(plaintext: String, dv: String, iv: String) -> String {
+    let start = Instant::now();
     // derive secret
     let secret: String = derive_password(dv);
     // decode IV
     let initialization_vector: Vec<u8> = base64::decode(iv).unwrap();
+    // compress if enabled, the compression flag is authenticated as associated data
+    let (plaintext, compressed) = compress(plaintext);
     // encrypt string
-    let ciphertext: Vec<u8> = aes_256_gcm_siv_encrypt(secret, initialization_vector, plaintext);
-    // base64 encode Vec<u8>
-    base64::encode(ciphertext)
+    let ciphertext: Vec<u8> = aes_256_gcm_siv_encrypt(
+        secret,
+        initialization_vector,
+        &plaintext,
+        associated_data(compressed),
+    );
+    ENCRYPTION_DURATION.observe(start.elapsed());
+    // base64 encode Vec<u8>, flagged if compressed
+    if compressed {
+        format!("{}{}", COMPRESSED_PREFIX, base64::encode(ciphertext))
+    } else {
+        base64::encode(ciphertext)
+    }
 }
 
 // Decrypt function wrapper for File Backend
 pub fn file_decrypt(base64_ciphertext: String, dv: String, iv: String) -> String {
-    // base64 decode string
-    let ciphertext: Vec<u8> = base64::decode(base64_ciphertext).unwrap();
-    // derive secret
-    let secret: String = derive_password(dv);
-    // decode IV
-    let initialization_vector: Vec<u8> = base64::decode(iv).unwrap();
-    // decrypt Vec<8u>
-    let plaintext: Vec<u8> = aes_256_gcm_siv_decrypt(secret, initialization_vector, ciphertext);
-    // Return decrypted text
-    return str::from_utf8(&plaintext).unwrap().to_string();
+    file_try_decrypt(&base64_ciphertext, &dv, &iv).expect("decryption failure!")
+}
+
+// Decrypt function wrapper for File Backend, fails instead of panicking if the ciphertext
+// is malformed or not authentic, e.g. when a value file does not match its meta data
+pub fn file_try_decrypt(base64_ciphertext: &str, dv: &str, iv: &str) -> Result<String, String> {
+    let start = Instant::now();
+    let (base64_ciphertext, compressed) = split_compression_flag(base64_ciphertext);
+    let ciphertext: Vec<u8> = match base64::decode(base64_ciphertext) {
+        Ok(ciphertext) => ciphertext,
+        Err(_e) => return Err("Malformed ciphertext.".to_string()),
+    };
+    let initialization_vector: Vec<u8> = match base64::decode(iv) {
+        Ok(iv) if iv.len() == IV_LEN => iv,
+        _ => return Err("Malformed initialization vector.".to_string()),
+    };
+    let plaintext = aes_256_gcm_siv_try_decrypt(
+        derive_password(dv.to_string()),
+        initialization_vector,
+        ciphertext,
+        associated_data(compressed),
+    )?;
+    let plaintext = decode_plaintext(plaintext, compressed);
+    DECRYPTION_DURATION.observe(start.elapsed());
+    plaintext
 }
 
 // To run these tests use: `cargo test crypto`
@@ -219,9 +475,141 @@ mod tests {
         assert_eq!(derive_password("test".to_string()).len(), 32)
     }
 
+    // ============== Hashing ===============================
+    #[test]
+    fn sha3_256_hex_ok() {
+        assert_eq!(
+            sha3_256_hex(b""),
+            "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a"
+        )
+    }
+
+    #[test]
+    fn keyed_sha3_256_hex_ok() {
+        let hash = keyed_sha3_256_hex("key", b"data");
+        assert_eq!(hash, keyed_sha3_256_hex("key", b"data"));
+        assert_ne!(hash, keyed_sha3_256_hex("other key", b"data"));
+        assert_ne!(hash, sha3_256_hex(b"data"));
+    }
+
+    // ============== Key encryption ===============================
+    #[test]
+    fn key_encrypt_ok() {
+        let key = [7; KEY_LEN];
+        let encrypted = key_encrypt(&key, b"plaintext", b"aad");
+        assert_eq!(
+            key_try_decrypt(&key, &encrypted, b"aad").unwrap(),
+            b"plaintext".to_vec()
+        );
+    }
+
+    #[test]
+    fn key_encrypt_wrong_aad_failed() {
+        let key = [7; KEY_LEN];
+        let encrypted = key_encrypt(&key, b"plaintext", b"aad");
+        assert_eq!(key_try_decrypt(&key, &encrypted, b"other").is_err(), true);
+        assert_eq!(
+            key_try_decrypt(&[8; KEY_LEN], &encrypted, b"aad").is_err(),
+            true
+        );
+        assert_eq!(
+            key_try_decrypt(&key, &encrypted[..4], b"aad").is_err(),
+            true
+        );
+    }
+
+    #[test]
+    fn derive_key_from_passphrase_ok() {
+        let key = derive_key_from_passphrase("passphrase", b"salt", 10);
+        assert_eq!(key, derive_key_from_passphrase("passphrase", b"salt", 10));
+        assert_ne!(
+            key,
+            derive_key_from_passphrase("passphrase", b"other salt", 10)
+        );
+        assert_ne!(key, derive_key_from_passphrase("passphrase", b"salt", 11));
+    }
+
     // ============== IV generation ===============================
     #[test]
     fn generate_initialization_vector_ok() {
         assert_eq!(generate_initialization_vector().len(), IV_LEN)
     }
+
+    // ============== Encryption ===============================
+    #[test]
+    fn json_encrypted_len_ok() {
+        for length in 0..48 {
+            let plaintext = "a".repeat(length);
+            assert_eq!(json_encrypt(plaintext).len(), json_encrypted_len(length))
+        }
+    }
+
+    // ============== Decryption ===============================
+    #[test]
+    fn json_try_decrypt_ok() {
+        let ciphertext = json_encrypt("test".to_string());
+        assert_eq!(json_try_decrypt(&ciphertext), Ok("test".to_string()))
+    }
+    #[test]
+    fn json_try_decrypt_tampered_failed() {
+        let ciphertext = json_encrypt("test".to_string());
+        // Changing the derivation value changes the key
+        let tampered = format!("-{}", &ciphertext[1..]);
+        assert_eq!(json_try_decrypt(&tampered).is_err(), true)
+    }
+    #[test]
+    fn json_try_decrypt_malformed_failed() {
+        assert_eq!(json_try_decrypt("").is_err(), true)
+    }
+    #[test]
+    fn file_try_decrypt_ok() {
+        let dv = generate_derivation_value();
+        let iv = base64::encode(generate_initialization_vector());
+        let ciphertext = file_encrypt("test".to_string(), dv.clone(), iv.clone());
+        assert_eq!(
+            file_try_decrypt(&ciphertext, &dv, &iv),
+            Ok("test".to_string())
+        )
+    }
+    #[test]
+    fn file_try_decrypt_wrong_key_failed() {
+        let iv = base64::encode(generate_initialization_vector());
+        let ciphertext = file_encrypt("test".to_string(), generate_derivation_value(), iv.clone());
+        assert_eq!(
+            file_try_decrypt(&ciphertext, &generate_derivation_value(), &iv).is_err(),
+            true
+        )
+    }
+
+    // ============== Compression ===============================
+    #[test]
+    fn json_compressed_ok() {
+        set_compression_threshold(64);
+        let plaintext = "{\"setting\": \"value\"}".repeat(100);
+        let ciphertext = json_encrypt(plaintext.clone());
+        assert_eq!(
+            ciphertext.contains(&format!("${}", COMPRESSED_PREFIX)),
+            true
+        );
+        assert_eq!(ciphertext.len() < plaintext.len(), true);
+        assert_eq!(json_try_decrypt(&ciphertext), Ok(plaintext))
+    }
+    #[test]
+    fn json_compression_flag_removed_failed() {
+        set_compression_threshold(64);
+        let ciphertext = json_encrypt("a".repeat(1000));
+        // The flag is authenticated, a ciphertext without it fails
+        let tampered = ciphertext.replace(&format!("${}", COMPRESSED_PREFIX), "$");
+        assert_eq!(json_try_decrypt(&tampered).is_err(), true)
+    }
+    #[test]
+    fn file_compressed_ok() {
+        set_compression_threshold(64);
+        let dv = generate_derivation_value();
+        let iv = base64::encode(generate_initialization_vector());
+        let plaintext = "a".repeat(1000);
+        let ciphertext = file_encrypt(plaintext.clone(), dv.clone(), iv.clone());
+        assert_eq!(ciphertext.starts_with(COMPRESSED_PREFIX), true);
+        assert_eq!(file_try_decrypt(&ciphertext, &dv, &iv), Ok(plaintext))
+    }
 }
@@ -6,22 +6,20 @@
 
 // Rust Standard Library
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
 use std::io::prelude::*;
+use std::path::Path;
 
 // kvs modules
-use crate::log::{log, LOG_STDERR};
+use crate::log_error;
 
 // Get directory of executable
 pub fn get_exec_dir() -> String {
     let mut dir = match env::current_exe() {
         Ok(dir) => dir,
         Err(_e) => {
-            log(
-                "Could not access executable directory.".to_string(),
-                LOG_STDERR,
-            );
+            log_error!("Could not access executable directory.");
             std::process::exit(0x0001);
         }
     };
@@ -34,7 +32,7 @@ pub fn delete_file(path: String) -> Result<(), io::Error> {
     match std::fs::remove_file(path.clone()) {
         Ok(_o) => Ok(()),
         Err(e) => {
-            log(format!("Failed deleting file at: {}", path), LOG_STDERR);
+            log_error!("Failed deleting file at: {}", path);
             Err(e)
         }
     }
@@ -45,7 +43,7 @@ pub fn read_file_to_vec(path: String) -> Result<Vec<u8>, io::Error> {
     let content = match std::fs::read(path) {
         Ok(c) => c,
         Err(e) => {
-            log(format!("Failed reading file: {}", e), LOG_STDERR);
+            log_error!("Failed reading file: {}", e);
             return Err(e);
         }
     };
@@ -57,43 +55,139 @@ pub fn read_file_to_string(path: String) -> Result<String, io::Error> {
     let content = match std::fs::read_to_string(path) {
         Ok(c) => c,
         Err(e) => {
-            log(format!("Failed reading file: {}", e), LOG_STDERR);
+            log_error!("Failed reading file: {}", e);
             return Err(e);
         }
     };
     Ok(content)
 }
 
-// Generic write string to file
-pub fn write_string_to_file(path: String, data: String) {
-    let string_buffer = data.as_bytes();
-    let mut pos = 0;
-    let mut file_buffer = match File::create(path) {
-        Ok(o) => o,
-        Err(e) => return log(format!("Failed creating file: {}", e), LOG_STDERR),
-    };
-    while pos < data.len() {
-        let written_bytes = match file_buffer.write(&string_buffer[pos..]) {
-            Ok(o) => o,
-            Err(e) => return log(format!("Could not write to file: {}", e), LOG_STDERR),
-        };
-        pos += written_bytes;
+// Generic write string to file.
+// The write is atomic, after a crash the file contains either the previous or the new content:
+// the data is written to "<path>.tmp" and synced, renamed over the file and the directory is synced.
+pub fn write_string_to_file(path: String, data: String) -> Result<(), io::Error> {
+    let temp_path = format!("{}.tmp", path);
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data.as_bytes())
+                .and_then(|()| file.sync_all())
+        })
+        .and_then(|()| fs::rename(&temp_path, &path))
+        .and_then(|()| sync_directory(parent_directory(&path)));
+    if let Err(e) = result {
+        log_error!("Failed writing file at {}: {}", path, e);
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// Atomically write string to file and keep the replaced content as "<path>.prev",
+// e.g. to recover a store file that was damaged after it was written
+pub fn write_string_to_file_keep_previous(path: String, data: String) -> Result<(), io::Error> {
+    if Path::new(&path).exists() {
+        let previous_path = previous_generation_path(&path);
+        let _ = fs::remove_file(&previous_path);
+        // The previous generation shares the content of the file until it is replaced
+        if let Err(e) = fs::hard_link(&path, &previous_path).or_else(|_e| {
+            fs::copy(&path, &previous_path).and_then(|_size| sync_file(previous_path.clone()))
+        }) {
+            log_error!("Failed keeping previous generation of {}: {}", path, e);
+            return Err(e);
+        }
+    }
+    write_string_to_file(path, data)
+}
+
+// Path of the previous generation of a file written by write_string_to_file_keep_previous
+pub fn previous_generation_path(path: &str) -> String {
+    format!("{}.prev", path)
+}
+
+// Directory containing a file, to sync a rename in it
+fn parent_directory(path: &str) -> String {
+    match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().to_string(),
+        _ => ".".to_string(),
+    }
+}
+
+// Flush the content of a file to the storage device
+pub fn sync_file(path: String) -> Result<(), io::Error> {
+    match File::open(path.clone()).and_then(|file| file.sync_all()) {
+        Ok(_o) => Ok(()),
+        Err(e) => {
+            log_error!("Failed syncing file at: {}", path);
+            Err(e)
+        }
     }
 }
 
+// Flush the entries of a directory to the storage device, e.g. after a rename.
+// Directories can not be opened as files on Windows, NTFS persists renames in its journal.
+#[cfg(unix)]
+pub fn sync_directory(path: String) -> Result<(), io::Error> {
+    sync_file(path)
+}
+
+#[cfg(not(unix))]
+pub fn sync_directory(_path: String) -> Result<(), io::Error> {
+    Ok(())
+}
+
 // JSON Backend specific read file
 pub fn read_persistent_store_file_to_string(path: String) -> Result<String, io::Error> {
     let content = match read_file_to_string(format!("{}/store.json", path)) {
         Ok(c) => c,
         Err(e) => {
-            log(format!("Failed reading JSON file: {}", e), LOG_STDERR);
+            log_error!("Failed reading JSON file: {}", e);
             return Err(e);
         }
     };
     Ok(content)
 }
 
-// JSON Backend specific write file
-pub fn write_persistent_store_file_from_string(path: String, data: String) {
-    write_string_to_file(format!("{}/store.json", path), data);
+// JSON Backend specific write file, the previous generation is kept
+pub fn write_persistent_store_file_from_string(
+    path: String,
+    data: String,
+) -> Result<(), io::Error> {
+    write_string_to_file_keep_previous(format!("{}/store.json", path), data)
+}
+
+// To run these tests use: `cargo test filesystem_wrapper`
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("kvs_filesystem_wrapper_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().to_string()
+    }
+
+    // ============== Atomic writes ===============================
+    #[test]
+    fn write_string_to_file_ok() {
+        let path = format!("{}/file", test_dir("write"));
+        write_string_to_file(path.clone(), "first".to_string()).unwrap();
+        write_string_to_file(path.clone(), "second".to_string()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(Path::new(&format!("{}.tmp", path)).exists(), false)
+    }
+    #[test]
+    fn write_string_to_file_keep_previous_ok() {
+        let path = format!("{}/file", test_dir("keep_previous"));
+        write_string_to_file_keep_previous(path.clone(), "first".to_string()).unwrap();
+        assert_eq!(Path::new(&previous_generation_path(&path)).exists(), false);
+        write_string_to_file_keep_previous(path.clone(), "second".to_string()).unwrap();
+        write_string_to_file_keep_previous(path.clone(), "third".to_string()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third");
+        assert_eq!(
+            fs::read_to_string(previous_generation_path(&path)).unwrap(),
+            "second"
+        )
+    }
 }
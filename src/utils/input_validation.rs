@@ -8,20 +8,25 @@
 use regex::Regex;
 
 // kvs modules
-use crate::log::{log, LOG_STDERR};
+use crate::log_warn;
 
 // Constants
 const KEY_LEN_MIN: usize = 1;
-const KEY_LEN_MAX: usize = 32;
-const VALUE_LEN_MIN: usize = 1;
-const VALUE_LEN_MAX: usize = 1024;
+pub const KEY_LEN_MAX: usize = 32;
+pub const VALUE_LEN_MIN: usize = 1;
+pub const VALUE_LEN_MAX: usize = 1024;
 
 pub fn validate_key(input: String) -> bool {
+    validate_key_with_limit(input, KEY_LEN_MAX)
+}
+
+// Validate a key against a configured maximum length instead of the default
+pub fn validate_key_with_limit(input: String, max_length: usize) -> bool {
     lazy_static! {
         static ref RE_KEY: Regex = Regex::new(r"^\w*$").unwrap();
     }
     // Check length
-    if input.len() < KEY_LEN_MIN || input.len() > KEY_LEN_MAX {
+    if input.len() < KEY_LEN_MIN || input.len() > max_length {
         return false;
     }
     // Check regex
@@ -62,7 +67,7 @@ pub fn validate_ipv4(input: String) -> bool {
     }
     // Shortes "0.0.0.0" = 7, longest "255.255.255.255" = 15
     if input.len() < 7 || input.len() > 15 {
-        log("IP length invalid".to_string(), LOG_STDERR);
+        log_warn!("IP length invalid");
         return false;
     }
     //Check regex
@@ -88,7 +93,7 @@ pub fn validate_port(input: String) -> bool {
     }
     // Shortes "0" = 1, longest "65534" =
     if input.is_empty() || input.len() > 5 {
-        log("Port length invalid".to_string(), LOG_STDERR);
+        log_warn!("Port length invalid");
         return false;
     }
     //Check regex
@@ -102,6 +107,15 @@ pub fn validate_port(input: String) -> bool {
     }
 }
 
+pub fn validate_unsigned_integer(input: String) -> bool {
+    // Check for digits
+    lazy_static! {
+        static ref RE_NUMBER: Regex = Regex::new(r"^(\d+)$").unwrap();
+    }
+    //Check regex and range of u64
+    RE_NUMBER.is_match(&input) && input.parse::<u64>().is_ok()
+}
+
 // To run these tests use: `cargo test input_validation`
 // Run the following to see println!()
 // cargo test input_validation_random_invalid -- --nocapture
@@ -140,6 +154,15 @@ mod tests {
         )
     }
     #[test]
+    fn input_validation_key_with_limit_ok() {
+        assert_eq!(validate_key_with_limit("a".repeat(64), 64), true)
+    }
+    #[test]
+    fn input_validation_key_with_limit_failed() {
+        assert_eq!(validate_key_with_limit("a".repeat(65), 64), false);
+        assert_eq!(validate_key_with_limit("test$key".to_string(), 64), false)
+    }
+    #[test]
     fn input_validation_key_random_string_repeated() {
         const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
         let mut rng = rand::thread_rng();
@@ -310,4 +333,28 @@ mod tests {
     fn input_validation_port_65535() {
         assert_eq!(validate_port("65535".to_string()), false)
     }
+    // ============== Unsigned Integer Validation ===============================
+    #[test]
+    fn input_validation_unsigned_integer_0() {
+        assert_eq!(validate_unsigned_integer("0".to_string()), true)
+    }
+    #[test]
+    fn input_validation_unsigned_integer_1000() {
+        assert_eq!(validate_unsigned_integer("1000".to_string()), true)
+    }
+    #[test]
+    fn input_validation_unsigned_integer_empty() {
+        assert_eq!(validate_unsigned_integer("".to_string()), false)
+    }
+    #[test]
+    fn input_validation_unsigned_integer_negative_1() {
+        assert_eq!(validate_unsigned_integer("-1".to_string()), false)
+    }
+    #[test]
+    fn input_validation_unsigned_integer_overflow() {
+        assert_eq!(
+            validate_unsigned_integer("18446744073709551616".to_string()),
+            false
+        )
+    }
 }
@@ -5,16 +5,437 @@
 */
 
 // Rust Standard Library
-use std::sync::atomic::AtomicBool;
+use std::cmp::Reverse;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
 use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicU8};
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 // Constants
 pub const LOG_STDOUT: u8 = 0;
 pub const LOG_STDERR: u8 = 1;
 
+// Levels of log events, a filter level includes all lower levels
+pub const LEVEL_OFF: u8 = 0;
+pub const LEVEL_ERROR: u8 = 1;
+pub const LEVEL_WARN: u8 = 2;
+pub const LEVEL_INFO: u8 = 3;
+pub const LEVEL_DEBUG: u8 = 4;
+pub const LEVEL_TRACE: u8 = 5;
+pub const LEVEL_NAMES: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+// Formats of log events
+pub const FORMAT_TEXT: u8 = 0;
+pub const FORMAT_JSON: u8 = 1;
+pub const FORMAT_NAMES: [&str; 2] = ["text", "json"];
+
+// Outputs of log events
+pub const OUTPUT_STDERR: u8 = 0;
+pub const OUTPUT_FILE: u8 = 1;
+pub const OUTPUT_SYSLOG: u8 = 2;
+pub const OUTPUT_JOURNALD: u8 = 3;
+pub const OUTPUT_NAMES: [&str; 4] = ["stderr", "file", "syslog", "journald"];
+
+// Sockets of the local syslog daemon and journald
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+// Syslog facility "daemon"
+const SYSLOG_FACILITY: u8 = 3;
+// Syslog severity per level
+const SYSLOG_SEVERITY: [u8; 6] = [7, 3, 4, 6, 7, 7];
+// Value of redacted fields
+const REDACTED: &str = "<redacted>";
+
 pub static LOG_SILENT: AtomicBool = AtomicBool::new(false);
+static LOG_FORMAT: AtomicU8 = AtomicU8::new(FORMAT_TEXT);
+static REDACT_KEYS: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref FILTER: RwLock<Filter> = RwLock::new(Filter::default());
+    static ref SINK: Mutex<Sink> = Mutex::new(Sink::Stderr);
+}
+
+// Level per module, parsed from directives like "info,kvsd::grpc=debug"
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    level: u8,
+    // Module path prefixes and their level, the longest matching prefix applies
+    modules: Vec<(String, u8)>,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            level: LEVEL_INFO,
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(directives: &str) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+        for directive in directives.split(',').map(str::trim) {
+            if directive.is_empty() {
+                continue;
+            }
+            match directive.find('=') {
+                Some(position) => {
+                    let module = directive[..position].trim();
+                    if module.is_empty() {
+                        return Err(format!("Log filter \"{}\" misses a module.", directive));
+                    }
+                    let level = parse_level(directive[position + 1..].trim())?;
+                    filter.modules.push((module.to_string(), level));
+                }
+                None => filter.level = parse_level(directive)?,
+            }
+        }
+        // Longest prefixes first
+        filter.modules.sort_by_key(|module| Reverse(module.0.len()));
+        Ok(filter)
+    }
+
+    // Level enabled for a module path like "kvsd::grpc"
+    pub fn level(&self, module: &str) -> u8 {
+        for (prefix, level) in self.modules.iter() {
+            if module == prefix
+                || (module.starts_with(prefix.as_str()) && module[prefix.len()..].starts_with("::"))
+            {
+                return *level;
+            }
+        }
+        self.level
+    }
+}
+
+fn parse_level(name: &str) -> Result<u8, String> {
+    match LEVEL_NAMES.iter().position(|level| *level == name) {
+        Some(level) => Ok(level as u8),
+        None => Err(format!(
+            "Log level \"{}\" invalid, possible values: {}.",
+            name,
+            LEVEL_NAMES.join(", ")
+        )),
+    }
+}
+
+// Settings of the logger
+pub struct LogSettings {
+    pub filter: Filter,
+    pub format: u8,
+    pub output: u8,
+    // Log file, required for OUTPUT_FILE
+    pub file: String,
+    // Size in bytes after which the log file is rotated
+    pub file_max_size: u64,
+    // Rotated log files kept besides the current one
+    pub file_max_files: u32,
+    // Replace the values of fields named "key"
+    pub redact_keys: bool,
+}
+
+// Destination of log events
+enum Sink {
+    Stderr,
+    File(RotatingFile),
+    #[cfg(unix)]
+    Syslog(UnixDatagram),
+    #[cfg(unix)]
+    Journald(UnixDatagram),
+}
 
-/// Log a message to a defined destination
+// Log file which is rotated to file.1, file.2, ... when exceeding its maximum size
+struct RotatingFile {
+    path: String,
+    max_size: u64,
+    max_files: u32,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: String, max_size: u64, max_files: u32) -> Result<RotatingFile, String> {
+        let file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("Could not open log file \"{}\": {}", path, e)),
+        };
+        let size = match file.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(_e) => 0,
+        };
+        Ok(RotatingFile {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = format!("{}.{}", self.path, index);
+                if fs::metadata(&from).is_ok() {
+                    fs::rename(&from, format!("{}.{}", self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+// Configure the logger, events logged before are written to stderr as text
+pub fn init(settings: LogSettings) -> Result<(), String> {
+    let sink = match settings.output {
+        OUTPUT_STDERR => Sink::Stderr,
+        OUTPUT_FILE => Sink::File(RotatingFile::open(
+            settings.file,
+            settings.file_max_size,
+            settings.file_max_files,
+        )?),
+        #[cfg(unix)]
+        OUTPUT_SYSLOG => Sink::Syslog(connect_datagram(SYSLOG_SOCKET)?),
+        #[cfg(unix)]
+        OUTPUT_JOURNALD => Sink::Journald(connect_datagram(JOURNALD_SOCKET)?),
+        _ => return Err("Log output not supported on this platform.".to_string()),
+    };
+    *SINK.lock().unwrap() = sink;
+    LOG_FORMAT.store(settings.format, Ordering::Relaxed);
+    set_filter(settings.filter, settings.redact_keys);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn connect_datagram(path: &str) -> Result<UnixDatagram, String> {
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(e) => return Err(format!("Could not create log socket: {}", e)),
+    };
+    match socket.connect(path) {
+        Ok(()) => Ok(socket),
+        Err(e) => Err(format!(
+            "Could not connect to log socket \"{}\": {}",
+            path, e
+        )),
+    }
+}
+
+// Change the level filter and the redaction at runtime
+pub fn set_filter(filter: Filter, redact_keys: bool) {
+    *FILTER.write().unwrap() = filter;
+    REDACT_KEYS.store(redact_keys, Ordering::Relaxed);
+}
+
+// Whether events of a level are logged for a module, used by the log macros
+pub fn enabled(level: u8, module: &str) -> bool {
+    !LOG_SILENT.load(Ordering::Relaxed) && level <= FILTER.read().unwrap().level(module)
+}
+
+// Write an event with structured fields, used by the log macros
+pub fn log_event(level: u8, module: &str, message: String, fields: &[(&str, String)]) {
+    let redact_keys = REDACT_KEYS.load(Ordering::Relaxed);
+    let fields: Vec<(&str, &str)> = fields
+        .iter()
+        .map(|(name, value)| match redact_keys && *name == "key" {
+            true => (*name, REDACTED),
+            false => (*name, value.as_str()),
+        })
+        .collect();
+    let format = LOG_FORMAT.load(Ordering::Relaxed);
+    let mut sink = SINK.lock().unwrap();
+    let result = match &mut *sink {
+        Sink::Stderr => {
+            eprintln!("{}", format_event(format, level, module, &message, &fields));
+            Ok(())
+        }
+        Sink::File(file) => file.write(&format!(
+            "{}\n",
+            format_event(format, level, module, &message, &fields)
+        )),
+        #[cfg(unix)]
+        Sink::Syslog(socket) => {
+            // The syslog daemon adds the timestamp
+            let event = match format {
+                FORMAT_JSON => format_json(level, module, &message, &fields),
+                _ => format_text(module, &message, &fields),
+            };
+            let datagram = format!(
+                "<{}>kvsd[{}]: {}",
+                SYSLOG_FACILITY * 8 + SYSLOG_SEVERITY[level as usize],
+                std::process::id(),
+                event
+            );
+            socket.send(datagram.as_bytes()).map(|_| ())
+        }
+        #[cfg(unix)]
+        Sink::Journald(socket) => socket
+            .send(&journald_datagram(level, module, &message, &fields))
+            .map(|_| ()),
+    };
+    if let Err(e) = result {
+        eprintln!(
+            "Could not write log event: {}\n{}",
+            e,
+            format_event(format, level, module, &message, &fields)
+        );
+    }
+}
+
+fn format_event(
+    format: u8,
+    level: u8,
+    module: &str,
+    message: &str,
+    fields: &[(&str, &str)],
+) -> String {
+    match format {
+        FORMAT_JSON => format_json(level, module, message, fields),
+        _ => format!(
+            "{} {:5} {}",
+            timestamp(),
+            LEVEL_NAMES[level as usize].to_uppercase(),
+            format_text(module, message, fields)
+        ),
+    }
+}
+
+// "module: message field=value"
+fn format_text(module: &str, message: &str, fields: &[(&str, &str)]) -> String {
+    let mut line = format!("{}: {}", module, message);
+    for (name, value) in fields.iter() {
+        // Values with spaces, quotes or "=" are quoted and escaped
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+            line.push_str(&format!(" {}={:?}", name, value));
+        } else {
+            line.push_str(&format!(" {}={}", name, value));
+        }
+    }
+    line
+}
+
+// Single line JSON object, fields follow the fixed members in their given order
+fn format_json(level: u8, module: &str, message: &str, fields: &[(&str, &str)]) -> String {
+    let mut line = format!(
+        "{{\"timestamp\":\"{}\",\"level\":\"{}\",\"module\":{},\"message\":{}",
+        timestamp(),
+        LEVEL_NAMES[level as usize],
+        serde_json::to_string(module).unwrap(),
+        serde_json::to_string(message).unwrap()
+    );
+    for (name, value) in fields.iter() {
+        line.push_str(&format!(
+            ",{}:{}",
+            serde_json::to_string(name).unwrap(),
+            serde_json::to_string(value).unwrap()
+        ));
+    }
+    line.push('}');
+    line
+}
+
+// Datagram of the journald native protocol, fields are upper case
+#[cfg(unix)]
+fn journald_datagram(level: u8, module: &str, message: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    let priority = SYSLOG_SEVERITY[level as usize].to_string();
+    let mut append = |name: &str, value: &str| {
+        if value.contains('\n') {
+            // Values containing newlines are length prefixed
+            datagram.extend_from_slice(name.as_bytes());
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            datagram.extend_from_slice(value.as_bytes());
+            datagram.push(b'\n');
+        } else {
+            datagram.extend_from_slice(format!("{}={}\n", name, value).as_bytes());
+        }
+    };
+    append("MESSAGE", message);
+    append("PRIORITY", &priority);
+    append("SYSLOG_IDENTIFIER", "kvsd");
+    append("CODE_MODULE", module);
+    for (name, value) in fields.iter() {
+        append(&journald_field_name(name), value);
+    }
+    datagram
+}
+
+// Journald field names only allow upper case letters, digits and "_"
+#[cfg(unix)]
+fn journald_field_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+// Log an event with a level, capturing the module path. Structured fields follow the
+// message after ";", e.g. log_info!("Storing key."; key = kv.key)
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:expr),+ $(; $($name:ident = $value:expr),+)?) => {
+        if $crate::log::enabled($level, module_path!()) {
+            $crate::log::log_event(
+                $level,
+                module_path!(),
+                format!($($arg),+),
+                &[$($((stringify!($name), $value.to_string())),+)?],
+            );
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::LEVEL_ERROR, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::LEVEL_WARN, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::LEVEL_INFO, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::LEVEL_DEBUG, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)+) => { $crate::log_at!($crate::log::LEVEL_TRACE, $($arg)+) };
+}
+
+/// Log a plain message to a defined destination, used for the output of kvsc
 ///
 /// # Arguments
 ///
@@ -35,3 +456,126 @@ pub fn log(message: String, destination: u8) {
 pub fn set_log_silent(value: bool) {
     LOG_SILENT.store(value, Ordering::Relaxed);
 }
+
+// Current UTC time in RFC 3339 format with milliseconds, e.g. 2020-09-13T12:26:40.123Z
+pub fn timestamp() -> String {
+    let millis = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis() as u64,
+        Err(_e) => 0,
+    };
+    format_timestamp(millis)
+}
+
+// Format milliseconds since UNIX epoch in RFC 3339 format
+pub fn format_timestamp(millis: u64) -> String {
+    let seconds = millis / 1000;
+    let days = (seconds / 86400) as i64;
+    let seconds_of_day = seconds % 86400;
+    // Convert days since epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        millis % 1000
+    )
+}
+
+// To run these tests use: `cargo test log`
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn format_timestamp_epoch_ok() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00.000Z")
+    }
+    #[test]
+    fn format_timestamp_ok() {
+        assert_eq!(
+            format_timestamp(1_600_000_000_123),
+            "2020-09-13T12:26:40.123Z"
+        )
+    }
+    #[test]
+    fn format_timestamp_leap_day_ok() {
+        assert_eq!(
+            format_timestamp(1_582_934_400_000),
+            "2020-02-29T00:00:00.000Z"
+        )
+    }
+    #[test]
+    fn filter_parse_ok() {
+        let filter = Filter::parse("warn, kvsd=info,kvsd::grpc=trace").unwrap();
+        assert_eq!(filter.level("kvsd::grpc"), LEVEL_TRACE);
+        assert_eq!(filter.level("kvsd::grpc::kvs_api"), LEVEL_TRACE);
+        assert_eq!(filter.level("kvsd::grpcx"), LEVEL_INFO);
+        assert_eq!(filter.level("utils::filesystem_wrapper"), LEVEL_WARN);
+    }
+    #[test]
+    fn filter_parse_default_ok() {
+        assert_eq!(Filter::parse("").unwrap(), Filter::default());
+    }
+    #[test]
+    fn filter_parse_nok() {
+        assert_eq!(Filter::parse("verbose").is_err(), true);
+        assert_eq!(Filter::parse("kvsd=loud").is_err(), true);
+        assert_eq!(Filter::parse("=debug").is_err(), true);
+    }
+    #[test]
+    fn format_text_ok() {
+        assert_eq!(
+            format_text("kvsd::grpc", "Storing key.", &[("key", "a \"b\"")]),
+            "kvsd::grpc: Storing key. key=\"a \\\"b\\\"\""
+        )
+    }
+    #[test]
+    fn format_text_unquoted_ok() {
+        assert_eq!(
+            format_text(
+                "kvsd::stats",
+                "Stats.",
+                &[("get_requests", "3"), ("peer", "")]
+            ),
+            "kvsd::stats: Stats. get_requests=3 peer=\"\""
+        )
+    }
+    #[test]
+    fn format_json_ok() {
+        let line = format_json(LEVEL_WARN, "kvsd", "Line\nbreak", &[("key", "k")]);
+        let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(event["level"], "warn");
+        assert_eq!(event["module"], "kvsd");
+        assert_eq!(event["message"], "Line\nbreak");
+        assert_eq!(event["key"], "k");
+    }
+    #[cfg(unix)]
+    #[test]
+    fn journald_datagram_ok() {
+        let datagram = journald_datagram(LEVEL_ERROR, "kvsd", "a\nb", &[("peer-ip", "local")]);
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(
+            b"a\nb\nPRIORITY=3\nSYSLOG_IDENTIFIER=kvsd\nCODE_MODULE=kvsd\nPEER_IP=local\n",
+        );
+        assert_eq!(datagram, expected);
+    }
+}
@@ -0,0 +1,119 @@
+/*
+*  metrics utils module
+*  SPDX-License-Identifier: MIT
+*  Copyright (C) 2020 Benjamin Schilling
+*/
+
+// Rust Standard Library
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// Upper bounds in seconds of the latency histogram buckets
+pub const LATENCY_BUCKETS: [f64; 14] = [
+    0.000_01, 0.000_025, 0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025,
+    0.1, 0.5, 1.0,
+];
+
+// Latency histogram which can be updated concurrently
+pub struct Histogram {
+    // Observations per bucket, the last bucket counts observations above all bounds
+    buckets: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            buckets: (0..=LATENCY_BUCKETS.len())
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+
+    // Append the samples of the histogram in Prometheus text format,
+    // labels are given like "rpc=\"store\"" or empty
+    pub fn render(&self, name: &str, labels: &str, output: &mut String) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            cumulative += self.buckets[i].load(Ordering::Relaxed);
+            let _ = writeln!(
+                output,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bound, cumulative
+            );
+        }
+        cumulative += self.buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(
+            output,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, cumulative
+        );
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1_000_000_000.0;
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(output, "{}_sum{} {}", name, labels, sum);
+        let _ = writeln!(output, "{}_count{} {}", name, labels, cumulative);
+    }
+}
+
+// To run these tests use: `cargo test metrics`
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn histogram_observe_ok() {
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_micros(5));
+        histogram.observe(Duration::from_secs(2));
+        assert_eq!(histogram.count(), 2);
+    }
+
+    #[test]
+    fn histogram_render_ok() {
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_millis(2));
+        let mut output = String::new();
+        histogram.render("test_duration_seconds", "rpc=\"get\"", &mut output);
+        assert_eq!(
+            output.contains("test_duration_seconds_bucket{rpc=\"get\",le=\"0.001\"} 0\n"),
+            true
+        );
+        assert_eq!(
+            output.contains("test_duration_seconds_bucket{rpc=\"get\",le=\"0.0025\"} 1\n"),
+            true
+        );
+        assert_eq!(
+            output.contains("test_duration_seconds_count{rpc=\"get\"} 1\n"),
+            true
+        );
+    }
+}
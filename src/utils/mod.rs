@@ -7,7 +7,9 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod backup;
 pub mod crypto;
 pub mod filesystem_wrapper;
 pub mod input_validation;
 pub mod log;
+pub mod metrics;